@@ -0,0 +1,77 @@
+//! The time of day for the status bar.
+//!
+//! Working out the local time zone is left to `date`, which already knows how, so the time is
+//! only asked for again once the minute has changed.
+
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct Clock {
+    /// How `date` should format the time, like `%H:%M`.
+    format: String,
+    pub shown: bool,
+    text: String,
+    /// The minute since the epoch that `text` is for.
+    minute: Option<u64>,
+}
+
+impl Clock {
+    pub fn new(format: String, shown: bool) -> Self {
+        Self {
+            format,
+            shown,
+            text: String::new(),
+            minute: None,
+        }
+    }
+
+    /// Asks for the time again if the minute has changed since it was last asked for.
+    pub fn update(&mut self) {
+        let minute = since_epoch().as_secs() / MINUTE.as_secs();
+        if !self.shown || self.minute == Some(minute) {
+            return;
+        }
+
+        self.text = date(&self.format).unwrap_or_default();
+        self.minute = Some(minute);
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        (self.shown && !self.text.is_empty()).then_some(&self.text)
+    }
+
+    /// How long until the time shown will be out of date.
+    pub fn until_next_minute(&self) -> Option<Duration> {
+        let into_minute =
+            Duration::from_millis((since_epoch().as_millis() % MINUTE.as_millis()) as u64);
+        self.shown.then(|| MINUTE - into_minute)
+    }
+}
+
+fn date(format: &str) -> Option<String> {
+    let output = Command::new("date")
+        .arg(format!("+{format}"))
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(
+        String::from_utf8(output.stdout)
+            .ok()?
+            .trim_end()
+            .to_string(),
+    )
+}
+
+fn since_epoch() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
@@ -0,0 +1,189 @@
+use crate::keymap::{self, Action, Keymap};
+use crate::theme::Theme;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::{env, fs, io};
+
+/// Settings read from `~/.config/editor/config.toml`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// How many columns apart tab stops are.
+    pub tab_width: usize,
+    /// Whether to indent with spaces rather than tabs.
+    pub soft_tabs: bool,
+    /// Whether Left and Right move a tab stop at a time through indentation made of spaces.
+    pub soft_tab_movement: bool,
+    /// Whether new lines keep the indentation of the line before, and closing brackets typed
+    /// at the start of a line line up with what they close.
+    pub auto_indent: bool,
+    /// Whether to auto-indent, by file extension, for kinds of files that should be different,
+    /// like prose that shouldn't be.
+    pub auto_indent_by_extension: HashMap<String, bool>,
+    /// How many columns long lines can be before the rest of them is marked, if there's a limit.
+    pub line_length_limit: Option<usize>,
+    /// Line length limits by file extension, for kinds of files that should have a different one.
+    pub line_length_limit_by_extension: HashMap<String, usize>,
+    /// Words that are highlighted to draw attention to them, like TODO.
+    pub markers: Vec<String>,
+    /// Whether to highlight spaces and tabs at the ends of lines.
+    pub trailing_whitespace: bool,
+    /// Whether searching carries on from the other end of the file after the last match.
+    pub search_wraps: bool,
+    /// How many columns wide paragraphs are rewrapped to.
+    pub text_width: usize,
+    /// Whether to show line numbers beside the text.
+    pub line_numbers: bool,
+    /// Whether to show a scrollbar at the right edge of the text.
+    pub scrollbar: bool,
+    /// How many spaces go on either side of line numbers.
+    pub gutter_padding: usize,
+    /// What goes between the line numbers and the text, like `│`.
+    pub gutter_separator: Option<char>,
+    /// Whether the cursor can move past the ends of lines.
+    pub virtual_space: bool,
+    /// The name of the theme to use, either built in or from `themes`.
+    pub theme: String,
+    pub themes: HashMap<String, Theme>,
+    /// How to tell that a key did nothing, like moving left at the start of a line.
+    pub bell: Bell,
+    /// Whether to show the time in the status bar.
+    pub clock: bool,
+    /// How to show the time, in the format `date` takes.
+    pub clock_format: String,
+    /// How many lines each notch of the mouse wheel scrolls by.
+    pub scroll_lines: usize,
+    /// Whether scrolling quickly scrolls further with each notch.
+    pub scroll_acceleration: bool,
+    /// Whether reopening a file goes back to where the cursor was left in it.
+    pub restore_position: bool,
+    /// Whether closing the last file leaves an empty buffer open instead of quitting.
+    pub close_to_scratch: bool,
+    /// How to write comments, by file extension, for languages that aren't built in
+    /// or to write them differently.
+    pub comments: HashMap<String, editor::Comments>,
+    /// Commands to start language servers with, by file extension.
+    pub language_servers: HashMap<String, Vec<String>>,
+    /// Templates that a word expands into when Tab is pressed after it, by that word.
+    pub snippets: HashMap<String, String>,
+    /// Whether to underline misspelled words, in comments and strings for source files.
+    pub spell_check: bool,
+    /// A list of correctly spelled words, one per line.
+    pub dictionary: PathBuf,
+    /// Keys to bind actions to instead of their defaults, by the action's name as the command
+    /// palette shows it, like `quit = "Ctrl+X Ctrl+C"`.
+    pub keys: HashMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tab_width: 4,
+            soft_tabs: true,
+            soft_tab_movement: false,
+            auto_indent: true,
+            auto_indent_by_extension: HashMap::new(),
+            line_length_limit: None,
+            line_length_limit_by_extension: HashMap::new(),
+            markers: ["TODO", "FIXME", "XXX", "HACK"].map(String::from).to_vec(),
+            trailing_whitespace: true,
+            search_wraps: true,
+            text_width: 80,
+            line_numbers: false,
+            scrollbar: false,
+            gutter_padding: 1,
+            gutter_separator: None,
+            virtual_space: false,
+            theme: "dark".to_string(),
+            themes: HashMap::new(),
+            bell: Bell::Off,
+            clock: false,
+            clock_format: "%H:%M".to_string(),
+            scroll_lines: 3,
+            scroll_acceleration: false,
+            restore_position: true,
+            close_to_scratch: false,
+            comments: HashMap::new(),
+            language_servers: HashMap::new(),
+            snippets: HashMap::new(),
+            spell_check: false,
+            dictionary: PathBuf::from("/usr/share/dict/words"),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bell {
+    Off,
+    /// Flashing the status bar.
+    Visual,
+    /// The terminal's own bell, which might beep or flash depending on the terminal.
+    Audible,
+}
+
+impl Config {
+    pub fn theme(&self) -> Result<Theme, String> {
+        self.themes
+            .get(&self.theme)
+            .cloned()
+            .or_else(|| Theme::builtin(&self.theme))
+            .ok_or_else(|| format!("there's no theme called {}", self.theme))
+    }
+
+    pub fn keymap(&self) -> Result<Keymap, String> {
+        let mut keymap = Keymap::default();
+        for (name, keys) in &self.keys {
+            let action =
+                Action::named(name).ok_or_else(|| format!("there's no action called {name}"))?;
+            let keys = keymap::parse_keys(keys)
+                .ok_or_else(|| format!("couldn't read the keys for {name}: {keys}"))?;
+            keymap.bind(action, keys);
+        }
+        Ok(keymap)
+    }
+}
+
+/// Reads the config file, falling back to the defaults if there isn't one.
+pub fn load() -> Result<Config, String> {
+    let path = match path() {
+        Some(path) => path,
+        None => return Ok(Config::default()),
+    };
+
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(format!("couldn't read {}: {e}", path.display())),
+    };
+
+    toml::from_str(&text).map_err(|e| format!("couldn't load {}: {}", path.display(), e.message()))
+}
+
+fn path() -> Option<PathBuf> {
+    Some(dir()?.join("config.toml"))
+}
+
+/// Where the config file and anything else the user might want to edit by hand are kept.
+pub fn dir() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_dir.join("editor"))
+}
+
+/// Where what the editor remembers between sessions is kept, like the recently opened files.
+pub fn state_dir() -> Option<PathBuf> {
+    let state_dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("state"),
+    };
+
+    Some(state_dir.join("editor"))
+}
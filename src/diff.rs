@@ -0,0 +1,197 @@
+use std::ops::Range;
+
+/// Diffs bigger than this are reported as a single change,
+/// since finding the smallest one gets slow.
+const MAX_EDITS: isize = 1000;
+
+/// A run of lines that differ between the old and new versions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Finds what changed between `old` and `new` using Myers' algorithm.
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    // most edits touch a small part of the file, so skip the unchanged ends
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_changed = prefix..old.len() - suffix;
+    let new_changed = prefix..new.len() - suffix;
+
+    let ops = match shortest_edit(&old[old_changed.clone()], &new[new_changed.clone()]) {
+        Some(ops) => ops,
+        None => {
+            return vec![Hunk {
+                old: old_changed,
+                new: new_changed,
+            }]
+        }
+    };
+
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut i, mut j) = (prefix, prefix);
+
+    for op in ops {
+        if op != Op::Equal {
+            match hunks.last() {
+                Some(hunk) if hunk.old.end == i && hunk.new.end == j => {}
+                _ => hunks.push(Hunk {
+                    old: i..i,
+                    new: j..j,
+                }),
+            }
+        }
+
+        let hunk = hunks.last_mut();
+        match op {
+            Op::Equal => {
+                i += 1;
+                j += 1;
+            }
+            Op::Delete => {
+                hunk.unwrap().old.end += 1;
+                i += 1;
+            }
+            Op::Insert => {
+                hunk.unwrap().new.end += 1;
+                j += 1;
+            }
+        }
+    }
+
+    hunks
+}
+
+/// The differences between `old` and `new` in the unified format that `diff -u` uses, with
+/// `context` unchanged lines around each change. Changes that are close enough together to
+/// share their context are shown as one hunk.
+pub fn unified(old: &[&str], new: &[&str], context: usize) -> Vec<String> {
+    let hunks = diff(old, new);
+    let mut lines = Vec::new();
+    let mut idx = 0;
+
+    while idx < hunks.len() {
+        let mut end = idx + 1;
+        while end < hunks.len() && hunks[end].old.start - hunks[end - 1].old.end <= 2 * context {
+            end += 1;
+        }
+        let (first, last) = (&hunks[idx], &hunks[end - 1]);
+
+        // the lines around the changes are the same in both, so they line up
+        let before = first.old.start.min(context);
+        let after = (old.len() - last.old.end).min(context);
+        let (old_start, new_start) = (first.old.start - before, first.new.start - before);
+        let (old_end, new_end) = (last.old.end + after, last.new.end + after);
+        lines.push(format!(
+            "@@ -{},{} +{},{} @@",
+            old_start + 1,
+            old_end - old_start,
+            new_start + 1,
+            new_end - new_start
+        ));
+
+        let mut row = old_start;
+        for hunk in &hunks[idx..end] {
+            lines.extend(
+                old[row..hunk.old.start]
+                    .iter()
+                    .map(|line| format!(" {line}")),
+            );
+            lines.extend(old[hunk.old.clone()].iter().map(|line| format!("-{line}")));
+            lines.extend(new[hunk.new.clone()].iter().map(|line| format!("+{line}")));
+            row = hunk.old.end;
+        }
+        lines.extend(old[row..old_end].iter().map(|line| format!(" {line}")));
+
+        idx = end;
+    }
+
+    lines
+}
+
+fn shortest_edit<T: PartialEq>(old: &[T], new: &[T]) -> Option<Vec<Op>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    let index = |k: isize| (k + offset) as usize;
+
+    // the furthest x reached on each diagonal k = x - y
+    let mut v = vec![0; 2 * max as usize + 3];
+    // the relevant part of `v` before each round, for retracing the path afterwards
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max {
+        if d > MAX_EDITS {
+            return None;
+        }
+
+        trace.push(v[index(-d)..=index(d)].to_vec());
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || k != d && v[index(k - 1)] < v[index(k + 1)] {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            v[index(k)] = x;
+
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| v[(k + d) as usize];
+
+        if d == 0 {
+            ops.extend((0..x).map(|_| Op::Equal));
+            break;
+        }
+
+        let k = x - y;
+        let prev_k = if k == -d || k != d && get(k - 1) < get(k + 1) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            ops.push(Op::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        ops.push(if x == prev_x { Op::Insert } else { Op::Delete });
+
+        (x, y) = (prev_x, prev_y);
+    }
+
+    ops.reverse();
+    Some(ops)
+}
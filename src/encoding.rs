@@ -0,0 +1,160 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+
+/// The encodings that can be switched between when the detected one is wrong.
+pub const CHOICES: &[&Encoding] = &[UTF_8, WINDOWS_1252, UTF_16LE, UTF_16BE];
+
+/// How a file's text was stored, so it can be saved the same way.
+#[derive(Debug, Clone, Copy)]
+pub struct FileEncoding {
+    pub encoding: &'static Encoding,
+    /// Whether the file started with a byte order mark.
+    pub bom: bool,
+}
+
+impl Default for FileEncoding {
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            bom: false,
+        }
+    }
+}
+
+impl FileEncoding {
+    pub fn name(self) -> &'static str {
+        self.encoding.name()
+    }
+
+    pub fn is_utf16(self) -> bool {
+        is_utf16(self.encoding)
+    }
+
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+
+        // encoding_rs only decodes UTF-16, so we have to encode it ourselves
+        if self.is_utf16() {
+            let units = self
+                .bom
+                .then_some('\u{feff}')
+                .into_iter()
+                .chain(text.chars());
+            for unit in units.collect::<String>().encode_utf16() {
+                if self.encoding == UTF_16LE {
+                    bytes.extend(unit.to_le_bytes());
+                } else {
+                    bytes.extend(unit.to_be_bytes());
+                }
+            }
+            return Ok(bytes);
+        }
+
+        if self.bom && self.encoding == UTF_8 {
+            bytes.extend(b"\xef\xbb\xbf");
+        }
+
+        let (encoded, _, had_errors) = self.encoding.encode(text);
+        if had_errors {
+            return Err(format!(
+                "the buffer has characters that can't be saved as {}",
+                self.name()
+            ));
+        }
+        bytes.extend_from_slice(&encoded);
+
+        Ok(bytes)
+    }
+}
+
+pub fn is_utf16(encoding: &Encoding) -> bool {
+    encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+/// Guesses how `bytes` is encoded and decodes it.
+pub fn decode(bytes: &[u8]) -> (String, FileEncoding) {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return decode_as(bytes, encoding);
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(text) => (text.to_string(), FileEncoding::default()),
+        // most text that isn't UTF-8 was written on Windows
+        Err(_) => decode_as(bytes, WINDOWS_1252),
+    }
+}
+
+/// Decodes `bytes` as `encoding`, replacing anything invalid.
+pub fn decode_as(bytes: &[u8], encoding: &'static Encoding) -> (String, FileEncoding) {
+    let bom_len = match Encoding::for_bom(bytes) {
+        Some((bom, len)) if bom == encoding => len,
+        _ => 0,
+    };
+    let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+
+    (
+        text.into_owned(),
+        FileEncoding {
+            encoding,
+            bom: bom_len != 0,
+        },
+    )
+}
+
+/// Everything about how a file's text is stored.
+#[derive(Debug, Clone, Copy)]
+pub struct FileFormat {
+    pub encoding: FileEncoding,
+    pub line_ending: LineEnding,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            encoding: FileEncoding::default(),
+            line_ending: LineEnding::Lf,
+        }
+    }
+}
+
+/// Which line breaks a file uses. The buffer always uses LF,
+/// and they're converted back when saving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// Guesses which line breaks `text` uses and converts them to LF.
+    pub fn normalize(text: String) -> (String, Self) {
+        let crlf = text.matches("\r\n").count();
+        let lf = text.matches('\n').count() - crlf;
+
+        if crlf > lf {
+            (text.replace("\r\n", "\n"), Self::Crlf)
+        } else {
+            (text, Self::Lf)
+        }
+    }
+
+    pub fn apply(self, text: String) -> String {
+        match self {
+            Self::Lf => text,
+            Self::Crlf => text.replace('\n', "\r\n"),
+        }
+    }
+
+    pub fn toggle(self) -> Self {
+        match self {
+            Self::Lf => Self::Crlf,
+            Self::Crlf => Self::Lf,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::Crlf => "CRLF",
+        }
+    }
+}
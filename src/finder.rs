@@ -0,0 +1,47 @@
+//! Lists the files that the fuzzy finder picks from.
+
+use crate::git;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many files to list at most, so that starting in a huge directory doesn't hang.
+const MAX_FILES: usize = 50_000;
+
+/// The files under the current directory, leaving out whatever git ignores if it's a
+/// repository, and hidden files and directories otherwise.
+pub fn files() -> Vec<PathBuf> {
+    let mut files = git::unignored_files().unwrap_or_else(|| {
+        let mut files = Vec::new();
+        walk(Path::new("."), &mut files);
+        files
+    });
+
+    files.sort();
+    files.truncate(MAX_FILES);
+    files
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+    // unreadable directories are skipped rather than failing the whole listing
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        if files.len() >= MAX_FILES {
+            return;
+        }
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let path = entry.path();
+        let path = path.strip_prefix(".").unwrap_or(&path).to_path_buf();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => walk(&path, files),
+            Ok(file_type) if file_type.is_file() => files.push(path),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,35 @@
+/// Scores how well `pattern` matches `candidate` as a case-insensitive subsequence,
+/// or returns `None` if it doesn't match at all. Higher scores are better matches:
+/// characters matched in a row or at the start of a word count for more.
+pub fn score(pattern: &str, candidate: &str) -> Option<usize> {
+    let mut candidate = candidate.chars();
+    let mut score = 0;
+    let mut previous: Option<char> = None;
+    let mut previous_matched = false;
+
+    for p in pattern.chars() {
+        loop {
+            let c = candidate.next()?;
+            let matched = c.to_lowercase().eq(p.to_lowercase());
+
+            if matched {
+                score += 1;
+                if previous_matched {
+                    score += 2;
+                }
+                if previous.is_none_or(|previous| !previous.is_alphanumeric()) {
+                    score += 3;
+                }
+            }
+
+            previous = Some(c);
+            previous_matched = matched;
+
+            if matched {
+                break;
+            }
+        }
+    }
+
+    Some(score)
+}
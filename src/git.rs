@@ -0,0 +1,50 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The contents of `path` as of the last commit,
+/// or `None` if it isn't tracked in a git repository.
+pub fn head_contents(path: &Path) -> Option<String> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name()?.to_str()?;
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["show", &format!("HEAD:./{name}")])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    String::from_utf8(output.stdout).ok()
+}
+
+/// The files under the current directory that git doesn't ignore,
+/// or `None` if it isn't in a git repository.
+pub fn unignored_files() -> Option<Vec<PathBuf>> {
+    let output = Command::new("git")
+        .args(["ls-files", "--cached", "--others", "--exclude-standard"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let files = String::from_utf8(output.stdout).ok()?;
+    Some(
+        files
+            .lines()
+            .map(PathBuf::from)
+            // deleted files are still listed until the deletion is committed
+            .filter(|file| file.is_file())
+            .collect(),
+    )
+}
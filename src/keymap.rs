@@ -0,0 +1,807 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// A named editor command that can be bound to a key or run from the command palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Backspace,
+    Delete,
+    Enter,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Find,
+    FindBackward,
+    RepeatSearch,
+    FindCharForward,
+    FindCharBackward,
+    TillCharForward,
+    TillCharBackward,
+    NextBlankLine,
+    PreviousBlankLine,
+    NextSiblingLine,
+    PreviousSiblingLine,
+    SelectLeft,
+    SelectRight,
+    SelectUp,
+    SelectDown,
+    SelectHome,
+    SelectEnd,
+    SelectLines,
+    SelectBlock,
+    ExpandSelection,
+    ShrinkSelection,
+    SelectEnclosingNode,
+    SelectNextSibling,
+    SelectPreviousSibling,
+    SelectFunction,
+    AddCursorAtNextOccurrence,
+    AddCursorAbove,
+    AddCursorBelow,
+    Copy,
+    Cut,
+    Paste,
+    PasteReindented,
+    SelectAll,
+    DuplicateSelection,
+    SetMark,
+    CutToMark,
+    CopyToMark,
+    IndentToMark,
+    CommentToMark,
+    FilterToMark,
+    Indent,
+    Dedent,
+    ReverseLines,
+    UniqueLines,
+    TrimTrailingBlankLines,
+    ReflowParagraph,
+    Align,
+    FilterThroughShell,
+    ToggleComment,
+    Increment,
+    Decrement,
+    ToggleFold,
+    FoldAll,
+    UnfoldAll,
+    NextDiagnostic,
+    PreviousDiagnostic,
+    NextHunk,
+    PreviousHunk,
+    ScrollUp,
+    ScrollDown,
+    Undo,
+    Redo,
+    Earlier,
+    Later,
+    RepeatLastChange,
+    ToggleTrailingWhitespace,
+    ToggleRainbowBrackets,
+    ToggleSoftTabs,
+    ToggleAutoIndent,
+    ToggleVirtualSpace,
+    ToggleOverwrite,
+    ToggleLineNumbers,
+    ToggleScrollbar,
+    ChangeTabWidth,
+    ToggleReadOnly,
+    ToggleClock,
+    Split,
+    VerticalSplit,
+    FocusOtherPane,
+    CloseSplit,
+    Save,
+    ShowUnsavedChanges,
+    ReopenWithEncoding,
+    ChangeSaveEncoding,
+    ToggleLineEnding,
+    SetLanguage,
+    CommandPalette,
+    OpenRecent,
+    SaveSession,
+    RestoreSession,
+    Complete,
+    GoToDefinition,
+    Hover,
+    SpellingSuggestions,
+    AddToDictionary,
+    JumpBack,
+    JumpForward,
+    FindFile,
+    NewFile,
+    AlternateFile,
+    CloseFile,
+    BrowseDirectory,
+    ToggleMacroRecording,
+    ReplayMacro,
+    ReplayMacroOnLines,
+    Cancel,
+    Quit,
+}
+
+impl Action {
+    pub const ALL: &'static [Action] = &[
+        Action::Backspace,
+        Action::Delete,
+        Action::Enter,
+        Action::Left,
+        Action::Right,
+        Action::Up,
+        Action::Down,
+        Action::Home,
+        Action::End,
+        Action::Find,
+        Action::FindBackward,
+        Action::RepeatSearch,
+        Action::FindCharForward,
+        Action::FindCharBackward,
+        Action::TillCharForward,
+        Action::TillCharBackward,
+        Action::NextBlankLine,
+        Action::PreviousBlankLine,
+        Action::NextSiblingLine,
+        Action::PreviousSiblingLine,
+        Action::SelectLeft,
+        Action::SelectRight,
+        Action::SelectUp,
+        Action::SelectDown,
+        Action::SelectHome,
+        Action::SelectEnd,
+        Action::SelectLines,
+        Action::SelectBlock,
+        Action::ExpandSelection,
+        Action::ShrinkSelection,
+        Action::SelectEnclosingNode,
+        Action::SelectNextSibling,
+        Action::SelectPreviousSibling,
+        Action::SelectFunction,
+        Action::AddCursorAtNextOccurrence,
+        Action::AddCursorAbove,
+        Action::AddCursorBelow,
+        Action::Copy,
+        Action::Cut,
+        Action::Paste,
+        Action::PasteReindented,
+        Action::SelectAll,
+        Action::DuplicateSelection,
+        Action::SetMark,
+        Action::CutToMark,
+        Action::CopyToMark,
+        Action::IndentToMark,
+        Action::CommentToMark,
+        Action::FilterToMark,
+        Action::Indent,
+        Action::Dedent,
+        Action::ReverseLines,
+        Action::UniqueLines,
+        Action::TrimTrailingBlankLines,
+        Action::ReflowParagraph,
+        Action::Align,
+        Action::FilterThroughShell,
+        Action::ToggleComment,
+        Action::Increment,
+        Action::Decrement,
+        Action::ToggleFold,
+        Action::FoldAll,
+        Action::UnfoldAll,
+        Action::NextDiagnostic,
+        Action::PreviousDiagnostic,
+        Action::NextHunk,
+        Action::PreviousHunk,
+        Action::ScrollUp,
+        Action::ScrollDown,
+        Action::Undo,
+        Action::Redo,
+        Action::Earlier,
+        Action::Later,
+        Action::RepeatLastChange,
+        Action::ToggleTrailingWhitespace,
+        Action::ToggleRainbowBrackets,
+        Action::ToggleSoftTabs,
+        Action::ToggleAutoIndent,
+        Action::ToggleVirtualSpace,
+        Action::ToggleOverwrite,
+        Action::ToggleLineNumbers,
+        Action::ToggleScrollbar,
+        Action::ChangeTabWidth,
+        Action::ToggleReadOnly,
+        Action::ToggleClock,
+        Action::Split,
+        Action::VerticalSplit,
+        Action::FocusOtherPane,
+        Action::CloseSplit,
+        Action::Save,
+        Action::ShowUnsavedChanges,
+        Action::ReopenWithEncoding,
+        Action::ChangeSaveEncoding,
+        Action::ToggleLineEnding,
+        Action::SetLanguage,
+        Action::CommandPalette,
+        Action::OpenRecent,
+        Action::SaveSession,
+        Action::RestoreSession,
+        Action::Complete,
+        Action::GoToDefinition,
+        Action::Hover,
+        Action::SpellingSuggestions,
+        Action::AddToDictionary,
+        Action::JumpBack,
+        Action::JumpForward,
+        Action::FindFile,
+        Action::NewFile,
+        Action::AlternateFile,
+        Action::CloseFile,
+        Action::BrowseDirectory,
+        Action::ToggleMacroRecording,
+        Action::ReplayMacro,
+        Action::ReplayMacroOnLines,
+        Action::Cancel,
+        Action::Quit,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Backspace => "delete backward",
+            Action::Delete => "delete forward",
+            Action::Enter => "insert newline",
+            Action::Left => "move left",
+            Action::Right => "move right",
+            Action::Up => "move up",
+            Action::Down => "move down",
+            Action::Home => "move to start of line",
+            Action::End => "move to end of line",
+            Action::Find => "find",
+            Action::FindBackward => "find backward",
+            Action::RepeatSearch => "repeat last search",
+            Action::FindCharForward => "jump to next character on the line",
+            Action::FindCharBackward => "jump to previous character on the line",
+            Action::TillCharForward => "jump to before next character on the line",
+            Action::TillCharBackward => "jump to after previous character on the line",
+            Action::NextBlankLine => "move to next blank line",
+            Action::PreviousBlankLine => "move to previous blank line",
+            Action::NextSiblingLine => "move to next line indented the same",
+            Action::PreviousSiblingLine => "move to previous line indented the same",
+            Action::SelectLeft => "select left",
+            Action::SelectRight => "select right",
+            Action::SelectUp => "select up",
+            Action::SelectDown => "select down",
+            Action::SelectHome => "select to start of line",
+            Action::SelectEnd => "select to end of line",
+            Action::SelectLines => "select lines",
+            Action::SelectBlock => "select block",
+            Action::ExpandSelection => "expand selection",
+            Action::ShrinkSelection => "shrink selection",
+            Action::SelectEnclosingNode => "select enclosing syntax node",
+            Action::SelectNextSibling => "select next syntax node",
+            Action::SelectPreviousSibling => "select previous syntax node",
+            Action::SelectFunction => "select current function",
+            Action::AddCursorAtNextOccurrence => "add cursor at next occurrence",
+            Action::AddCursorAbove => "add cursor above",
+            Action::AddCursorBelow => "add cursor below",
+            Action::Copy => "copy",
+            Action::Cut => "cut",
+            Action::Paste => "paste",
+            Action::PasteReindented => "paste, reindented to fit",
+            Action::SelectAll => "select all",
+            Action::DuplicateSelection => "duplicate selection",
+            Action::SetMark => "set mark",
+            Action::CutToMark => "cut from mark to cursor",
+            Action::CopyToMark => "copy from mark to cursor",
+            Action::IndentToMark => "indent from mark to cursor",
+            Action::CommentToMark => "comment or uncomment from mark to cursor",
+            Action::FilterToMark => "filter from mark to cursor through a shell command",
+            Action::Indent => "indent",
+            Action::Dedent => "dedent",
+            Action::ReverseLines => "reverse lines",
+            Action::UniqueLines => "remove duplicate lines",
+            Action::TrimTrailingBlankLines => "remove blank lines at the end",
+            Action::ReflowParagraph => "reflow paragraph",
+            Action::Align => "align lines on a character",
+            Action::FilterThroughShell => "filter selection through a shell command",
+            Action::ToggleComment => "comment or uncomment",
+            Action::Increment => "increment number",
+            Action::Decrement => "decrement number",
+            Action::ToggleFold => "fold/unfold",
+            Action::FoldAll => "fold all",
+            Action::UnfoldAll => "unfold all",
+            Action::NextDiagnostic => "go to next diagnostic",
+            Action::PreviousDiagnostic => "go to previous diagnostic",
+            Action::NextHunk => "go to next change",
+            Action::PreviousHunk => "go to previous change",
+            Action::ScrollUp => "scroll up",
+            Action::ScrollDown => "scroll down",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::Earlier => "undo to the previous change in time",
+            Action::Later => "redo to the next change in time",
+            Action::RepeatLastChange => "repeat last change",
+            Action::ToggleTrailingWhitespace => "toggle trailing whitespace highlighting",
+            Action::ToggleRainbowBrackets => "toggle rainbow brackets",
+            Action::ToggleSoftTabs => "toggle indenting with spaces",
+            Action::ToggleAutoIndent => "toggle auto-indenting",
+            Action::ToggleVirtualSpace => "toggle virtual space",
+            Action::ToggleOverwrite => "toggle overwrite mode",
+            Action::ToggleLineNumbers => "toggle line numbers",
+            Action::ToggleScrollbar => "toggle scrollbar",
+            Action::ChangeTabWidth => "change tab width",
+            Action::ToggleReadOnly => "toggle read-only",
+            Action::ToggleClock => "toggle clock",
+            Action::Split => "split window",
+            Action::VerticalSplit => "split window vertically",
+            Action::FocusOtherPane => "focus other pane",
+            Action::CloseSplit => "close split",
+            Action::Save => "save",
+            Action::ShowUnsavedChanges => "show what saving would change",
+            Action::ReopenWithEncoding => "reopen with next encoding",
+            Action::ChangeSaveEncoding => "change encoding to save with",
+            Action::ToggleLineEnding => "toggle line endings to save with",
+            Action::SetLanguage => "set the language to highlight as",
+            Action::CommandPalette => "command palette",
+            Action::OpenRecent => "open recent file",
+            Action::SaveSession => "save session",
+            Action::RestoreSession => "restore session",
+            Action::Complete => "complete",
+            Action::GoToDefinition => "go to definition",
+            Action::Hover => "show documentation",
+            Action::SpellingSuggestions => "suggest spellings",
+            Action::AddToDictionary => "add word to dictionary",
+            Action::JumpBack => "jump back",
+            Action::JumpForward => "jump forward",
+            Action::FindFile => "find file",
+            Action::NewFile => "new file",
+            Action::AlternateFile => "switch to previous file",
+            Action::CloseFile => "close file",
+            Action::BrowseDirectory => "browse directory",
+            Action::ToggleMacroRecording => "start/stop recording macro",
+            Action::ReplayMacro => "replay macro",
+            Action::ReplayMacroOnLines => "replay macro on each selected line",
+            Action::Cancel => "cancel",
+            Action::Quit => "quit",
+        }
+    }
+
+    /// Whether the action only moves the cursor or the view,
+    /// so that it doing nothing means it couldn't go any further.
+    pub fn is_movement(self) -> bool {
+        matches!(
+            self,
+            Action::Left
+                | Action::Right
+                | Action::Up
+                | Action::Down
+                | Action::Home
+                | Action::End
+                | Action::NextBlankLine
+                | Action::PreviousBlankLine
+                | Action::NextSiblingLine
+                | Action::PreviousSiblingLine
+                | Action::NextDiagnostic
+                | Action::PreviousDiagnostic
+                | Action::NextHunk
+                | Action::PreviousHunk
+                | Action::ScrollUp
+                | Action::ScrollDown
+                | Action::JumpBack
+                | Action::JumpForward
+        )
+    }
+
+    /// Whether a count before this action should run it that many times.
+    pub fn is_repeatable(self) -> bool {
+        !matches!(
+            self,
+            Action::ToggleTrailingWhitespace
+                | Action::ToggleRainbowBrackets
+                | Action::ToggleSoftTabs
+                | Action::ToggleAutoIndent
+                | Action::ToggleVirtualSpace
+                | Action::ToggleOverwrite
+                | Action::ToggleLineNumbers
+                | Action::ToggleScrollbar
+                | Action::ChangeTabWidth
+                | Action::ToggleReadOnly
+                | Action::ToggleClock
+                | Action::SelectLines
+                | Action::SelectBlock
+                | Action::SelectAll
+                | Action::SetMark
+                | Action::CutToMark
+                | Action::CopyToMark
+                | Action::CommentToMark
+                | Action::FilterToMark
+                | Action::SelectFunction
+                | Action::ExpandSelection
+                | Action::ShrinkSelection
+                | Action::UniqueLines
+                | Action::TrimTrailingBlankLines
+                | Action::Align
+                | Action::FilterThroughShell
+                | Action::ToggleFold
+                | Action::FoldAll
+                | Action::UnfoldAll
+                | Action::Copy
+                | Action::Split
+                | Action::VerticalSplit
+                | Action::CloseSplit
+                | Action::Save
+                | Action::ShowUnsavedChanges
+                | Action::ReopenWithEncoding
+                | Action::ChangeSaveEncoding
+                | Action::ToggleLineEnding
+                | Action::SetLanguage
+                | Action::CommandPalette
+                | Action::OpenRecent
+                | Action::SaveSession
+                | Action::RestoreSession
+                | Action::Complete
+                | Action::GoToDefinition
+                | Action::Hover
+                | Action::SpellingSuggestions
+                | Action::AddToDictionary
+                | Action::FindFile
+                | Action::NewFile
+                | Action::AlternateFile
+                | Action::Find
+                | Action::FindBackward
+                | Action::FindCharForward
+                | Action::FindCharBackward
+                | Action::TillCharForward
+                | Action::TillCharBackward
+                | Action::CloseFile
+                | Action::BrowseDirectory
+                | Action::ToggleMacroRecording
+                | Action::ReplayMacroOnLines
+                | Action::Cancel
+                | Action::Quit
+        )
+    }
+
+    /// The action called `name`, ignoring case.
+    pub fn named(name: &str) -> Option<Action> {
+        Action::ALL
+            .iter()
+            .copied()
+            .find(|action| action.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Which key sequences run which actions: the default bindings, with any of them changed.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<(Vec<KeyEvent>, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = BINDINGS
+            .iter()
+            .map(|(keys, action)| (keys.to_vec(), *action))
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl Keymap {
+    /// Binds `action` to `keys` instead of the keys it was bound to before. Whatever else `keys`
+    /// ran is unbound, as is anything they're the start of or that's the start of them, since
+    /// it couldn't be reached any more.
+    pub fn bind(&mut self, action: Action, keys: Vec<KeyEvent>) {
+        self.bindings.retain(|(bound, bound_action)| {
+            *bound_action != action && !bound.starts_with(&keys) && !keys.starts_with(bound)
+        });
+        self.bindings.push((keys, action));
+    }
+
+    pub fn lookup(&self, keys: &[KeyEvent]) -> Lookup {
+        let mut lookup = Lookup::Unbound;
+
+        for (bound, action) in &self.bindings {
+            if *bound == keys {
+                return Lookup::Action(*action);
+            }
+            if bound.starts_with(keys) {
+                lookup = Lookup::Prefix;
+            }
+        }
+
+        lookup
+    }
+
+    /// The bindings that start with `prefix`, with the prefix removed.
+    pub fn continuations(&self, prefix: &[KeyEvent]) -> Vec<(&[KeyEvent], Action)> {
+        self.bindings
+            .iter()
+            .filter(|(bound, _)| bound.len() > prefix.len() && bound.starts_with(prefix))
+            .map(|(bound, action)| (&bound[prefix.len()..], *action))
+            .collect()
+    }
+
+    /// The first key sequence bound to `action`, if any.
+    pub fn keys(&self, action: Action) -> Option<&[KeyEvent]> {
+        self.bindings
+            .iter()
+            .find(|(_, bound_action)| *bound_action == action)
+            .map(|(keys, _)| keys.as_slice())
+    }
+}
+
+const BINDINGS: &[(&[KeyEvent], Action)] = &[
+    (&[key(KeyCode::Backspace)], Action::Backspace),
+    (&[key(KeyCode::Delete)], Action::Delete),
+    (&[key(KeyCode::Enter)], Action::Enter),
+    (&[key(KeyCode::Left)], Action::Left),
+    (&[key(KeyCode::Right)], Action::Right),
+    (&[key(KeyCode::Up)], Action::Up),
+    (&[key(KeyCode::Down)], Action::Down),
+    (&[key(KeyCode::Home)], Action::Home),
+    (&[key(KeyCode::End)], Action::End),
+    (&[ctrl('f')], Action::Find),
+    (&[ctrl('r')], Action::FindBackward),
+    // like vim's ; for repeating a search within the line
+    (&[alt(';')], Action::RepeatSearch),
+    // like vim's f, F, t and T
+    (&[alt('j')], Action::FindCharForward),
+    (&[alt('k')], Action::FindCharBackward),
+    (&[alt('t')], Action::TillCharForward),
+    (&[alt('y')], Action::TillCharBackward),
+    (&[ctrl_code(KeyCode::Down)], Action::NextBlankLine),
+    (&[ctrl_code(KeyCode::Up)], Action::PreviousBlankLine),
+    (&[alt_code(KeyCode::PageDown)], Action::NextSiblingLine),
+    (&[alt_code(KeyCode::PageUp)], Action::PreviousSiblingLine),
+    (&[shift(KeyCode::Left)], Action::SelectLeft),
+    (&[shift(KeyCode::Right)], Action::SelectRight),
+    (&[shift(KeyCode::Up)], Action::SelectUp),
+    (&[shift(KeyCode::Down)], Action::SelectDown),
+    (&[shift(KeyCode::Home)], Action::SelectHome),
+    (&[shift(KeyCode::End)], Action::SelectEnd),
+    (&[ctrl('l')], Action::SelectLines),
+    (&[ctrl('b')], Action::SelectBlock),
+    (&[alt('e')], Action::ExpandSelection),
+    (&[alt('s')], Action::ShrinkSelection),
+    (&[alt('o')], Action::SelectEnclosingNode),
+    (&[alt('n')], Action::SelectNextSibling),
+    (&[alt('p')], Action::SelectPreviousSibling),
+    (&[alt('f')], Action::SelectFunction),
+    (&[ctrl('d')], Action::AddCursorAtNextOccurrence),
+    (&[alt_code(KeyCode::Up)], Action::AddCursorAbove),
+    (&[alt_code(KeyCode::Down)], Action::AddCursorBelow),
+    (&[ctrl('c')], Action::Copy),
+    (&[ctrl('x')], Action::Cut),
+    (&[ctrl('v')], Action::Paste),
+    (&[alt('v')], Action::PasteReindented),
+    (&[ctrl('a')], Action::SelectAll),
+    (&[ctrl('k'), char_key('u')], Action::DuplicateSelection),
+    (&[ctrl('k'), char_key(' ')], Action::SetMark),
+    // Emacs's kill-region
+    (&[ctrl('k'), char_key('k')], Action::CutToMark),
+    (&[key(KeyCode::Tab)], Action::Indent),
+    // keys are normalized so that Shift+Tab always arrives as this
+    (&[shift(KeyCode::BackTab)], Action::Dedent),
+    (&[alt('r')], Action::ReverseLines),
+    (&[alt('u')], Action::UniqueLines),
+    // Emacs's fill-paragraph
+    (&[alt('q')], Action::ReflowParagraph),
+    (&[ctrl('k'), char_key('=')], Action::Align),
+    // for piping it through, like vim's `!` and Kakoune's `|`
+    (&[ctrl('k'), char_key('p')], Action::FilterThroughShell),
+    // vim's Ctrl+A and Ctrl+X, moved to Alt since Ctrl+X cuts
+    (&[alt('a')], Action::Increment),
+    (&[alt('x')], Action::Decrement),
+    (&[ctrl('k'), char_key('f')], Action::ToggleFold),
+    (&[ctrl('k'), char_key('0')], Action::FoldAll),
+    (&[ctrl('k'), char_key('j')], Action::UnfoldAll),
+    (&[key(KeyCode::F(8))], Action::NextDiagnostic),
+    (&[shift(KeyCode::F(8))], Action::PreviousDiagnostic),
+    (&[ctrl('k'), char_key(']')], Action::NextHunk),
+    (&[ctrl('k'), char_key('[')], Action::PreviousHunk),
+    (&[ctrl('k'), key(KeyCode::Up)], Action::ScrollUp),
+    (&[ctrl('k'), key(KeyCode::Down)], Action::ScrollDown),
+    (&[ctrl('z')], Action::Undo),
+    (&[ctrl('y')], Action::Redo),
+    (&[ctrl('k'), char_key(',')], Action::Earlier),
+    (&[ctrl('k'), char_key('.')], Action::Later),
+    (&[alt('.')], Action::RepeatLastChange),
+    (
+        &[ctrl('k'), char_key('w')],
+        Action::ToggleTrailingWhitespace,
+    ),
+    (&[ctrl('k'), char_key('b')], Action::ToggleRainbowBrackets),
+    (&[ctrl('k'), key(KeyCode::Tab)], Action::ToggleSoftTabs),
+    (&[ctrl('k'), char_key('i')], Action::ToggleAutoIndent),
+    (&[ctrl('k'), char_key('v')], Action::ToggleVirtualSpace),
+    (&[key(KeyCode::Insert)], Action::ToggleOverwrite),
+    (&[ctrl('k'), char_key('n')], Action::ToggleLineNumbers),
+    (&[ctrl('k'), char_key('t')], Action::ChangeTabWidth),
+    (&[ctrl('k'), char_key('r')], Action::ToggleReadOnly),
+    (&[ctrl('w'), char_key('s')], Action::Split),
+    (&[ctrl('w'), char_key('v')], Action::VerticalSplit),
+    (&[ctrl('w'), char_key('w')], Action::FocusOtherPane),
+    (&[ctrl('w'), char_key('q')], Action::CloseSplit),
+    (&[ctrl('s')], Action::Save),
+    (&[ctrl('k'), char_key('m')], Action::ShowUnsavedChanges),
+    (&[ctrl('k'), char_key('e')], Action::ReopenWithEncoding),
+    (&[ctrl('k'), char_key('c')], Action::ChangeSaveEncoding),
+    (&[ctrl('k'), char_key('l')], Action::ToggleLineEnding),
+    (&[ctrl('k'), char_key('y')], Action::SetLanguage),
+    (&[ctrl('p')], Action::CommandPalette),
+    (&[ctrl('k'), char_key('o')], Action::OpenRecent),
+    (&[ctrl(' ')], Action::Complete),
+    (&[key(KeyCode::F(12))], Action::GoToDefinition),
+    (&[ctrl('k'), char_key('h')], Action::Hover),
+    (&[ctrl('k'), char_key('s')], Action::SpellingSuggestions),
+    (&[ctrl('k'), char_key('a')], Action::AddToDictionary),
+    (&[alt('-')], Action::JumpBack),
+    (&[alt('=')], Action::JumpForward),
+    (&[ctrl('t')], Action::FindFile),
+    (&[ctrl('n')], Action::NewFile),
+    // Ctrl+^ like in vim, which terminals send as Ctrl+6
+    (&[ctrl('6')], Action::AlternateFile),
+    // Ctrl+/, which terminals send as Ctrl+7
+    (&[ctrl('7')], Action::ToggleComment),
+    (&[ctrl('k'), char_key('x')], Action::CloseFile),
+    (&[ctrl('k'), char_key('d')], Action::BrowseDirectory),
+    (&[key(KeyCode::F(3))], Action::ToggleMacroRecording),
+    (&[key(KeyCode::F(4))], Action::ReplayMacro),
+    (&[shift(KeyCode::F(4))], Action::ReplayMacroOnLines),
+    (&[key(KeyCode::Esc)], Action::Cancel),
+    (&[ctrl('q')], Action::Quit),
+];
+
+pub enum Lookup {
+    Action(Action),
+    /// The keys are the start of at least one longer binding.
+    Prefix,
+    Unbound,
+}
+
+/// Makes the different ways terminals send the same key look the same, so that bindings only
+/// have to be written one way.
+pub fn normalize(key: KeyEvent) -> KeyEvent {
+    let code = match key.code {
+        // Shift+Tab is BackTab in most terminals, but Tab with Shift in some
+        KeyCode::Tab if key.modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+        code => code,
+    };
+    let mut modifiers = key.modifiers;
+    // and BackTab comes with or without Shift
+    if code == KeyCode::BackTab {
+        modifiers |= KeyModifiers::SHIFT;
+    }
+    // Shift+Delete is still just Delete, since nothing is bound to it
+    if code == KeyCode::Delete {
+        modifiers -= KeyModifiers::SHIFT;
+    }
+
+    // neither does whether it's repeating, or caps lock or num lock being on
+    KeyEvent::new(code, modifiers)
+}
+
+pub fn keys_name(keys: &[KeyEvent]) -> String {
+    keys.iter()
+        .map(|key| key_name(*key))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn key_name(key: KeyEvent) -> String {
+    let mut name = String::new();
+
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        name.push_str("Ctrl+");
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        name.push_str("Alt+");
+    }
+    if key.modifiers.contains(KeyModifiers::SHIFT) {
+        name.push_str("Shift+");
+    }
+
+    match key.code {
+        KeyCode::Char(' ') => name.push_str("Space"),
+        KeyCode::Char(c) if key.modifiers.is_empty() => name.push(c),
+        KeyCode::Char(c) => name.push(c.to_ascii_uppercase()),
+        KeyCode::F(n) => name.push_str(&format!("F{n}")),
+        KeyCode::Backspace => name.push_str("Backspace"),
+        KeyCode::Enter => name.push_str("Enter"),
+        KeyCode::Left => name.push_str("Left"),
+        KeyCode::Right => name.push_str("Right"),
+        KeyCode::Up => name.push_str("Up"),
+        KeyCode::Down => name.push_str("Down"),
+        KeyCode::Home => name.push_str("Home"),
+        KeyCode::End => name.push_str("End"),
+        KeyCode::PageUp => name.push_str("PageUp"),
+        KeyCode::PageDown => name.push_str("PageDown"),
+        KeyCode::Tab => name.push_str("Tab"),
+        KeyCode::BackTab => name.push_str("BackTab"),
+        KeyCode::Delete => name.push_str("Delete"),
+        KeyCode::Insert => name.push_str("Insert"),
+        KeyCode::Null => name.push_str("Null"),
+        KeyCode::Esc => name.push_str("Esc"),
+        code => name.push_str(&format!("{code:?}")),
+    }
+
+    name
+}
+
+/// Reads a key sequence written the way `keys_name` writes them, like `Ctrl+X Ctrl+C`.
+pub fn parse_keys(text: &str) -> Option<Vec<KeyEvent>> {
+    let keys: Option<Vec<KeyEvent>> = text.split_whitespace().map(parse_key).collect();
+    keys.filter(|keys| !keys.is_empty())
+}
+
+/// Reads a key written the way `key_name` writes them, like `Ctrl+Q` or `Alt+Left`.
+fn parse_key(text: &str) -> Option<KeyEvent> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = text;
+    // a lone `+` is the key itself rather than joining a modifier on
+    while let Some((modifier, after)) = rest.split_once('+').filter(|(_, after)| !after.is_empty())
+    {
+        modifiers |= match modifier.to_ascii_lowercase().as_str() {
+            "ctrl" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+        rest = after;
+    }
+
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        // letters are named in upper case after a modifier, but terminals send them in lower
+        (Some(c), None) if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+            KeyCode::Char(c.to_ascii_lowercase())
+        }
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match rest.to_ascii_lowercase().as_str() {
+            "space" => KeyCode::Char(' '),
+            "backspace" => KeyCode::Backspace,
+            "enter" => KeyCode::Enter,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "delete" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "esc" => KeyCode::Esc,
+            name => KeyCode::F(name.strip_prefix('f')?.parse().ok()?),
+        },
+    };
+
+    Some(normalize(KeyEvent::new(code, modifiers)))
+}
+
+const fn key(code: KeyCode) -> KeyEvent {
+    KeyEvent::new(code, KeyModifiers::NONE)
+}
+
+const fn char_key(c: char) -> KeyEvent {
+    key(KeyCode::Char(c))
+}
+
+const fn shift(code: KeyCode) -> KeyEvent {
+    KeyEvent::new(code, KeyModifiers::SHIFT)
+}
+
+const fn ctrl(c: char) -> KeyEvent {
+    KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+}
+
+const fn ctrl_code(code: KeyCode) -> KeyEvent {
+    KeyEvent::new(code, KeyModifiers::CONTROL)
+}
+
+const fn alt_code(code: KeyCode) -> KeyEvent {
+    KeyEvent::new(code, KeyModifiers::ALT)
+}
+
+const fn alt(c: char) -> KeyEvent {
+    KeyEvent::new(KeyCode::Char(c), KeyModifiers::ALT)
+}
@@ -0,0 +1,18 @@
+//! The editor's editing, apart from the terminal: [`SourceEditor`] holds a buffer and everything
+//! about how it's being edited, and the modules here do the highlighting, diffing, spell checking
+//! and talking to language servers that it relies on.
+
+pub mod diff;
+pub mod lsp;
+pub mod snippet;
+pub mod spell;
+pub mod syntax;
+#[cfg(feature = "tree-sitter")]
+pub mod tree;
+
+mod paging;
+#[cfg(test)]
+mod rng;
+mod source_editor;
+
+pub use source_editor::*;
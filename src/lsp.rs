@@ -0,0 +1,518 @@
+//! A minimal Language Server Protocol client, just enough for completions, diagnostics,
+//! hover documentation and going to definitions.
+//!
+//! Requests block until the server answers (or takes too long), which keeps things simple
+//! since everything but diagnostics is only ever asked for explicitly. Diagnostics come
+//! whenever the server is done working them out, so they're collected in between.
+
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the server to answer a request before giving up on it.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    /// Messages from the server, read in on another thread.
+    messages: Receiver<Value>,
+    next_id: u64,
+    command: Vec<String>,
+    uri: String,
+    version: u64,
+    /// The latest diagnostics for the file, if they haven't been taken yet.
+    diagnostics: Option<Vec<Diagnostic>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub label: String,
+    pub detail: String,
+    pub text: String,
+    /// The UTF-16 columns on the cursor's line that `text` replaces, if the server said.
+    pub range: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub path: PathBuf,
+    pub row: usize,
+    /// In UTF-16, like all LSP positions.
+    pub column: usize,
+}
+
+/// Ordered from most to least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// Rows and UTF-16 columns, like all LSP positions.
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Client {
+    /// Starts the server and tells it about the file being edited.
+    pub fn start(command: &[String], path: &Path, text: &str) -> Result<Self, String> {
+        let (program, args) = command
+            .split_first()
+            .ok_or("the language server command is empty")?;
+        let path = path
+            .canonicalize()
+            .map_err(|e| format!("couldn't find {}: {e}", path.display()))?;
+        let root = path.parent().unwrap_or(&path);
+
+        let mut child = Command::new(program)
+            .args(args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("couldn't start {program}: {e}"))?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            while let Ok(message) = read_message(&mut reader) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut client = Self {
+            child,
+            stdin,
+            messages,
+            next_id: 0,
+            command: command.to_vec(),
+            uri: String::new(),
+            version: 0,
+            diagnostics: None,
+        };
+
+        client.request(
+            "initialize",
+            json!({
+                "processId": std::process::id(),
+                "rootUri": file_uri(root),
+                "capabilities": {
+                    "textDocument": {
+                        "completion": { "completionItem": { "snippetSupport": false } },
+                    },
+                },
+            }),
+        )?;
+        client.notify("initialized", json!({}))?;
+        client.open(&path, text)?;
+
+        Ok(client)
+    }
+
+    /// The command the server was started with.
+    pub fn command(&self) -> &[String] {
+        &self.command
+    }
+
+    /// Switches to editing another file, which is much quicker than starting a new server.
+    pub fn open(&mut self, path: &Path, text: &str) -> Result<(), String> {
+        let path = path
+            .canonicalize()
+            .map_err(|e| format!("couldn't find {}: {e}", path.display()))?;
+
+        if !self.uri.is_empty() {
+            let uri = self.uri.clone();
+            self.notify(
+                "textDocument/didClose",
+                json!({ "textDocument": { "uri": uri } }),
+            )?;
+        }
+        self.uri = file_uri(&path);
+        self.version = 0;
+        self.diagnostics = None;
+
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": self.uri,
+                    "languageId": language_id(&path),
+                    "version": self.version,
+                    "text": text,
+                },
+            }),
+        )
+    }
+
+    /// Sends the whole of the new text, which is simplest and is fast enough locally.
+    pub fn did_change(&mut self, text: &str) -> Result<(), String> {
+        self.version += 1;
+        self.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": self.uri, "version": self.version },
+                "contentChanges": [{ "text": text }],
+            }),
+        )
+    }
+
+    /// The completions at `row` and the given UTF-16 column.
+    pub fn completions(&mut self, row: usize, column: usize) -> Result<Vec<Completion>, String> {
+        let result = self.request(
+            "textDocument/completion",
+            json!({
+                "textDocument": { "uri": self.uri },
+                "position": { "line": row, "character": column },
+            }),
+        )?;
+
+        // the result is either a list of items or an object holding one
+        let items = match &result {
+            Value::Array(items) => items,
+            Value::Object(list) => match list.get("items") {
+                Some(Value::Array(items)) => items,
+                _ => return Ok(Vec::new()),
+            },
+            _ => return Ok(Vec::new()),
+        };
+
+        Ok(items.iter().filter_map(completion).collect())
+    }
+
+    /// Where the symbol at `row` and the given UTF-16 column is defined, if the server knows.
+    pub fn definition(&mut self, row: usize, column: usize) -> Result<Option<Location>, String> {
+        let result = self.request(
+            "textDocument/definition",
+            json!({
+                "textDocument": { "uri": self.uri },
+                "position": { "line": row, "character": column },
+            }),
+        )?;
+
+        // there can be several definitions, but the first is usually the one wanted
+        let location = match &result {
+            Value::Array(locations) => match locations.first() {
+                Some(location) => location,
+                None => return Ok(None),
+            },
+            Value::Null => return Ok(None),
+            location => location,
+        };
+
+        // it's either a Location or a LocationLink
+        let uri = location["uri"].as_str().or(location["targetUri"].as_str());
+        let range = match location.get("targetSelectionRange") {
+            Some(range) => range,
+            None => &location["range"],
+        };
+        let start = &range["start"];
+        let location = (|| {
+            Some(Location {
+                path: file_path(uri?)?,
+                row: start["line"].as_u64()? as usize,
+                column: start["character"].as_u64()? as usize,
+            })
+        })();
+
+        Ok(location)
+    }
+
+    /// The documentation for whatever's at `row` and the given UTF-16 column, as plain text.
+    pub fn hover(&mut self, row: usize, column: usize) -> Result<Option<String>, String> {
+        let result = self.request(
+            "textDocument/hover",
+            json!({
+                "textDocument": { "uri": self.uri },
+                "position": { "line": row, "character": column },
+            }),
+        )?;
+
+        let text = match &result["contents"] {
+            Value::Array(parts) => parts
+                .iter()
+                .map(marked_string)
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+            contents => marked_string(contents),
+        };
+
+        Ok(Some(text).filter(|text| !text.trim().is_empty()))
+    }
+
+    /// Any diagnostics that have come in since this was last called.
+    pub fn take_diagnostics(&mut self) -> Result<Option<Vec<Diagnostic>>, String> {
+        loop {
+            match self.messages.try_recv() {
+                Ok(message) => self.handle(message)?,
+                Err(TryRecvError::Empty) => return Ok(self.diagnostics.take()),
+                Err(TryRecvError::Disconnected) => {
+                    return Err("the language server exited".to_string())
+                }
+            }
+        }
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let message = match self.messages.recv_timeout(timeout) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err("the language server took too long to answer".to_string())
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err("the language server exited".to_string())
+                }
+            };
+
+            if message.get("method").is_some() || message.get("id") != Some(&json!(id)) {
+                self.handle(message)?;
+                continue;
+            }
+
+            if let Some(error) = message.get("error") {
+                let text = error["message"].as_str().unwrap_or("unknown error");
+                return Err(format!("the language server failed: {text}"));
+            }
+            return Ok(message.get("result").cloned().unwrap_or(Value::Null));
+        }
+    }
+
+    /// Deals with a message that isn't the response to a request we're waiting on.
+    fn handle(&mut self, message: Value) -> Result<(), String> {
+        let method = match message["method"].as_str() {
+            Some(method) => method,
+            // a response to a request we've given up on
+            None => return Ok(()),
+        };
+
+        // the server can ask us things too; we don't support any of them,
+        // but answering keeps it from waiting on us
+        if let Some(id) = message.get("id") {
+            return self.send(json!({ "jsonrpc": "2.0", "id": id, "result": null }));
+        }
+
+        let params = &message["params"];
+        if method == "textDocument/publishDiagnostics" && params["uri"] == self.uri.as_str() {
+            if let Some(diagnostics) = params["diagnostics"].as_array() {
+                self.diagnostics = Some(diagnostics.iter().filter_map(diagnostic).collect());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> Result<(), String> {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn send(&mut self, message: Value) -> Result<(), String> {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{body}", body.len())
+            .and_then(|()| self.stdin.flush())
+            .map_err(|e| format!("couldn't talk to the language server: {e}"))
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The UTF-16 column that LSP positions use for the char column `column` in `line`.
+pub fn utf16_column(line: &str, column: usize) -> usize {
+    line.chars().take(column).map(char::len_utf16).sum()
+}
+
+/// The char column in `line` for the UTF-16 column `column`.
+pub fn char_column(line: &str, column: usize) -> usize {
+    let mut utf16 = 0;
+    line.chars()
+        .take_while(|c| {
+            utf16 += c.len_utf16();
+            utf16 <= column
+        })
+        .count()
+}
+
+fn completion(item: &Value) -> Option<Completion> {
+    let label = item["label"].as_str()?.to_string();
+    let detail = item["detail"].as_str().unwrap_or_default().to_string();
+
+    let edit = &item["textEdit"];
+    // servers can give separate ranges for inserting and replacing; we always replace
+    let range = edit.get("range").or_else(|| edit.get("replace"));
+    let range = range.and_then(|range| {
+        let start = range["start"]["character"].as_u64()?;
+        let end = range["end"]["character"].as_u64()?;
+        Some((start as usize, end as usize))
+    });
+    let text = edit["newText"]
+        .as_str()
+        .or_else(|| item["insertText"].as_str())
+        .unwrap_or(&label)
+        .to_string();
+
+    Some(Completion {
+        label,
+        detail,
+        text,
+        range,
+    })
+}
+
+/// Hover contents are markdown or plain strings, or code in a given language.
+fn marked_string(contents: &Value) -> String {
+    match contents {
+        Value::String(text) => markdown_to_plain_text(text),
+        Value::Object(contents) => match contents.get("value").and_then(Value::as_str) {
+            Some(text) if contents.get("kind").and_then(Value::as_str) == Some("plaintext") => {
+                text.to_string()
+            }
+            // code that's been given with its language is shown as-is
+            Some(text) if contents.contains_key("language") => text.to_string(),
+            Some(text) => markdown_to_plain_text(text),
+            None => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+/// Drops the bits of markdown syntax that are just noise when shown as they are.
+fn markdown_to_plain_text(markdown: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_code = false;
+
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            lines.push(line.to_string());
+            continue;
+        }
+
+        let line = line.trim_start_matches('#').trim_start();
+        let line = if line.chars().all(|c| c == '-') && line.len() >= 3 {
+            ""
+        } else {
+            line
+        };
+        lines.push(line.replace("**", "").replace('`', "").replace("\\", ""));
+    }
+
+    lines.join("\n")
+}
+
+fn diagnostic(diagnostic: &Value) -> Option<Diagnostic> {
+    let position = |position: &Value| {
+        let row = position["line"].as_u64()?;
+        let column = position["character"].as_u64()?;
+        Some((row as usize, column as usize))
+    };
+    let severity = match diagnostic["severity"].as_u64() {
+        Some(2) => Severity::Warning,
+        Some(3) => Severity::Information,
+        Some(4) => Severity::Hint,
+        // servers should always say but don't have to, and errors are the safest guess
+        _ => Severity::Error,
+    };
+
+    Some(Diagnostic {
+        start: position(&diagnostic["range"]["start"])?,
+        end: position(&diagnostic["range"]["end"])?,
+        severity,
+        message: diagnostic["message"].as_str()?.to_string(),
+    })
+}
+
+fn read_message(reader: &mut BufReader<ChildStdout>) -> io::Result<Value> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            len = value.trim().parse().ok();
+        }
+    }
+
+    let len = len.ok_or(io::ErrorKind::InvalidData)?;
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map_err(io::Error::from)
+}
+
+fn file_uri(path: &Path) -> String {
+    let mut uri = "file://".to_string();
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri
+}
+
+fn file_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::new();
+    let mut idx = 0;
+    while idx < encoded.len() {
+        if encoded[idx] == b'%' {
+            let hex = std::str::from_utf8(encoded.get(idx + 1..idx + 3)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            idx += 3;
+        } else {
+            bytes.push(encoded[idx]);
+            idx += 1;
+        }
+    }
+
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+fn language_id(path: &Path) -> &str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("rs") => "rust",
+        Some("py") => "python",
+        Some("js") => "javascript",
+        Some("ts") => "typescript",
+        Some("c" | "h") => "c",
+        Some("cpp" | "hpp" | "cc") => "cpp",
+        Some("go") => "go",
+        Some(extension) => extension,
+        None => "plaintext",
+    }
+}
@@ -1,324 +1,3750 @@
+mod clock;
+mod config;
+mod encoding;
+mod finder;
+mod fuzzy;
+mod git;
+mod keymap;
+mod osc52;
+mod positions;
+mod recent;
+#[cfg(test)]
+mod rng;
+mod session;
+mod shell;
+mod theme;
+
 use crossterm::style::Stylize;
 use crossterm::{cursor, event, queue, style, terminal};
-use std::io::{self, Write};
-use std::path::PathBuf;
-use std::{env, fs};
+#[cfg(feature = "tree-sitter")]
+use editor::tree;
+use editor::{
+    diff, lsp, spell, syntax, Clipboard, Found, HighlightKind, Search, Settings, SourceEditor,
+    Span, SyntaxNode, View,
+};
+use encoding::{FileEncoding, FileFormat, LineEnding};
+use keymap::{Action, Keymap};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, fs, mem, process};
+use theme::Theme;
+
+/// The tab widths that changing it cycles through.
+const TAB_WIDTHS: &[usize] = &[2, 4, 8];
+
+/// The smallest window everything can be laid out in.
+const MIN_WIDTH: usize = 20;
+const MIN_HEIGHT: usize = 5;
+
+/// The most times a count can repeat an action, so that a mistyped one can't hang the editor.
+const MAX_COUNT: usize = 10_000;
+
+/// How many symlinks in a row are followed when saving, like the limit the OS has.
+const MAX_SYMLINKS: usize = 40;
+
+/// Files larger than this are loaded incrementally as they're scrolled through.
+const LAZY_LOAD_THRESHOLD: u64 = 16 * 1024 * 1024;
+/// Files larger than this are paged in and out around the view, rather than ever all being in
+/// memory.
+const PAGING_THRESHOLD: u64 = 256 * 1024 * 1024;
+
+/// How long to wait after a key prefix before showing what can follow it.
+const KEY_HINT_DELAY: Duration = Duration::from_millis(500);
+
+/// How soon after the last one a mouse wheel notch has to come to scroll further when scrolling
+/// speeds up, and how many times as far as usual it can go.
+const SCROLL_ACCELERATION_WINDOW: Duration = Duration::from_millis(50);
+const MAX_SCROLL_ACCELERATION: usize = 3;
+
+/// How long the status bar flashes for when a key does nothing.
+const FLASH_DURATION: Duration = Duration::from_millis(100);
+
+/// How long messages stay in the status bar for.
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+/// How often to check for new diagnostics while waiting for input.
+const DIAGNOSTICS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many unchanged lines are shown around each change when showing unsaved changes.
+const DIFF_CONTEXT: usize = 3;
+
+fn main() {
+    if let Err(e) = edit() {
+        eprintln!("editor: {e}");
+        process::exit(1);
+    }
+}
+
+fn edit() -> io::Result<()> {
+    // anything other than a terminal would only get escape codes written to it
+    if !io::stdout().is_terminal() {
+        return Err(io::Error::other("not a terminal"));
+    }
+
+    let mut read_only = false;
+    let mut osc52 = false;
+    let mut file_to_edit = None;
+    let mut dir_to_browse = None;
+    let mut from_stdin = false;
+    let mut restore_session = false;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--read-only" => read_only = true,
+            "--osc52" => osc52 = true,
+            "-" => from_stdin = true,
+            "--session" => restore_session = true,
+            _ => file_to_edit = Some(arg),
+        }
+    }
+    // something's being piped in, and keys will be read from the terminal instead
+    if file_to_edit.is_none() && !io::stdin().is_terminal() {
+        from_stdin = true;
+    }
 
-fn main() -> io::Result<()> {
-    let (path, text) = match env::args().nth(1) {
+    let (path, mut source_editor, binary, format) = match file_to_edit {
+        _ if from_stdin => {
+            let (source_editor, binary, format) = read_stdin()?;
+            (None, source_editor, binary, format)
+        }
+        Some(dir) if Path::new(&dir).is_dir() => {
+            dir_to_browse = Some(PathBuf::from(dir));
+            (
+                None,
+                SourceEditor::new(String::new()),
+                false,
+                FileFormat::default(),
+            )
+        }
         Some(file_to_edit) => {
             let file_to_edit = PathBuf::from(file_to_edit);
-            let text = fs::read_to_string(&file_to_edit)?;
-            (Some(file_to_edit), text)
+            let (source_editor, binary, format) = open_file(&file_to_edit).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("couldn't open {}: {e}", file_to_edit.display()),
+                )
+            })?;
+            recent::add(config::state_dir().as_deref(), &file_to_edit);
+            (Some(file_to_edit), source_editor, binary, format)
         }
-        None => (None, String::new()),
+        None => (
+            None,
+            SourceEditor::new(String::new()),
+            false,
+            FileFormat::default(),
+        ),
     };
     let stdout = io::stdout();
 
-    Ui::new(text, path, stdout.lock())?.run()?;
+    // saving a binary file that was loaded as text can corrupt it,
+    // so we only allow viewing it
+    source_editor.read_only = read_only || binary;
+
+    let (config, mut config_error) = match config::load() {
+        Ok(config) => (config, None),
+        Err(e) => (config::Config::default(), Some(e)),
+    };
+    let theme = config.theme().unwrap_or_else(|e| {
+        config_error = Some(e);
+        Theme::default()
+    });
+    let keymap = config.keymap().unwrap_or_else(|e| {
+        config_error = Some(e);
+        Keymap::default()
+    });
+    let dictionary = if config.spell_check {
+        let personal = config::dir().map(|dir| dir.join("dictionary"));
+        spell::Dictionary::load(&config.dictionary, personal)
+            .map_err(|e| {
+                let path = config.dictionary.display();
+                config_error = Some(format!("couldn't load dictionary {path}: {e}"));
+            })
+            .ok()
+    } else {
+        None
+    };
+    if config.tab_width == 0 {
+        config_error = Some("tab_width has to be at least 1".to_string());
+    }
+    if config.text_width == 0 {
+        config_error = Some("text_width has to be at least 1".to_string());
+    }
+    if config.scroll_lines == 0 {
+        config_error = Some("scroll_lines has to be at least 1".to_string());
+    }
+    source_editor.settings = Settings {
+        tab_width: config.tab_width.max(1),
+        soft_tabs: config.soft_tabs,
+        soft_tab_movement: config.soft_tab_movement,
+        auto_indent: config.auto_indent,
+        markers: config.markers,
+        trailing_whitespace: config.trailing_whitespace,
+        search_wraps: config.search_wraps,
+        text_width: config.text_width.max(1),
+        line_numbers: config.line_numbers,
+        scrollbar: config.scrollbar,
+        gutter_padding: config.gutter_padding,
+        gutter_separator: config.gutter_separator,
+        virtual_space: config.virtual_space,
+        snippets: config.snippets,
+        dictionary,
+        ..Settings::default()
+    };
+
+    let (width, height) = terminal::size()
+        .map_err(|e| io::Error::new(e.kind(), format!("couldn't get the terminal's size: {e}")))?;
+    let mut ui = Ui::new(
+        source_editor,
+        path,
+        binary,
+        format,
+        theme,
+        osc52,
+        stdout.lock(),
+    );
+    ui.resize(width.into(), height.into());
+    ui.read_only_flag = read_only;
+    ui.keymap = keymap;
+    ui.language_servers = config.language_servers;
+    ui.comments = config.comments;
+    ui.auto_indent = config.auto_indent;
+    ui.auto_indent_by_extension = config.auto_indent_by_extension;
+    ui.line_length_limit = config.line_length_limit;
+    ui.line_length_limit_by_extension = config.line_length_limit_by_extension;
+    ui.use_settings_for_file();
+    ui.close_to_scratch = config.close_to_scratch;
+    ui.restore_positions = config.restore_position;
+    ui.restore_position();
+    ui.scroll_lines = config.scroll_lines.max(1);
+    ui.scroll_acceleration = config.scroll_acceleration;
+    ui.bell = config.bell;
+    ui.clock = clock::Clock::new(config.clock_format, config.clock);
+    ui.clock.update();
+    ui.open_language_server();
+    if let Some(dir) = dir_to_browse {
+        ui.browse(&dir);
+    }
+    if restore_session {
+        ui.restore_session();
+    }
+    if let Some(e) = config_error {
+        ui.set_error(e);
+    }
+    ui.run()?;
 
     Ok(())
 }
 
-#[derive(Debug)]
-struct Ui<'a> {
-    source_editor: SourceEditor,
-    file: Option<PathBuf>,
-    stdout: io::StdoutLock<'a>,
-    width: usize,
-    height: usize,
-    should_exit: bool,
-}
+/// Loads a file, also returning whether it's binary and how it's encoded.
+fn open_file(path: &Path) -> io::Result<(SourceEditor, bool, FileFormat)> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
 
-impl<'a> Ui<'a> {
-    fn new(buffer: String, file: Option<PathBuf>, stdout: io::StdoutLock<'a>) -> io::Result<Self> {
-        let (width, height) = terminal::size()?;
-        let width = width.into();
-        let height = height.into();
+    let (mut source_editor, binary, format) = if len > LAZY_LOAD_THRESHOLD {
+        let mut reader = BufReader::new(file);
+        let binary = is_binary(reader.fill_buf()?);
+        // paging needs valid UTF-8 all the way through, which binary files seldom are
+        let source_editor = if len > PAGING_THRESHOLD && !binary {
+            SourceEditor::paged(reader.into_inner(), len)?
+        } else {
+            SourceEditor::lazy(reader, len)
+        };
+        (source_editor, binary, FileFormat::default())
+    } else {
+        decode(&fs::read(path)?)
+    };
+    source_editor.set_language(syntax::Language::detect(path));
+    #[cfg(feature = "tree-sitter")]
+    if source_editor.loader.is_none() && !source_editor.is_paged() {
+        source_editor.tree = tree::SyntaxTree::new(path, &source_editor.buffer);
+    }
 
-        Ok(Self {
-            source_editor: SourceEditor::new(buffer, width, height - 1),
-            file,
-            stdout,
-            width,
-            height,
-            should_exit: false,
-        })
+    // the whole file is needed to diff it, which would defeat lazily loading it
+    if len <= LAZY_LOAD_THRESHOLD && !binary {
+        if let Some(head) = git::head_contents(path) {
+            source_editor.set_diff_base(&head);
+        }
     }
 
-    fn run(mut self) -> io::Result<()> {
-        queue!(
-            self.stdout,
-            terminal::EnterAlternateScreen,
-            event::EnableMouseCapture
-        )?;
-        terminal::enable_raw_mode()?;
+    Ok((source_editor, binary, format))
+}
 
-        while !self.should_exit {
-            self.render()?;
-            self.handle_event()?;
-        }
+/// Reads the buffer from whatever's piped in.
+fn read_stdin() -> io::Result<(SourceEditor, bool, FileFormat)> {
+    let mut bytes = Vec::new();
+    io::stdin().lock().read_to_end(&mut bytes)?;
 
-        terminal::disable_raw_mode()?;
-        queue!(
-            self.stdout,
-            event::DisableMouseCapture,
-            terminal::LeaveAlternateScreen
-        )?;
+    Ok(decode(&bytes))
+}
 
-        Ok(())
-    }
+/// Makes a buffer from the bytes of a file, also returning whether they're binary and how
+/// they're encoded.
+fn decode(bytes: &[u8]) -> (SourceEditor, bool, FileFormat) {
+    let (text, encoding) = encoding::decode(bytes);
+    let (text, line_ending) = LineEnding::normalize(text);
+    // UTF-16 text is full of zero bytes, but that doesn't make it binary
+    let binary = !encoding.is_utf16() && is_binary(bytes);
+    let format = FileFormat {
+        encoding,
+        line_ending,
+    };
 
-    fn render(&mut self) -> io::Result<()> {
-        queue!(
-            self.stdout,
-            terminal::Clear(terminal::ClearType::All),
-            cursor::MoveTo(0, 0)
-        )?;
+    (SourceEditor::new(text), binary, format)
+}
 
-        let (lines, column, row) = self.source_editor.render();
+/// Writes to a temporary file first so that the original isn't lost if writing fails.
+///
+/// If `path` is a symlink, it's the file it points to that's written, so that the link stays.
+fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    write_atomically_with(path, |file| file.write_all(bytes))
+}
 
-        for line in lines {
-            let line = if line.len() < self.width {
-                line
-            } else {
-                &line[..self.width]
-            };
+/// Like [`write_atomically`], but with `write` writing to the temporary file, for when what's
+/// written is too big to have all of in memory at once.
+fn write_atomically_with(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
+    let path = &resolve_symlinks(path);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{name}.tmp"));
 
-            writeln!(self.stdout, "{}\r", line)?;
-        }
+    let result = write_and_replace(&temp, path, write);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
 
-        let file = match &self.file {
-            Some(file) => file.display().to_string(),
-            None => "[New File]".to_string(),
-        };
-        let status_bar = format!(" {file}{}", " ".repeat(self.width - file.len() - 1));
-        write!(
-            self.stdout,
-            "{}",
-            style::style(status_bar)
-                .bold()
-                .with(style::Color::DarkGrey)
-                .on(style::Color::Black)
-        )?;
+    result
+}
 
-        queue!(self.stdout, cursor::MoveTo(column as u16, row as u16))?;
+fn write_and_replace(
+    temp: &Path,
+    path: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut file = File::create(temp)?;
+    write(&mut file)?;
+    file.sync_all()?;
 
-        self.stdout.flush()?;
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(temp, metadata.permissions())?;
+    }
 
-        Ok(())
+    fs::rename(temp, path)
+}
+
+/// The file `path` ends up at after following any symlinks, even if it doesn't exist yet.
+fn resolve_symlinks(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    // links that point back at each other would go around forever
+    for _ in 0..MAX_SYMLINKS {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {}
+            _ => break,
+        }
+        match fs::read_link(&path) {
+            // relative targets are relative to the link's directory
+            Ok(target) => path = path.parent().unwrap_or(Path::new("")).join(target),
+            Err(_) => break,
+        }
     }
 
-    fn handle_event(&mut self) -> io::Result<()> {
-        match event::read()? {
-            event::Event::Key(key_event) => match key_event {
-                event::KeyEvent {
-                    code,
-                    modifiers: event::KeyModifiers::NONE,
-                } => match code {
-                    event::KeyCode::Backspace => self.source_editor.backspace(),
-                    event::KeyCode::Enter => self.source_editor.enter(),
-                    event::KeyCode::Left => self.source_editor.left(),
-                    event::KeyCode::Right => self.source_editor.right(),
-                    event::KeyCode::Up => self.source_editor.up(),
-                    event::KeyCode::Down => self.source_editor.down(),
-                    event::KeyCode::Home => self.source_editor.home(),
-                    event::KeyCode::End => self.source_editor.end(),
-                    event::KeyCode::PageUp => todo!(),
-                    event::KeyCode::PageDown => todo!(),
-                    event::KeyCode::Tab => todo!(),
-                    event::KeyCode::BackTab => todo!(),
-                    event::KeyCode::Delete => todo!(),
-                    event::KeyCode::Insert => todo!(),
-                    event::KeyCode::F(_) => todo!(),
-                    event::KeyCode::Char(c) => self.source_editor.keypress(c),
-                    event::KeyCode::Null => {}
-                    event::KeyCode::Esc => self.should_exit = true,
-                },
-                event::KeyEvent { .. } => {}
-            },
+    path
+}
 
-            event::Event::Mouse(mouse_event) => match mouse_event.kind {
-                event::MouseEventKind::Down(_) => {}
-                event::MouseEventKind::Up(_) => {}
-                event::MouseEventKind::Drag(_) => {}
-                event::MouseEventKind::Moved => {}
-                event::MouseEventKind::ScrollDown => self.source_editor.scroll_down(),
-                event::MouseEventKind::ScrollUp => self.source_editor.scroll_up(),
-            },
+/// How `path` is shown to the user: relative to the current directory if it's inside it,
+/// or as it is otherwise.
+fn display_path(path: &Path) -> String {
+    let relative = env::current_dir()
+        .ok()
+        .and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf));
+    match relative {
+        Some(relative) if !relative.as_os_str().is_empty() => relative.display().to_string(),
+        _ => path.display().to_string(),
+    }
+}
+
+/// Breaks `text` into lines no wider than `width`, between words where possible.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        let mut line_width = 0;
+
+        for word in paragraph.split(' ') {
+            let word_width = word.chars().count();
+            if line_width > 0 && line_width + 1 + word_width > width {
+                lines.push(mem::take(&mut line));
+                line_width = 0;
+            }
+            if line_width > 0 {
+                line.push(' ');
+                line_width += 1;
+            }
 
-            event::Event::Resize(width, height) => {
-                let width = width.into();
-                let height = height.into();
-                self.width = width;
-                self.height = height;
-                self.source_editor.resize(width, height - 1);
+            // words longer than a whole line have to be broken up anyway
+            for c in word.chars() {
+                if line_width == width {
+                    lines.push(mem::take(&mut line));
+                    line_width = 0;
+                }
+                line.push(c);
+                line_width += 1;
             }
         }
 
-        std::net::TcpStream::connect("127.0.0.1:9292")
-            .unwrap()
-            .write_all(format!("\n\n\n\n\n\n\n\n{self:#?}").as_bytes())
-            .unwrap();
+        lines.push(line);
+    }
 
-        Ok(())
+    lines
+}
+
+fn is_binary(bytes: &[u8]) -> bool {
+    // like git, only look at the start of the file
+    let bytes = &bytes[..bytes.len().min(8000)];
+
+    if bytes.contains(&0) {
+        return true;
     }
+
+    let is_text_control = |b: &u8| matches!(b, b'\t' | b'\n' | b'\r' | b'\x0c' | b'\x1b');
+    let control_bytes = bytes
+        .iter()
+        .filter(|b| b.is_ascii_control() && !is_text_control(b))
+        .count();
+
+    control_bytes * 10 > bytes.len()
+}
+
+#[derive(Debug)]
+struct Ui<W: Write> {
+    source_editor: SourceEditor,
+    file: Option<PathBuf>,
+    /// How `file` is shown in the status bar, worked out whenever it changes.
+    file_name: String,
+    binary: bool,
+    /// Whether every buffer is opened read-only, as it is with `--read-only`.
+    read_only_flag: bool,
+    /// How the file will be saved.
+    format: FileFormat,
+    /// What the file was like when it was last read or written,
+    /// to tell whether something else has changed it since.
+    disk_stamp: Option<DiskStamp>,
+    stdout: W,
+    width: usize,
+    height: usize,
+    /// How big the terminal really is. If that's too small to lay everything out in,
+    /// it's laid out as if it were big enough but not drawn.
+    terminal_size: (usize, usize),
+    split: Option<Split>,
+    theme: Theme,
+    clipboard: Clipboard,
+    /// Whether to also copy to the terminal's clipboard,
+    /// for when the editor is running somewhere else over SSH.
+    osc52: bool,
+    palette: Option<Palette>,
+    /// What saving would change about the file, shown over the whole window.
+    unsaved_changes: Option<UnsavedChanges>,
+    /// Commands to start language servers with, by file extension.
+    language_servers: HashMap<String, Vec<String>>,
+    /// How to write comments, by file extension, instead of how the highlighter does.
+    comments: HashMap<String, editor::Comments>,
+    /// Whether to auto-indent files, unless `auto_indent_by_extension` says otherwise.
+    auto_indent: bool,
+    auto_indent_by_extension: HashMap<String, bool>,
+    /// How long lines can be before the rest is marked, unless `line_length_limit_by_extension`
+    /// says otherwise.
+    line_length_limit: Option<usize>,
+    line_length_limit_by_extension: HashMap<String, usize>,
+    /// Whether closing the last file leaves an empty buffer open instead of quitting.
+    close_to_scratch: bool,
+    /// Whether reopening a file goes back to where the cursor was left in it.
+    restore_positions: bool,
+    /// Where the recent files, cursor positions and session are kept between runs.
+    state_dir: Option<PathBuf>,
+    /// What paths typed into the status bar are relative to, which is empty for the current
+    /// directory.
+    prompt_dir: PathBuf,
+    /// What filter commands are run with.
+    shell: PathBuf,
+    /// How many lines each notch of the mouse wheel scrolls by.
+    scroll_lines: usize,
+    scroll_acceleration: bool,
+    clock: clock::Clock,
+    /// How to tell that a key did nothing.
+    bell: config::Bell,
+    /// When the status bar started flashing, if it still is.
+    flashed_at: Option<Instant>,
+    /// When the mouse wheel last scrolled, which way, and by how many lines.
+    last_scroll: Option<(Instant, bool, usize)>,
+    /// Whether the mouse button went down on the scrollbar, so that dragging scrolls rather than
+    /// selecting even once the mouse has moved off it.
+    dragging_scrollbar: bool,
+    /// Started the first time completions are asked for.
+    language_server: Option<lsp::Client>,
+    /// The value of `SourceEditor::changes` when the language server last saw the buffer.
+    synced_changes: usize,
+    completion: Option<CompletionMenu>,
+    /// Documentation shown until the next key is pressed.
+    hover: Option<String>,
+    /// Where to go back to after jumping to a definition, most recent last.
+    jumps_back: Vec<Jump>,
+    jumps_forward: Vec<Jump>,
+    /// Where the cursor was in the file that was open before this one, to switch back to it.
+    alternate: Option<Jump>,
+    keymap: Keymap,
+    pending_keys: Vec<event::KeyEvent>,
+    pending_since: Instant,
+    show_key_hint: bool,
+    /// How many times to repeat the next command.
+    count: Option<usize>,
+    status_message: Option<StatusMessage>,
+    recording: Option<Vec<event::Event>>,
+    last_macro: Vec<event::Event>,
+    replaying: bool,
+    /// A question in the status bar waiting for a character to be typed as the answer.
+    char_prompt: Option<CharPrompt>,
+    /// Text being typed into the status bar, such as a path to save to.
+    text_prompt: Option<TextPrompt>,
+    should_exit: bool,
+}
+
+#[derive(Debug)]
+struct StatusMessage {
+    text: String,
+    error: bool,
+    shown_at: Instant,
 }
 
 #[derive(Debug)]
-struct SourceEditor {
-    buffer: Vec<String>,
+struct Split {
+    direction: SplitDirection,
+    /// The view shown in whichever pane doesn't have focus.
+    other: View,
+    focus_first: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SplitDirection {
+    /// One pane above the other.
+    Horizontal,
+    /// One pane beside the other.
+    Vertical,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x: usize,
+    y: usize,
     width: usize,
     height: usize,
+}
+
+/// When a file was last modified and how big it was, which changes when it's written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DiskStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl DiskStamp {
+    fn read(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+impl Rect {
+    /// Where `(x, y)` on the terminal is relative to the rectangle, if it's inside it.
+    fn relative(self, x: usize, y: usize) -> Option<(usize, usize)> {
+        let inside = (self.x..self.x + self.width).contains(&x)
+            && (self.y..self.y + self.height).contains(&y);
+        inside.then(|| (x - self.x, y - self.y))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Jump {
+    file: Option<PathBuf>,
     row: usize,
-    column: usize,
+    column: Option<usize>,
+    /// LSP gives columns in UTF-16, which can only be converted once the file is open.
+    utf16_column: Option<usize>,
+}
+
+#[derive(Debug)]
+struct CompletionMenu {
+    items: Vec<lsp::Completion>,
+    selected: usize,
+}
+
+#[derive(Debug)]
+struct UnsavedChanges {
+    lines: Vec<String>,
     scroll: usize,
 }
 
-impl SourceEditor {
-    fn new(buffer: String, width: usize, height: usize) -> Self {
+#[derive(Debug)]
+struct Palette {
+    query: String,
+    selected: usize,
+    items: Vec<PaletteItem>,
+}
+
+#[derive(Debug)]
+struct PaletteItem {
+    label: String,
+    /// Shown right-aligned after the label.
+    detail: String,
+    target: PaletteTarget,
+}
+
+#[derive(Debug, Clone)]
+enum PaletteTarget {
+    Action(Action),
+    File(PathBuf),
+    Directory(PathBuf),
+}
+
+impl Palette {
+    fn new(items: Vec<PaletteItem>) -> Self {
         Self {
-            buffer: buffer.split('\n').map(str::to_string).collect(),
-            width,
-            height,
-            row: 0,
-            column: 0,
-            scroll: 0,
+            query: String::new(),
+            selected: 0,
+            items,
         }
     }
 
-    fn render(&self) -> (Vec<&str>, usize, usize) {
-        let mut lines = vec!["~"; self.height];
-
-        for (idx, line) in self
-            .buffer
+    fn commands(keymap: &Keymap) -> Self {
+        let items = Action::ALL
             .iter()
-            .skip(self.scroll)
-            .take(self.height)
-            .enumerate()
-        {
-            let line = if line.len() < self.width {
-                line
-            } else {
-                &line[..self.width]
-            };
+            .map(|&action| PaletteItem {
+                label: action.name().to_string(),
+                detail: keymap
+                    .keys(action)
+                    .map(keymap::keys_name)
+                    .unwrap_or_default(),
+                target: PaletteTarget::Action(action),
+            })
+            .collect();
+
+        Self::new(items)
+    }
+
+    fn files(files: Vec<PathBuf>) -> Self {
+        let items = files
+            .into_iter()
+            .map(|file| PaletteItem {
+                label: file.display().to_string(),
+                detail: if file.exists() {
+                    String::new()
+                } else {
+                    "missing".to_string()
+                },
+                target: PaletteTarget::File(file),
+            })
+            .collect();
+
+        Self::new(items)
+    }
+
+    /// The entries of `dir`, directories first, after an entry for going up to its parent.
+    fn directory(dir: &Path) -> io::Result<Self> {
+        let dir = dir.canonicalize()?;
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let is_dir = entry.path().is_dir();
+            entries.push((!is_dir, entry.file_name().to_string_lossy().into_owned()));
+        }
+        entries.sort();
 
-            lines[idx] = line;
+        let mut items = Vec::new();
+        if let Some(parent) = dir.parent() {
+            items.push(PaletteItem {
+                label: "../".to_string(),
+                detail: dir.display().to_string(),
+                target: PaletteTarget::Directory(parent.to_path_buf()),
+            });
+        }
+        for (is_file, name) in entries {
+            let path = dir.join(&name);
+            items.push(if is_file {
+                PaletteItem {
+                    label: name,
+                    detail: String::new(),
+                    target: PaletteTarget::File(path),
+                }
+            } else {
+                PaletteItem {
+                    label: format!("{name}/"),
+                    detail: String::new(),
+                    target: PaletteTarget::Directory(path),
+                }
+            });
         }
 
-        (lines, self.column, self.row - self.scroll)
+        Ok(Self::new(items))
     }
 
-    fn resize(&mut self, width: usize, height: usize) {
-        self.width = width;
-        self.height = height;
-        self.scroll_to_show_cursor();
+    fn matches(&self) -> Vec<&PaletteItem> {
+        let mut matches: Vec<_> = self
+            .items
+            .iter()
+            .filter_map(|item| Some((fuzzy::score(&self.query, &item.label)?, item)))
+            .collect();
+
+        // the sort is stable, so equally good matches stay in the order they were given
+        matches.sort_by_key(|(score, _)| Reverse(*score));
+
+        matches.into_iter().map(|(_, item)| item).collect()
     }
+}
+
+impl<W: Write> Ui<W> {
+    fn new(
+        source_editor: SourceEditor,
+        file: Option<PathBuf>,
+        binary: bool,
+        format: FileFormat,
+        theme: Theme,
+        osc52: bool,
+        stdout: W,
+    ) -> Self {
+        let disk_stamp = file.as_deref().and_then(DiskStamp::read);
+        let file_name = file.as_deref().map(display_path).unwrap_or_default();
+        let mut ui = Self {
+            source_editor,
+            file,
+            file_name,
+            binary,
+            read_only_flag: false,
+            format,
+            disk_stamp,
+            stdout,
+            width: MIN_WIDTH,
+            height: MIN_HEIGHT,
+            terminal_size: (MIN_WIDTH, MIN_HEIGHT),
+            split: None,
+            theme,
+            clipboard: Clipboard::default(),
+            osc52,
+            palette: None,
+            unsaved_changes: None,
+            language_servers: HashMap::new(),
+            comments: HashMap::new(),
+            auto_indent: true,
+            auto_indent_by_extension: HashMap::new(),
+            line_length_limit: None,
+            line_length_limit_by_extension: HashMap::new(),
+            close_to_scratch: false,
+            restore_positions: false,
+            state_dir: config::state_dir(),
+            prompt_dir: PathBuf::new(),
+            shell: PathBuf::from("sh"),
+            scroll_lines: 3,
+            scroll_acceleration: false,
+            last_scroll: None,
+            dragging_scrollbar: false,
+            clock: clock::Clock::new(String::new(), false),
+            bell: config::Bell::Off,
+            flashed_at: None,
+            language_server: None,
+            synced_changes: 0,
+            completion: None,
+            hover: None,
+            jumps_back: Vec::new(),
+            jumps_forward: Vec::new(),
+            alternate: None,
+            keymap: Keymap::default(),
+            pending_keys: Vec::new(),
+            pending_since: Instant::now(),
+            show_key_hint: false,
+            count: None,
+            status_message: None,
+            recording: None,
+            last_macro: Vec::new(),
+            replaying: false,
+            char_prompt: None,
+            text_prompt: None,
+            should_exit: false,
+        };
+        ui.resize_editor();
 
-    fn keypress(&mut self, c: char) {
-        self.buffer[self.row].insert(self.column, c);
-        self.column += 1;
+        ui
     }
 
-    fn backspace(&mut self) {
-        if self.column == 0 {
-            if self.row == 0 {
-                return;
+    fn run(mut self) -> io::Result<()> {
+        terminal::enable_raw_mode().map_err(|e| {
+            io::Error::new(e.kind(), format!("couldn't take over the terminal: {e}"))
+        })?;
+        queue!(
+            self.stdout,
+            terminal::EnterAlternateScreen,
+            event::EnableMouseCapture,
+            event::EnableBracketedPaste
+        )?;
+
+        while !self.should_exit {
+            // only diff once pending input has been handled,
+            // so that typing quickly or pasting doesn't wait on it
+            if !event::poll(Duration::ZERO)? {
+                self.source_editor.update_diff();
+                self.sync_language_server();
+                self.receive_diagnostics();
             }
+            self.render()?;
 
-            let row = self.buffer.remove(self.row);
-            self.row -= 1;
-            let len = self.buffer[self.row].len();
-            self.buffer[self.row].push_str(&row);
-            self.column = len;
-            return;
+            if let Some(timeout) = self.next_timeout() {
+                if !event::poll(timeout)? {
+                    self.handle_timeouts();
+                    continue;
+                }
+            }
+
+            self.handle_pending_events()?;
         }
+        self.remember_position();
 
-        self.column -= 1;
-        self.buffer[self.row].remove(self.column);
-    }
+        terminal::disable_raw_mode()?;
+        queue!(
+            self.stdout,
+            event::DisableBracketedPaste,
+            event::DisableMouseCapture,
+            terminal::LeaveAlternateScreen
+        )?;
 
-    fn enter(&mut self) {
-        let rest = self.buffer[self.row].split_off(self.column);
-        self.row += 1;
-        self.buffer.insert(self.row, rest);
-        self.column = 0;
-        self.scroll_to_show_cursor();
+        Ok(())
     }
 
-    fn left(&mut self) {
-        if self.column != 0 {
-            self.column -= 1;
+    /// Handles everything that's come in since the last render, waiting for at least one event.
+    ///
+    /// Dragging the edge of a window can send a flood of sizes, so a resize is only applied
+    /// once there's something other than another resize after it.
+    fn handle_pending_events(&mut self) -> io::Result<()> {
+        let mut resize = None;
+        loop {
+            match event::read()? {
+                event @ event::Event::Resize(..) => resize = Some(event),
+                event => {
+                    if let Some(resize) = resize.take() {
+                        self.handle_event(resize)?;
+                    }
+                    self.handle_event(event)?;
+                }
+            }
+
+            if self.should_exit || !event::poll(Duration::ZERO)? {
+                break;
+            }
         }
-    }
-    fn right(&mut self) {
-        if self.column < self.buffer[self.row].len() {
-            self.column += 1;
+
+        match resize {
+            Some(resize) => self.handle_event(resize),
+            None => Ok(()),
         }
     }
-    fn up(&mut self) {
-        if self.row != 0 {
-            self.row -= 1;
+
+    /// How long until something on screen needs to change without any input.
+    fn next_timeout(&self) -> Option<Duration> {
+        let key_hint = (!self.pending_keys.is_empty() && !self.show_key_hint)
+            .then(|| KEY_HINT_DELAY.saturating_sub(self.pending_since.elapsed()));
+        let message = self
+            .status_message
+            .as_ref()
+            .map(|message| MESSAGE_DURATION.saturating_sub(message.shown_at.elapsed()));
+
+        let diagnostics = self
+            .language_server
+            .is_some()
+            .then_some(DIAGNOSTICS_INTERVAL);
+
+        let clock = self.clock.until_next_minute();
+        let flash = self
+            .flashed_at
+            .map(|flashed_at| FLASH_DURATION.saturating_sub(flashed_at.elapsed()));
+
+        [key_hint, message, diagnostics, clock, flash]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    fn handle_timeouts(&mut self) {
+        if !self.pending_keys.is_empty() && self.pending_since.elapsed() >= KEY_HINT_DELAY {
+            self.show_key_hint = true;
+        }
+
+        let expired = self
+            .status_message
+            .as_ref()
+            .is_some_and(|message| message.shown_at.elapsed() >= MESSAGE_DURATION);
+        if expired {
+            self.status_message = None;
+        }
+
+        if self
+            .flashed_at
+            .is_some_and(|flashed_at| flashed_at.elapsed() >= FLASH_DURATION)
+        {
+            self.flashed_at = None;
         }
-        self.clamp_column();
-        self.scroll_to_show_cursor();
+
+        self.clock.update();
     }
-    fn down(&mut self) {
-        if self.row < self.buffer.len() - 1 {
-            self.row += 1;
+
+    /// Lets the user know that a key did nothing, if they want to know.
+    fn ring_bell(&mut self) {
+        match self.bell {
+            config::Bell::Off => {}
+            config::Bell::Visual => self.flashed_at = Some(Instant::now()),
+            config::Bell::Audible => {
+                // it's written out along with everything else when rendering
+                let _ = write!(self.stdout, "\x07");
+            }
         }
-        self.clamp_column();
-        self.scroll_to_show_cursor();
     }
-    fn home(&mut self) {
-        self.column = 0;
+
+    /// Shows a message in the status bar for a little while.
+    fn set_message(&mut self, text: impl Into<String>) {
+        self.status_message = Some(StatusMessage {
+            text: text.into(),
+            error: false,
+            shown_at: Instant::now(),
+        });
     }
-    fn end(&mut self) {
-        self.column = self.buffer[self.row].len();
+
+    fn set_error(&mut self, text: impl Into<String>) {
+        self.status_message = Some(StatusMessage {
+            text: text.into(),
+            error: true,
+            shown_at: Instant::now(),
+        });
     }
 
-    fn scroll_up(&mut self) {
-        if self.scroll == 0 {
-            return;
-        }
+    /// Says where the buffer is in its history after moving through it in time, since that
+    /// can go through changes on other branches that aren't otherwise shown anywhere.
+    fn show_history_position(&mut self) {
+        let (state, states) = self.source_editor.history_position();
+        self.set_message(format!("change {state} of {states}"));
+    }
 
-        self.scroll -= 1;
+    fn render(&mut self) -> io::Result<()> {
+        // whatever the commands since the last render did, what's in view has to be paged in
+        self.source_editor.settle_pages();
+        queue!(self.stdout, terminal::Clear(terminal::ClearType::All))?;
 
-        if self.row > self.scroll + self.height - 1 {
-            self.row = self.scroll + self.height - 1;
-            self.clamp_column();
+        // drawing everything would spill off the edges and scroll the terminal
+        let (width, height) = self.terminal_size;
+        if width < MIN_WIDTH || height < MIN_HEIGHT {
+            let message: String = "window too small".chars().take(width).collect();
+            queue!(self.stdout, cursor::MoveTo(0, 0))?;
+            write!(self.stdout, "{message}")?;
+            return self.stdout.flush();
         }
-    }
 
-    fn scroll_down(&mut self) {
-        // half height overscroll
-        if self.scroll >= self.buffer.len() - self.height / 2 {
-            return;
-        }
+        let (focused, other) = self.pane_rects();
 
-        self.scroll += 1;
+        if let (Some(split), Some(other)) = (&mut self.split, other) {
+            let (lines, thumb) = self.source_editor.with_view(
+                &mut split.other,
+                other.width,
+                other.height,
+                |source_editor| {
+                    source_editor.update_highlighting();
+                    (source_editor.render().0, source_editor.scrollbar_thumb())
+                },
+            );
+            draw_lines(&mut self.stdout, &lines, other, &self.theme)?;
+            draw_scrollbar(&mut self.stdout, thumb, other, &self.theme)?;
 
-        if self.row < self.scroll {
-            self.row = self.scroll;
-            self.clamp_column();
+            // the divider sits just below or to the right of the first pane
+            if split.direction == SplitDirection::Horizontal {
+                let divider = focused.y.max(other.y) - 1;
+                queue!(self.stdout, cursor::MoveTo(0, divider as u16))?;
+                write!(
+                    self.stdout,
+                    "{}",
+                    style::style("─".repeat(self.width)).with(self.theme.divider)
+                )?;
+            } else {
+                let divider = focused.x.max(other.x) - 1;
+                for row in 0..focused.height {
+                    queue!(self.stdout, cursor::MoveTo(divider as u16, row as u16))?;
+                    write!(
+                        self.stdout,
+                        "{}",
+                        style::style('│').with(self.theme.divider)
+                    )?;
+                }
+            }
         }
-    }
 
-    fn scroll_to_show_cursor(&mut self) {
-        let top_line = self.scroll;
-        let bottom_line = self.scroll + self.height;
+        self.source_editor.update_highlighting();
+        let (lines, column, row) = self.source_editor.render();
+        draw_lines(&mut self.stdout, &lines, focused, &self.theme)?;
+        let thumb = self.source_editor.scrollbar_thumb();
+        draw_scrollbar(&mut self.stdout, thumb, focused, &self.theme)?;
+        let (column, row) = (column + focused.x, row + focused.y);
 
-        if self.row < top_line {
-            self.scroll = self.row;
-        } else if self.row >= bottom_line {
-            self.scroll = self.row - self.height + 1;
+        let mut file = match &self.file {
+            Some(_) => self.file_name.clone(),
+            None => "[New File]".to_string(),
+        };
+        let mut flags = String::new();
+        if self.source_editor.modified {
+            flags.push_str(" [modified]");
         }
-    }
-
-    fn clamp_column(&mut self) {
-        let len = self.buffer[self.row].len();
-        if self.column > len {
-            self.column = len;
+        if self.binary {
+            flags.push_str(" [binary]");
+        }
+        if self.source_editor.read_only {
+            flags.push_str(" [readonly]");
+        }
+        if !self.binary && self.source_editor.missing_final_newline() {
+            flags.push_str(" [no final newline]");
+        }
+        if let Some(count) = self.count {
+            flags.push_str(&format!(" [count {count}]"));
+        }
+        if self.source_editor.settings.overwrite {
+            flags.push_str(" [overwrite]");
+        }
+        if self.recording.is_some() {
+            flags.push_str(" [recording]");
+        }
+        if !self.source_editor.cursors.is_empty() {
+            let cursors = self.source_editor.cursors.len() + 1;
+            flags.push_str(&format!(" [{cursors} cursors]"));
+        }
+        match self.source_editor.lines_over_limit() {
+            0 => {}
+            1 => flags.push_str(" [1 long line]"),
+            lines => flags.push_str(&format!(" [{lines} long lines]")),
+        }
+        if let Some((current, total)) = self.source_editor.search_position() {
+            // there could be more matches in what's still to be read or is paged out
+            let more = if self.source_editor.loader.is_some() || self.source_editor.is_paged() {
+                "+"
+            } else {
+                ""
+            };
+            flags.push_str(&format!(" [{current}/{total}{more}]"));
+        }
+        if self.source_editor.h_scroll() > 0 {
+            // the first column showing, so it's clear the rest of the line is off to the left
+            let column = self.source_editor.h_scroll() + 1;
+            flags.push_str(&format!(" [col {column}+]"));
         }
+        if let Some(loader) = &self.source_editor.loader {
+            flags.push_str(&format!(" [loaded {}%]", loader.offset * 100 / loader.len));
+        }
+        if let Some(position) = self.source_editor.page_position() {
+            flags.push_str(&format!(" [paged {position}%]"));
+        }
+        let diagnostic = self.source_editor.diagnostic_at_cursor();
+        let (message, color) = match (&self.status_message, diagnostic) {
+            _ if self.text_prompt.is_some() => {
+                let prompt = self.text_prompt.as_ref().unwrap();
+                (Some(prompt.line()), self.theme.message)
+            }
+            _ if self.char_prompt.is_some() => {
+                let question = self.char_prompt.as_ref().unwrap().question();
+                (Some(format!(" {question}")), self.theme.message)
+            }
+            (Some(message), _) if message.error => {
+                (Some(format!(" {}", message.text)), self.theme.error)
+            }
+            (Some(message), _) => (Some(format!(" {}", message.text)), self.theme.message),
+            (None, Some(diagnostic)) => {
+                // only the first line fits, and that's usually the gist of it
+                let text = diagnostic.message.lines().next().unwrap_or_default();
+                (
+                    Some(format!(" {text}")),
+                    self.theme.diagnostic(diagnostic.severity),
+                )
+            }
+            (None, None) => (None, self.theme.status_bar),
+        };
+        // the language and what the file will be saved as go on the right
+        let mut segments = [
+            self.source_editor
+                .highlighter
+                .language()
+                .map(|language| language.name.to_string()),
+            Some(self.format.encoding.name().to_string()),
+            Some(self.format.line_ending.name().to_string()),
+            self.clock.text().map(str::to_string),
+        ];
+        let left = |file: &str| message.clone().unwrap_or_else(|| format!(" {file}{flags}"));
+        let right = |segments: &[Option<String>]| {
+            let segments: Vec<&str> = segments.iter().flatten().map(String::as_str).collect();
+            if segments.is_empty() {
+                String::new()
+            } else {
+                // with a space to keep it apart from what's on the left
+                format!(" {} ", segments.join("  "))
+            }
+        };
+        let fits =
+            |left: &str, right: &str| left.chars().count() + right.chars().count() <= self.width;
+
+        // when there isn't room for everything, the least important things go first,
+        // and the file name is kept even if the rest of its path isn't
+        let short_file = self
+            .file
+            .as_deref()
+            .and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().to_string());
+        let mut dropped = 0;
+        while !fits(&left(&file), &right(&segments)) {
+            match dropped {
+                0 => segments[3] = None,
+                1 => segments[1] = None,
+                2 => file = short_file.clone().unwrap_or(file),
+                3 => segments[2] = None,
+                4 => segments[0] = None,
+                _ => break,
+            }
+            dropped += 1;
+        }
+
+        let right = right(&segments);
+        let width = self.width - right.chars().count();
+        let left: String = left(&file).chars().take(width).collect();
+        let status_bar = format!("{left:width$}{right}");
+        let mut status_bar = style::style(status_bar)
+            .bold()
+            .with(color)
+            .on(self.theme.status_bar_background);
+        if self.flashed_at.is_some() {
+            status_bar = status_bar.reverse();
+        }
+        queue!(self.stdout, cursor::MoveTo(0, self.height as u16 - 1))?;
+        write!(self.stdout, "{status_bar}")?;
+
+        if self.show_key_hint {
+            self.render_key_hint()?;
+        }
+
+        // the cursor goes where the prompt is being typed into instead
+        let (column, row) = match &self.text_prompt {
+            Some(prompt) => (
+                prompt.line().chars().count().min(self.width - 1),
+                self.height - 1,
+            ),
+            None => (column, row),
+        };
+        queue!(self.stdout, cursor::MoveTo(column as u16, row as u16))?;
+
+        if let Some(hover) = self.hover.take() {
+            self.render_hover(&hover, row)?;
+            queue!(self.stdout, cursor::MoveTo(column as u16, row as u16))?;
+            self.hover = Some(hover);
+        }
+
+        if let Some(completion) = self.completion.take() {
+            self.render_completion(&completion, column, row)?;
+            queue!(self.stdout, cursor::MoveTo(column as u16, row as u16))?;
+            self.completion = Some(completion);
+        }
+
+        if let Some(palette) = self.palette.take() {
+            self.render_palette(&palette)?;
+            self.palette = Some(palette);
+        }
+
+        if let Some(changes) = self.unsaved_changes.take() {
+            self.render_unsaved_changes(&changes)?;
+            self.unsaved_changes = Some(changes);
+        }
+
+        self.stdout.flush()?;
+
+        Ok(())
+    }
+
+    fn render_palette(&mut self, palette: &Palette) -> io::Result<()> {
+        let width = self.width;
+        let theme = &self.theme;
+        let stdout = &mut self.stdout;
+        let line = |text: String| format!(" {text:width$.width$}", width = width - 1);
+
+        queue!(stdout, cursor::MoveTo(0, 0))?;
+        write!(
+            stdout,
+            "{}",
+            style::style(line(format!("> {}", palette.query)))
+                .with(theme.popup)
+                .on(theme.popup_background)
+        )?;
+
+        for (idx, item) in palette
+            .matches()
+            .into_iter()
+            .take(self.height - 2)
+            .enumerate()
+        {
+            let PaletteItem { label, detail, .. } = item;
+            let padding =
+                (width - 2).saturating_sub(label.chars().count() + detail.chars().count() + 1);
+            let entry = line(format!("{label}{}{detail}", " ".repeat(padding)));
+
+            queue!(stdout, cursor::MoveTo(0, idx as u16 + 1))?;
+            if idx == palette.selected {
+                write!(stdout, "{}", style::style(entry).reverse())?;
+            } else {
+                write!(
+                    stdout,
+                    "{}",
+                    style::style(entry)
+                        .with(theme.popup_dim)
+                        .on(theme.popup_background)
+                )?;
+            }
+        }
+
+        // the query can be too long to show all of, and the cursor still has to be on screen
+        let column = (palette.query.chars().count() + 3).min(width - 1);
+        queue!(stdout, cursor::MoveTo(column as u16, 0))?;
+
+        Ok(())
+    }
+
+    /// Draws the menu just below the cursor, or above it if there isn't room.
+    fn render_completion(
+        &mut self,
+        completion: &CompletionMenu,
+        column: usize,
+        row: usize,
+    ) -> io::Result<()> {
+        let rows_below = (self.height - 1).saturating_sub(row + 1);
+        let len = completion.items.len().min(rows_below.max(row)).min(10);
+        let top = if len <= rows_below {
+            row + 1
+        } else {
+            row - len
+        };
+        // scroll the list so that the selected item is always shown
+        let first = (completion.selected + 1).saturating_sub(len);
+
+        let widest = completion
+            .items
+            .iter()
+            .map(|item| item.label.chars().count() + item.detail.chars().count() + 3)
+            .max()
+            .unwrap_or(0);
+        let width = widest.min(self.width);
+        let left = column.min(self.width - width);
+
+        for (idx, item) in completion.items.iter().enumerate().skip(first).take(len) {
+            let padding = (width - 2)
+                .saturating_sub(item.label.chars().count() + item.detail.chars().count());
+            let entry = format!(" {}{}{}", item.label, " ".repeat(padding), item.detail);
+            let entry = format!("{entry:width$.width$}");
+
+            queue!(
+                self.stdout,
+                cursor::MoveTo(left as u16, (top + idx - first) as u16)
+            )?;
+            if idx == completion.selected {
+                write!(self.stdout, "{}", style::style(entry).reverse())?;
+            } else {
+                write!(
+                    self.stdout,
+                    "{}",
+                    style::style(entry)
+                        .with(self.theme.popup)
+                        .on(self.theme.popup_background)
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Draws the documentation across the window below the cursor's line, or above it
+    /// if there's more room there.
+    fn render_hover(&mut self, hover: &str, row: usize) -> io::Result<()> {
+        let width = self.width;
+        let lines = wrap(hover, width.saturating_sub(2).max(1));
+        let rows_below = (self.height - 1).saturating_sub(row + 1);
+        let len = lines.len().min(rows_below.max(row));
+        let top = if len <= rows_below {
+            row + 1
+        } else {
+            row - len
+        };
+
+        for (idx, line) in lines.iter().take(len).enumerate() {
+            let entry = format!(" {line:width$.width$}", width = width - 1);
+            queue!(self.stdout, cursor::MoveTo(0, (top + idx) as u16))?;
+            write!(
+                self.stdout,
+                "{}",
+                style::style(entry)
+                    .with(self.theme.popup)
+                    .on(self.theme.popup_background)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Draws the diff over everything but the status bar, colored like the diff markers.
+    fn render_unsaved_changes(&mut self, changes: &UnsavedChanges) -> io::Result<()> {
+        let width = self.width;
+        for row in 0..self.height - 1 {
+            let line = changes.lines.get(changes.scroll + row);
+            let color = match line.and_then(|line| line.chars().next()) {
+                Some('+') => self.theme.diff_added,
+                Some('-') => self.theme.diff_removed,
+                Some(' ') => self.theme.popup,
+                _ => self.theme.popup_dim,
+            };
+            let line = self
+                .source_editor
+                .displayed(line.map_or("", String::as_str));
+            let line = format!(" {line:width$.width$}", width = width - 1);
+            queue!(self.stdout, cursor::MoveTo(0, row as u16))?;
+            write!(
+                self.stdout,
+                "{}",
+                style::style(line)
+                    .with(color)
+                    .on(self.theme.popup_background)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn render_key_hint(&mut self) -> io::Result<()> {
+        let continuations = self.keymap.continuations(&self.pending_keys);
+        let top = (self.height - 1).saturating_sub(continuations.len() + 1);
+        let key_width = continuations
+            .iter()
+            .map(|(keys, _)| keymap::keys_name(keys).len())
+            .max()
+            .unwrap_or(0);
+
+        let header = format!(" {}-", keymap::keys_name(&self.pending_keys));
+        queue!(self.stdout, cursor::MoveTo(0, top as u16))?;
+        write!(
+            self.stdout,
+            "{}",
+            style::style(format!("{header:width$}", width = self.width))
+                .bold()
+                .with(self.theme.popup)
+                .on(self.theme.popup_background)
+        )?;
+
+        for (idx, (keys, action)) in continuations.into_iter().enumerate() {
+            let keys = keymap::keys_name(keys);
+            let entry = format!("   {keys:key_width$}  {}", action.name());
+
+            queue!(self.stdout, cursor::MoveTo(0, (top + idx + 1) as u16))?;
+            write!(
+                self.stdout,
+                "{}",
+                style::style(format!("{entry:width$}", width = self.width))
+                    .with(self.theme.popup_dim)
+                    .on(self.theme.popup_background)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: event::Event) -> io::Result<()> {
+        let event = match event {
+            event::Event::Key(key_event) => event::Event::Key(keymap::normalize(key_event)),
+            event => event,
+        };
+
+        if let Some(recording) = &mut self.recording {
+            if !self.replaying && !matches!(event, event::Event::Resize(..)) {
+                recording.push(event.clone());
+            }
+        }
+
+        // documentation goes away with the next key, which still does what it usually would
+        if let event::Event::Key(_) = event {
+            self.hover = None;
+        }
+
+        // prompts and the like don't get to see Esc as a key, since it always leaves them
+        if let event::Event::Key(event::KeyEvent {
+            code: event::KeyCode::Esc,
+            ..
+        }) = event
+        {
+            if self.cancel() {
+                return Ok(());
+            }
+        }
+
+        if let (Some(prompt), event::Event::Key(key_event)) = (&mut self.text_prompt, &event) {
+            match key_event.code {
+                event::KeyCode::Enter => {
+                    let prompt = self.text_prompt.take().unwrap();
+                    self.submit(prompt);
+                }
+                event::KeyCode::Backspace => {
+                    prompt.text.pop();
+                }
+                event::KeyCode::Char(c) => prompt.text.push(c),
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        if let (Some(prompt), event::Event::Key(key_event)) = (self.char_prompt.clone(), &event) {
+            self.char_prompt = None;
+            // anything other than a character cancels it
+            if let event::KeyEvent {
+                code: event::KeyCode::Char(c),
+                modifiers: event::KeyModifiers::NONE | event::KeyModifiers::SHIFT,
+                ..
+            } = key_event
+            {
+                self.answer(prompt, *c);
+            }
+            return Ok(());
+        }
+
+        if let (Some(_), event::Event::Key(key_event)) = (&self.unsaved_changes, &event) {
+            self.handle_unsaved_changes_key(*key_event);
+            return Ok(());
+        }
+
+        if let (Some(_), event::Event::Key(key_event)) = (&self.completion, &event) {
+            if self.handle_completion_key(*key_event) {
+                return Ok(());
+            }
+        }
+
+        match event {
+            event::Event::Key(key_event) if self.palette.is_some() => {
+                self.handle_palette_key(key_event)?
+            }
+
+            event::Event::Key(event::KeyEvent {
+                code: event::KeyCode::Char(digit @ '0'..='9'),
+                modifiers: event::KeyModifiers::ALT,
+                ..
+            }) if self.pending_keys.is_empty() => {
+                let digit = digit.to_digit(10).unwrap() as usize;
+                let count = self.count.unwrap_or(0);
+                self.count = Some(
+                    count
+                        .saturating_mul(10)
+                        .saturating_add(digit)
+                        .min(MAX_COUNT),
+                );
+            }
+
+            event::Event::Key(key_event) => {
+                self.pending_keys.push(key_event);
+
+                match self.keymap.lookup(&self.pending_keys) {
+                    keymap::Lookup::Action(action) => {
+                        self.clear_pending_keys();
+                        let count = self.count.take().unwrap_or(1);
+
+                        if action == Action::ReplayMacro {
+                            self.replay_macro(count)?;
+                        } else if action == Action::Increment {
+                            // the count is added in one go, rather than one at a time
+                            self.add_to_number(count as i128);
+                        } else if action == Action::Decrement {
+                            self.add_to_number(-(count as i128));
+                        } else if action.is_movement() {
+                            let before = self.source_editor.view();
+                            for _ in 0..count {
+                                self.execute(action)?;
+                                // so that moving a long way isn't stopped by the buffer's end
+                                self.source_editor.settle_pages();
+                            }
+                            if self.source_editor.view() == before {
+                                self.ring_bell();
+                            }
+                        } else if action.is_repeatable() {
+                            for _ in 0..count {
+                                self.execute(action)?;
+                            }
+                        } else {
+                            self.execute(action)?;
+                        }
+                    }
+                    keymap::Lookup::Prefix => {
+                        if self.pending_keys.len() == 1 {
+                            self.pending_since = Instant::now();
+                        }
+                    }
+                    keymap::Lookup::Unbound => {
+                        // unbound keys after a prefix just cancel it
+                        let keys = self.clear_pending_keys();
+                        let count = self.count.take().unwrap_or(1);
+                        if let [event::KeyEvent {
+                            code: event::KeyCode::Char(c),
+                            modifiers: event::KeyModifiers::NONE | event::KeyModifiers::SHIFT,
+                            ..
+                        }] = keys[..]
+                        {
+                            for _ in 0..count {
+                                self.source_editor
+                                    .at_each_cursor(|source_editor| source_editor.keypress(c));
+                            }
+                        } else {
+                            self.ring_bell();
+                        }
+                    }
+                }
+            }
+
+            event::Event::Paste(text) => {
+                // terminals send line breaks in pastes as carriage returns
+                let text = text.replace("\r\n", "\n").replace('\r', "\n");
+                match (&mut self.palette, &mut self.text_prompt) {
+                    (Some(palette), _) => palette.query.extend(text.lines().next()),
+                    (None, Some(prompt)) => prompt.text.extend(text.lines().next()),
+                    (None, None) => self.source_editor.insert_text(&text),
+                }
+            }
+
+            event::Event::Mouse(mouse_event) => match mouse_event.kind {
+                event::MouseEventKind::Down(event::MouseButton::Left) => {
+                    self.click(mouse_event.column.into(), mouse_event.row.into(), false);
+                }
+                event::MouseEventKind::Up(event::MouseButton::Left) => {
+                    self.dragging_scrollbar = false;
+                }
+                event::MouseEventKind::Drag(event::MouseButton::Left) => {
+                    self.click(mouse_event.column.into(), mouse_event.row.into(), true);
+                }
+                event::MouseEventKind::Down(_) => {}
+                event::MouseEventKind::Up(_) => {}
+                event::MouseEventKind::Drag(_) => {}
+                event::MouseEventKind::Moved => {}
+                event::MouseEventKind::ScrollDown => self.scroll_wheel(true),
+                event::MouseEventKind::ScrollUp => self.scroll_wheel(false),
+            },
+
+            event::Event::Resize(width, height) => self.resize(width.into(), height.into()),
+
+            event::Event::FocusGained | event::Event::FocusLost => {}
+        }
+
+        Ok(())
+    }
+
+    /// The areas of the screen taken up by the focused pane and,
+    /// if the window is split, the other pane.
+    fn pane_rects(&self) -> (Rect, Option<Rect>) {
+        // leave room for the status bar
+        let height = self.height - 1;
+        let whole = Rect {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height,
+        };
+
+        let split = match &self.split {
+            Some(split) => split,
+            None => return (whole, None),
+        };
+
+        let (first, second) = match split.direction {
+            SplitDirection::Horizontal => {
+                let top_height = (height - 1) / 2;
+                let top = Rect {
+                    height: top_height,
+                    ..whole
+                };
+                let bottom = Rect {
+                    y: top_height + 1,
+                    height: height - top_height - 1,
+                    ..whole
+                };
+                (top, bottom)
+            }
+            SplitDirection::Vertical => {
+                let left_width = (self.width - 1) / 2;
+                let left = Rect {
+                    width: left_width,
+                    ..whole
+                };
+                let right = Rect {
+                    x: left_width + 1,
+                    width: self.width - left_width - 1,
+                    ..whole
+                };
+                (left, right)
+            }
+        };
+
+        if split.focus_first {
+            (first, Some(second))
+        } else {
+            (second, Some(first))
+        }
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        self.terminal_size = (width, height);
+        self.width = width.max(MIN_WIDTH);
+        self.height = height.max(MIN_HEIGHT);
+        self.resize_editor();
+    }
+
+    fn resize_editor(&mut self) {
+        let (focused, _) = self.pane_rects();
+        self.source_editor.resize(focused.width, focused.height);
+    }
+
+    fn complete(&mut self) {
+        if self.source_editor.read_only {
+            return;
+        }
+        if let Err(e) = self.prepare_language_server() {
+            return self.set_error(e);
+        }
+
+        let (row, column) = (self.source_editor.row, self.source_editor.column);
+        let line = self.source_editor.line(row).to_string();
+        let client = self.language_server.as_mut().unwrap();
+        match client.completions(row, lsp::utf16_column(&line, column)) {
+            Ok(items) if items.is_empty() => self.set_message("no completions"),
+            Ok(items) => self.completion = Some(CompletionMenu { items, selected: 0 }),
+            Err(e) => {
+                // it'll be restarted next time, in case that fixes it
+                self.language_server = None;
+                self.set_error(e);
+            }
+        }
+    }
+
+    fn spelling_suggestions(&mut self) {
+        if self.source_editor.read_only {
+            return;
+        }
+        let Some(dictionary) = &self.source_editor.settings.dictionary else {
+            return self.set_error("spell checking is turned off");
+        };
+        let Some(columns) = self.source_editor.spelled_word_at_cursor() else {
+            return self.set_message("there's no word here to check");
+        };
+
+        let line = self.source_editor.line(self.source_editor.row).to_string();
+        let word: String = line
+            .chars()
+            .skip(columns.start)
+            .take(columns.len())
+            .collect();
+        if dictionary.contains(&word) {
+            return self.set_message(format!("{word} is spelled correctly"));
+        }
+
+        let range = Some((
+            lsp::utf16_column(&line, columns.start),
+            lsp::utf16_column(&line, columns.end),
+        ));
+        let items: Vec<_> = dictionary
+            .suggestions(&word)
+            .into_iter()
+            .map(|suggestion| lsp::Completion {
+                label: suggestion.clone(),
+                detail: String::new(),
+                text: suggestion,
+                range,
+            })
+            .collect();
+        if items.is_empty() {
+            self.set_message(format!("no suggestions for {word}"));
+        } else {
+            self.completion = Some(CompletionMenu { items, selected: 0 });
+        }
+    }
+
+    fn add_to_dictionary(&mut self) {
+        let source_editor = &mut self.source_editor;
+        let Some(columns) = source_editor.spelled_word_at_cursor() else {
+            return self.set_message("there's no word here to add");
+        };
+        let line = source_editor.line(source_editor.row).to_string();
+        let word: String = line
+            .chars()
+            .skip(columns.start)
+            .take(columns.len())
+            .collect();
+        let Some(dictionary) = &mut source_editor.settings.dictionary else {
+            return self.set_error("spell checking is turned off");
+        };
+
+        match dictionary.add(&word) {
+            Ok(()) => self.set_message(format!("added {word} to your dictionary")),
+            Err(e) => self.set_error(format!("couldn't add {word} to your dictionary: {e}")),
+        }
+    }
+
+    fn go_to_definition(&mut self) {
+        if let Err(e) = self.prepare_language_server() {
+            return self.set_error(e);
+        }
+
+        let (row, column) = (self.source_editor.row, self.source_editor.column);
+        let line = self.source_editor.line(row).to_string();
+        let client = self.language_server.as_mut().unwrap();
+        match client.definition(row, lsp::utf16_column(&line, column)) {
+            Ok(Some(location)) => {
+                let from = self.current_jump();
+                let to = Jump {
+                    file: Some(location.path),
+                    row: location.row,
+                    column: None,
+                    utf16_column: Some(location.column),
+                };
+                if self.jump(to) {
+                    self.jumps_back.push(from);
+                    self.jumps_forward.clear();
+                }
+            }
+            Ok(None) => self.set_message("no definition found"),
+            Err(e) => {
+                self.language_server = None;
+                self.set_error(e);
+            }
+        }
+    }
+
+    fn submit(&mut self, prompt: TextPrompt) {
+        match prompt.kind {
+            TextPromptKind::SaveAs { quit } => {
+                self.save_as(self.prompt_dir.join(prompt.text));
+                if quit && !self.source_editor.modified {
+                    self.should_exit = true;
+                }
+            }
+            TextPromptKind::Find { forward } => {
+                // like in vim, searching for nothing searches for the last thing again
+                let query = match &self.source_editor.last_search {
+                    Some(search) if prompt.text.is_empty() => search.query.clone(),
+                    _ => prompt.text,
+                };
+                self.search(Search { query, forward });
+            }
+            TextPromptKind::Language => self.set_language(prompt.text.trim()),
+            TextPromptKind::Filter { range } => self.filter(range, &prompt.text),
+        }
+    }
+
+    fn filter(&mut self, range: Range<usize>, command: &str) {
+        if command.trim().is_empty() {
+            return self.set_message("not filtered");
+        }
+        if self.source_editor.read_only {
+            return self.set_error("the buffer is read-only");
+        }
+
+        let input = self.source_editor.file_text(range.clone());
+        match shell::filter(&self.shell, command, &input) {
+            Ok(output) => self.source_editor.replace_file_chars(range, &output),
+            Err(e) => self.set_error(format!("{command} failed: {e}")),
+        }
+    }
+
+    fn set_language(&mut self, name: &str) {
+        let language = match syntax::Language::named(name) {
+            Some(language) => Some(language),
+            None if name.is_empty() => None,
+            None => return self.set_error(format!("no language called {name}")),
+        };
+
+        self.source_editor.set_language(language);
+        let name = language.map_or("plain text", |language| language.name);
+        self.set_message(format!("highlighting as {name}"));
+    }
+
+    fn search(&mut self, search: Search) {
+        if search.query.is_empty() {
+            return;
+        }
+
+        let (query, forward) = (search.query.clone(), search.forward);
+        let found = self.source_editor.search(search);
+        match found {
+            Found::Match => {}
+            Found::Wrapped => self.set_message("search wrapped"),
+            Found::End if forward => self.set_message("hit bottom"),
+            Found::End => self.set_message("hit top"),
+            Found::Nothing => self.set_message(format!("no matches for {query}")),
+        }
+        if matches!(found, Found::End | Found::Nothing) {
+            self.ring_bell();
+        }
+    }
+
+    fn answer(&mut self, prompt: CharPrompt, c: char) {
+        match prompt {
+            CharPrompt::CloseModified => match c.to_ascii_lowercase() {
+                's' => {
+                    self.save();
+                    // saving can fail or need asking about first
+                    if !self.source_editor.modified {
+                        self.close_file();
+                    }
+                }
+                'd' => {
+                    self.source_editor.modified = false;
+                    self.close_file();
+                }
+                _ => {}
+            },
+            CharPrompt::QuitModified => match c.to_ascii_lowercase() {
+                // there's nowhere to save it yet, so quitting has to wait until it's named
+                's' if self.file.is_none() && !self.source_editor.read_only => {
+                    self.text_prompt = Some(TextPrompt::new(TextPromptKind::SaveAs { quit: true }));
+                }
+                's' => {
+                    self.save();
+                    if !self.source_editor.modified {
+                        self.should_exit = true;
+                    }
+                }
+                'd' => self.should_exit = true,
+                _ => {}
+            },
+            CharPrompt::OpenModified(file) => match c.to_ascii_lowercase() {
+                's' => {
+                    self.save();
+                    if !self.source_editor.modified {
+                        self.open(file);
+                    }
+                }
+                'd' => {
+                    self.source_editor.modified = false;
+                    self.open(file);
+                }
+                _ => {}
+            },
+            CharPrompt::ChangedOnDisk => match (c.to_ascii_lowercase(), self.file.clone()) {
+                ('o', Some(path)) => self.write_file(&path),
+                ('r', _) => self.reload_from_disk(),
+                _ => self.set_message("not saved"),
+            },
+            CharPrompt::Align => {
+                if let Some(rows) = self.source_editor.selected_lines() {
+                    self.source_editor.align(rows, c);
+                }
+            }
+            CharPrompt::FindChar { forward, inclusive } => {
+                let mut found = false;
+                self.source_editor.move_cursor(false, |editor| {
+                    found |= if forward {
+                        editor.find_char_forward(c, inclusive)
+                    } else {
+                        editor.find_char_backward(c, inclusive)
+                    };
+                });
+                if !found {
+                    self.set_message(format!("no {c} on this line"));
+                    self.ring_bell();
+                }
+            }
+        }
+    }
+
+    fn add_to_number(&mut self, amount: i128) {
+        if !self.source_editor.read_only && !self.source_editor.add_to_number(amount) {
+            self.set_message("there's no number after the cursor");
+        }
+    }
+
+    /// Whether an action on the selected lines would be on the whole of a paged file for want of
+    /// a selection, which it can't be, saying so if it would.
+    fn whole_paged_file(&mut self) -> bool {
+        let whole = self.source_editor.is_paged() && self.source_editor.selected_lines().is_none();
+        if whole {
+            self.set_error("select the lines first in a file this big");
+        }
+        whole
+    }
+
+    fn select_syntax_node(&mut self, node: SyntaxNode) {
+        if let Err(e) = self.source_editor.select_syntax_node(node) {
+            self.set_message(e);
+        }
+    }
+
+    fn show_hover(&mut self) {
+        if let Err(e) = self.prepare_language_server() {
+            return self.set_error(e);
+        }
+
+        let (row, column) = (self.source_editor.row, self.source_editor.column);
+        let line = self.source_editor.line(row).to_string();
+        let client = self.language_server.as_mut().unwrap();
+        match client.hover(row, lsp::utf16_column(&line, column)) {
+            Ok(Some(hover)) => self.hover = Some(hover),
+            Ok(None) => self.set_message("no documentation found"),
+            Err(e) => {
+                self.language_server = None;
+                self.set_error(e);
+            }
+        }
+    }
+
+    fn current_jump(&self) -> Jump {
+        Jump {
+            file: self.file.clone(),
+            row: self.source_editor.rows_before() + self.source_editor.row,
+            column: Some(self.source_editor.column),
+            utf16_column: None,
+        }
+    }
+
+    /// Goes to where `jump` points, returning whether it could.
+    fn jump(&mut self, jump: Jump) -> bool {
+        let same_file = match (&self.file, &jump.file) {
+            (Some(a), Some(b)) => a.canonicalize().ok() == b.canonicalize().ok(),
+            (a, b) => a == b,
+        };
+        if !same_file {
+            if let Some(file) = jump.file {
+                if !self.open(file) {
+                    return false;
+                }
+            }
+        }
+
+        let source_editor = &mut self.source_editor;
+        let row = source_editor.show_file_row(jump.row);
+        let column = match (jump.column, jump.utf16_column) {
+            (Some(column), _) => column,
+            (None, Some(column)) => lsp::char_column(&source_editor.line(row).to_string(), column),
+            (None, None) => 0,
+        };
+        let position = source_editor.clamp_position((row, column));
+        source_editor.jump_to_char(source_editor.position_to_char(position));
+
+        true
+    }
+
+    fn jump_back(&mut self) {
+        if let Some(to) = self.jumps_back.pop() {
+            let from = self.current_jump();
+            if self.jump(to.clone()) {
+                self.jumps_forward.push(from);
+            } else {
+                self.jumps_back.push(to);
+            }
+        }
+    }
+
+    fn jump_forward(&mut self) {
+        if let Some(to) = self.jumps_forward.pop() {
+            let from = self.current_jump();
+            if self.jump(to.clone()) {
+                self.jumps_back.push(from);
+            } else {
+                self.jumps_forward.push(to);
+            }
+        }
+    }
+
+    /// Makes sure the language server is running and knows about any edits.
+    fn prepare_language_server(&mut self) -> Result<(), String> {
+        if self.source_editor.loader.is_some() {
+            return Err("the language server can't be used until the file is loaded".to_string());
+        }
+        if self.source_editor.is_paged() {
+            return Err("a language server can't be used with a file this big".to_string());
+        }
+        if self.language_server.is_none() {
+            self.start_language_server()?;
+        }
+        self.sync_language_server();
+
+        Ok(())
+    }
+
+    /// How comments are written in the file, from the config or else its language.
+    fn comments(&self) -> Option<editor::Comments> {
+        let configured = self
+            .file
+            .as_ref()
+            .and_then(|file| file.extension()?.to_str())
+            .and_then(|extension| self.comments.get(extension));
+        if let Some(comments) = configured {
+            return Some(comments.clone());
+        }
+
+        let language = self.source_editor.highlighter.language()?;
+        Some(editor::Comments {
+            line: language.line_comment.map(String::from),
+            block: language
+                .block_comment
+                .map(|(start, end)| (start.to_string(), end.to_string())),
+        })
+    }
+
+    /// Goes back to auto-indenting and the line length limit as configured for the kind of file
+    /// that's open, after they've been changed for the file before.
+    fn use_settings_for_file(&mut self) {
+        let extension = self
+            .file
+            .as_ref()
+            .and_then(|file| file.extension()?.to_str());
+        let settings = &mut self.source_editor.settings;
+        settings.auto_indent = extension
+            .and_then(|extension| self.auto_indent_by_extension.get(extension))
+            .copied()
+            .unwrap_or(self.auto_indent);
+        settings.line_length_limit = extension
+            .and_then(|extension| self.line_length_limit_by_extension.get(extension))
+            .copied()
+            .or(self.line_length_limit);
+    }
+
+    /// Starts the language server for the file, if one is configured for it.
+    fn open_language_server(&mut self) {
+        let configured = self
+            .file
+            .as_ref()
+            .and_then(|file| file.extension()?.to_str())
+            .is_some_and(|extension| self.language_servers.contains_key(extension));
+
+        if configured && self.source_editor.loader.is_none() && !self.source_editor.is_paged() {
+            if let Err(e) = self.start_language_server() {
+                self.language_server = None;
+                self.set_error(e);
+            }
+        } else {
+            self.language_server = None;
+        }
+    }
+
+    /// Starts the language server, or reuses the running one if it's the same server.
+    fn start_language_server(&mut self) -> Result<(), String> {
+        let file = self
+            .file
+            .as_ref()
+            .ok_or("save the file before using a language server")?;
+        let extension = file.extension().and_then(|extension| extension.to_str());
+        let command = extension
+            .and_then(|extension| self.language_servers.get(extension))
+            .ok_or("no language server is configured for this kind of file")?;
+
+        let text = self.source_editor.buffer.to_string();
+        match &mut self.language_server {
+            Some(client) if client.command() == command.as_slice() => client.open(file, &text)?,
+            _ => self.language_server = Some(lsp::Client::start(command, file, &text)?),
+        }
+        self.synced_changes = self.source_editor.changes;
+
+        Ok(())
+    }
+
+    fn sync_language_server(&mut self) {
+        let client = match &mut self.language_server {
+            Some(client) if self.synced_changes != self.source_editor.changes => client,
+            _ => return,
+        };
+
+        self.synced_changes = self.source_editor.changes;
+        if let Err(e) = client.did_change(&self.source_editor.buffer.to_string()) {
+            self.language_server = None;
+            self.set_error(e);
+        }
+    }
+
+    fn receive_diagnostics(&mut self) {
+        let client = match &mut self.language_server {
+            Some(client) => client,
+            None => return,
+        };
+
+        match client.take_diagnostics() {
+            Ok(Some(diagnostics)) => self.source_editor.set_diagnostics(diagnostics),
+            Ok(None) => {}
+            Err(e) => {
+                self.language_server = None;
+                self.set_error(e);
+            }
+        }
+    }
+
+    fn accept_completion(&mut self, item: lsp::Completion) {
+        let source_editor = &mut self.source_editor;
+        let line = source_editor.line(source_editor.row).to_string();
+        let columns = match item.range {
+            Some((start, end)) => lsp::char_column(&line, start)..lsp::char_column(&line, end),
+            // without a range, replace the part of the word that's already been typed
+            None => {
+                let before: Vec<char> = line.chars().take(source_editor.column).collect();
+                let typed = before
+                    .iter()
+                    .rev()
+                    .take_while(|c| c.is_alphanumeric() || **c == '_')
+                    .count();
+                source_editor.column - typed..source_editor.column
+            }
+        };
+        source_editor.replace_columns(columns, &item.text);
+    }
+
+    fn browse(&mut self, dir: &Path) {
+        match Palette::directory(dir) {
+            Ok(palette) => self.palette = Some(palette),
+            Err(e) => self.set_error(format!("couldn't read {}: {e}", dir.display())),
+        }
+    }
+
+    /// Replaces the buffer with `file`, unless that would lose unsaved changes.
+    /// Returns whether it was opened.
+    fn open(&mut self, file: PathBuf) -> bool {
+        if self.source_editor.modified {
+            self.set_error("save your changes before opening another file");
+            return false;
+        }
+        if !file.exists() {
+            recent::remove(self.state_dir.as_deref(), &file);
+            self.set_error(format!("{} no longer exists", file.display()));
+            return false;
+        }
+
+        let (mut source_editor, binary, format) = match open_file(&file) {
+            Ok(opened) => opened,
+            Err(e) => {
+                self.set_error(format!("couldn't open {}: {e}", file.display()));
+                return false;
+            }
+        };
+        source_editor.settings = self.source_editor.settings.clone();
+        source_editor.read_only = self.read_only_flag || binary;
+        if self.file.is_some() {
+            self.alternate = Some(self.current_jump());
+        }
+        self.remember_position();
+
+        self.source_editor = source_editor;
+        self.completion = None;
+        if let Some(split) = &mut self.split {
+            split.other = self.source_editor.view();
+        }
+        self.resize_editor();
+        recent::add(self.state_dir.as_deref(), &file);
+        self.disk_stamp = DiskStamp::read(&file);
+        self.set_file(Some(file));
+        self.binary = binary;
+        self.format = format;
+        self.restore_position();
+        self.use_settings_for_file();
+        self.open_language_server();
+
+        true
+    }
+
+    /// Remembers where the cursor is in the file, for the next time it's opened.
+    fn remember_position(&self) {
+        if let (true, Some(file)) = (self.restore_positions, &self.file) {
+            let view = self.source_editor.file_view();
+            let position = positions::Position {
+                row: view.row,
+                column: view.column,
+                scroll: view.scroll,
+            };
+            positions::set(self.state_dir.as_deref(), file, position);
+        }
+    }
+
+    /// Goes back to where the cursor was when the file was last left,
+    /// or as close as it can if the file's shorter now.
+    fn restore_position(&mut self) {
+        let position = match (self.restore_positions, &self.file) {
+            (true, Some(file)) => positions::get(self.state_dir.as_deref(), file),
+            _ => None,
+        };
+        let Some(position) = position else {
+            return;
+        };
+
+        self.source_editor.set_file_view(View {
+            row: position.row,
+            column: position.column,
+            scroll: position.scroll,
+            h_scroll: 0,
+        });
+    }
+
+    /// Saves which files are open, where in them and how the window is split, to open them
+    /// again later.
+    fn save_session(&mut self) {
+        let position = |view: View| positions::Position {
+            row: view.row,
+            column: view.column,
+            scroll: view.scroll,
+        };
+        // restoring could be from another directory
+        let absolute = |file: &Path| file.canonicalize().unwrap_or(file.to_path_buf());
+        let session = session::Session {
+            file: self
+                .file
+                .as_deref()
+                .map(|file| (absolute(file), position(self.source_editor.file_view()))),
+            alternate: self.alternate.as_ref().and_then(|jump| {
+                let position = positions::Position {
+                    row: jump.row,
+                    column: jump.column.unwrap_or(0),
+                    scroll: 0,
+                };
+                Some((absolute(jump.file.as_deref()?), position))
+            }),
+            split: self.split.as_ref().map(|split| session::Split {
+                vertical: split.direction == SplitDirection::Vertical,
+                focus_first: split.focus_first,
+                other: position(split.other),
+            }),
+        };
+
+        match session::save(self.state_dir.as_deref(), &session) {
+            Ok(()) => self.set_message("saved the session"),
+            Err(e) => self.set_error(format!("couldn't save the session: {e}")),
+        }
+    }
+
+    /// Opens the files from the saved session again, where they were left, skipping any that
+    /// no longer exist.
+    fn restore_session(&mut self) {
+        let session = match session::load(self.state_dir.as_deref()) {
+            Ok(session) => session,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return self.set_error("there's no saved session");
+            }
+            Err(e) => return self.set_error(format!("couldn't load the session: {e}")),
+        };
+        let view = |position: positions::Position| View {
+            row: position.row,
+            column: position.column,
+            scroll: position.scroll,
+            h_scroll: 0,
+        };
+
+        let mut skipped = Vec::new();
+        let mut opened = None;
+        if let Some((file, position)) = session.file {
+            if !file.exists() {
+                skipped.push(file);
+            } else if self.open(file) {
+                opened = Some(position);
+            } else {
+                // opening it already said why it couldn't be
+                return;
+            }
+        }
+        if let Some((file, position)) = session.alternate {
+            if file.exists() {
+                self.alternate = Some(Jump {
+                    file: Some(file),
+                    row: position.row,
+                    column: Some(position.column),
+                    utf16_column: None,
+                });
+            } else {
+                skipped.push(file);
+            }
+        }
+        let paged = self.source_editor.is_paged();
+        self.split = session.split.filter(|_| !paged).map(|split| Split {
+            direction: if split.vertical {
+                SplitDirection::Vertical
+            } else {
+                SplitDirection::Horizontal
+            },
+            other: view(split.other),
+            focus_first: split.focus_first,
+        });
+        self.resize_editor();
+        if let Some(position) = opened {
+            self.source_editor.set_file_view(view(position));
+        }
+
+        match skipped.as_slice() {
+            [] => {}
+            [file] => self.set_error(format!(
+                "skipped {}, which no longer exists",
+                file.display()
+            )),
+            files => self.set_error(format!(
+                "skipped {} files that no longer exist",
+                files.len()
+            )),
+        }
+    }
+
+    fn split(&mut self, direction: SplitDirection) {
+        if self.split.is_some() {
+            return;
+        }
+        // the other view would be left pointing at rows that had been paged out
+        if self.source_editor.is_paged() {
+            return self.set_error("files this big can't be split");
+        }
+
+        self.split = Some(Split {
+            direction,
+            other: self.source_editor.view(),
+            focus_first: true,
+        });
+        self.resize_editor();
+    }
+
+    fn focus_other_pane(&mut self) {
+        let split = match &mut self.split {
+            Some(split) => split,
+            None => return,
+        };
+
+        let view = mem::replace(&mut split.other, self.source_editor.view());
+        split.focus_first = !split.focus_first;
+        self.resize_editor();
+        self.source_editor.set_view(view);
+    }
+
+    /// Moves the cursor to where the mouse was pressed or dragged to,
+    /// focusing the other pane first if it's there.
+    fn click(&mut self, x: usize, y: usize, drag: bool) {
+        let (focused, other) = self.pane_rects();
+        let (x, y) = match (
+            focused.relative(x, y),
+            other.and_then(|other| other.relative(x, y)),
+        ) {
+            (Some(position), _) => position,
+            // dragging is kept to the pane it started in
+            (None, Some(position)) if !drag => {
+                self.focus_other_pane();
+                position
+            }
+            _ => return,
+        };
+
+        if !drag {
+            self.dragging_scrollbar =
+                self.source_editor.settings.scrollbar && x + 1 == self.source_editor.width;
+        }
+        if self.dragging_scrollbar {
+            self.source_editor.scroll_to_scrollbar(y);
+            return;
+        }
+
+        if let Some((row, column)) = self.source_editor.buffer_position(x, y) {
+            self.source_editor.move_cursor(drag, |source_editor| {
+                source_editor.row = row;
+                source_editor.column = column;
+                source_editor.h_scroll_to_show_cursor();
+            });
+        }
+    }
+
+    /// Goes back to the previous file, or if there isn't one, quits or starts an empty buffer.
+    fn close_file(&mut self) {
+        // the file being closed shouldn't be the one to switch back to afterwards
+        if let Some(alternate) = self.alternate.take() {
+            if self.jump(alternate) {
+                self.alternate = None;
+                return;
+            }
+        }
+
+        if self.close_to_scratch {
+            self.open_scratch();
+        } else {
+            self.should_exit = true;
+        }
+    }
+
+    fn new_file(&mut self) {
+        if self.source_editor.modified {
+            return self.set_error("save your changes before opening another file");
+        }
+
+        if self.file.is_some() {
+            self.alternate = Some(self.current_jump());
+        }
+        self.open_scratch();
+    }
+
+    fn set_file(&mut self, file: Option<PathBuf>) {
+        self.file_name = file.as_deref().map(display_path).unwrap_or_default();
+        self.file = file;
+    }
+
+    /// Replaces the buffer with an empty one that isn't saved anywhere until it's given a path.
+    fn open_scratch(&mut self) {
+        self.remember_position();
+        let settings = self.source_editor.settings.clone();
+        self.source_editor = SourceEditor::new(String::new());
+        self.source_editor.settings = settings;
+        self.source_editor.read_only = self.read_only_flag;
+        self.set_file(None);
+        self.binary = false;
+        self.format = FileFormat::default();
+        self.disk_stamp = None;
+        self.completion = None;
+        if let Some(split) = &mut self.split {
+            split.other = self.source_editor.view();
+        }
+        self.resize_editor();
+        self.use_settings_for_file();
+        self.open_language_server();
+    }
+
+    /// Scrolls for a notch of the mouse wheel, further if it's come quickly after
+    /// others the same way and scrolling speeds up.
+    fn scroll_wheel(&mut self, down: bool) {
+        let mut lines = self.scroll_lines;
+        if let Some((time, last_down, last_lines)) = self.last_scroll {
+            let rapid = time.elapsed() < SCROLL_ACCELERATION_WINDOW && last_down == down;
+            if self.scroll_acceleration && rapid {
+                lines = (last_lines + 1).min(self.scroll_lines * MAX_SCROLL_ACCELERATION);
+            }
+        }
+        self.last_scroll = Some((Instant::now(), down, lines));
+
+        for _ in 0..lines {
+            if down {
+                self.source_editor.scroll_down();
+            } else {
+                self.source_editor.scroll_up();
+            }
+        }
+    }
+
+    fn close_split(&mut self) {
+        self.split = None;
+        self.resize_editor();
+    }
+
+    fn clear_pending_keys(&mut self) -> Vec<event::KeyEvent> {
+        self.show_key_hint = false;
+        mem::take(&mut self.pending_keys)
+    }
+
+    /// Leaves whatever is in progress, innermost first, returning whether there was anything.
+    fn cancel(&mut self) -> bool {
+        if self.text_prompt.take().is_some()
+            || self.char_prompt.take().is_some()
+            || self.palette.take().is_some()
+            || self.unsaved_changes.take().is_some()
+            || self.completion.take().is_some()
+        {
+            return true;
+        }
+        if !self.pending_keys.is_empty() {
+            self.clear_pending_keys();
+            return true;
+        }
+
+        self.count.take().is_some() || self.source_editor.clear_selection()
+    }
+
+    /// Does `action` to the text from the mark to the cursor, as if it were selected.
+    fn at_mark(&mut self, action: Action) -> io::Result<()> {
+        if !self.source_editor.select_to_mark() {
+            self.set_message("set a mark first");
+            return Ok(());
+        }
+
+        self.execute(action)?;
+        // it was only selected for `action` to work on
+        self.source_editor.selection = None;
+        Ok(())
+    }
+
+    fn execute(&mut self, action: Action) -> io::Result<()> {
+        match action {
+            Action::Backspace => self.source_editor.at_each_cursor(SourceEditor::backspace),
+            Action::Delete => self.source_editor.at_each_cursor(SourceEditor::delete),
+            Action::Enter => self.source_editor.at_each_cursor(SourceEditor::enter),
+            Action::AddCursorAtNextOccurrence => {
+                if !self.source_editor.add_cursor_at_next_occurrence() {
+                    self.set_message("there's no other occurrence to add a cursor at");
+                }
+            }
+            Action::AddCursorAbove => self.source_editor.add_cursor_vertically(false),
+            Action::AddCursorBelow => self.source_editor.add_cursor_vertically(true),
+            Action::Left => self.source_editor.move_cursor(false, SourceEditor::left),
+            Action::Right => self.source_editor.move_cursor(false, SourceEditor::right),
+            Action::Up => self.source_editor.move_cursor(false, SourceEditor::up),
+            Action::Down => self.source_editor.move_cursor(false, SourceEditor::down),
+            Action::Home => self.source_editor.move_cursor(false, SourceEditor::home),
+            Action::End => self.source_editor.move_cursor(false, SourceEditor::end),
+            Action::Find => {
+                let kind = TextPromptKind::Find { forward: true };
+                self.text_prompt = Some(TextPrompt::new(kind));
+            }
+            Action::FindBackward => {
+                let kind = TextPromptKind::Find { forward: false };
+                self.text_prompt = Some(TextPrompt::new(kind));
+            }
+            Action::RepeatSearch => match self.source_editor.last_search.clone() {
+                Some(search) => self.search(search),
+                None => self.set_message("nothing has been searched for yet"),
+            },
+            Action::FindCharForward => {
+                let prompt = CharPrompt::FindChar {
+                    forward: true,
+                    inclusive: true,
+                };
+                self.char_prompt = Some(prompt);
+            }
+            Action::FindCharBackward => {
+                let prompt = CharPrompt::FindChar {
+                    forward: false,
+                    inclusive: true,
+                };
+                self.char_prompt = Some(prompt);
+            }
+            Action::TillCharForward => {
+                let prompt = CharPrompt::FindChar {
+                    forward: true,
+                    inclusive: false,
+                };
+                self.char_prompt = Some(prompt);
+            }
+            Action::TillCharBackward => {
+                let prompt = CharPrompt::FindChar {
+                    forward: false,
+                    inclusive: false,
+                };
+                self.char_prompt = Some(prompt);
+            }
+            Action::NextBlankLine => {
+                let movement = SourceEditor::next_blank_line;
+                self.source_editor.move_cursor(false, movement);
+            }
+            Action::PreviousBlankLine => {
+                let movement = SourceEditor::previous_blank_line;
+                self.source_editor.move_cursor(false, movement);
+            }
+            Action::NextSiblingLine => {
+                let movement = SourceEditor::next_sibling_line;
+                self.source_editor.move_cursor(false, movement);
+            }
+            Action::PreviousSiblingLine => {
+                let movement = SourceEditor::previous_sibling_line;
+                self.source_editor.move_cursor(false, movement);
+            }
+            Action::SelectLeft => self.source_editor.move_cursor(true, SourceEditor::left),
+            Action::SelectRight => self.source_editor.move_cursor(true, SourceEditor::right),
+            Action::SelectUp => self.source_editor.move_cursor(true, SourceEditor::up),
+            Action::SelectDown => self.source_editor.move_cursor(true, SourceEditor::down),
+            Action::SelectHome => self.source_editor.move_cursor(true, SourceEditor::home),
+            Action::SelectEnd => self.source_editor.move_cursor(true, SourceEditor::end),
+            Action::SelectLines => self.source_editor.select_lines(),
+            Action::SelectBlock => self.source_editor.select_block(),
+            Action::ReverseLines if self.whole_paged_file() => {}
+            Action::ReverseLines => {
+                self.source_editor.load_all();
+                let rows = self.source_editor.selected_lines_or_all();
+                self.source_editor.reverse_lines(rows);
+            }
+            Action::ToggleComment => {
+                let comments = self.comments().unwrap_or_default();
+                if let Err(e) = self.source_editor.toggle_comment(&comments) {
+                    self.set_message(e);
+                }
+            }
+            Action::ReflowParagraph => {
+                self.source_editor.load_all();
+                let comments = self.comments().unwrap_or_default();
+                if let Err(e) = self.source_editor.reflow_paragraph(&comments) {
+                    self.set_message(e);
+                }
+            }
+            Action::Align => match self.source_editor.selected_lines() {
+                Some(_) => self.char_prompt = Some(CharPrompt::Align),
+                None => self.set_message("select the lines to align first"),
+            },
+            Action::FilterThroughShell => match self.source_editor.selected_file_chars() {
+                Some(range) => {
+                    let kind = TextPromptKind::Filter { range };
+                    self.text_prompt = Some(TextPrompt::new(kind));
+                }
+                None => self.set_message("select the text to filter first"),
+            },
+            Action::Increment => self.add_to_number(1),
+            Action::Decrement => self.add_to_number(-1),
+            Action::UniqueLines if self.whole_paged_file() => {}
+            Action::UniqueLines => {
+                self.source_editor.load_all();
+                let rows = self.source_editor.selected_lines_or_all();
+                let removed = self.source_editor.unique_lines(rows);
+                let plural = if removed == 1 { "" } else { "s" };
+                self.set_message(format!("removed {removed} duplicate line{plural}"));
+            }
+            Action::TrimTrailingBlankLines if self.source_editor.is_paged() => {
+                self.set_error("files this big can't be trimmed");
+            }
+            Action::TrimTrailingBlankLines => {
+                self.source_editor.load_all();
+                let removed = self.source_editor.trim_trailing_blank_lines();
+                let plural = if removed == 1 { "" } else { "s" };
+                self.set_message(format!("removed {removed} blank line{plural} at the end"));
+            }
+            Action::ExpandSelection => {
+                if !self.source_editor.expand_selection() {
+                    self.set_message("nothing encloses this");
+                }
+            }
+            Action::ShrinkSelection => {
+                if !self.source_editor.shrink_selection() {
+                    self.set_message("the selection hasn't been expanded");
+                }
+            }
+            Action::SelectEnclosingNode => self.select_syntax_node(SyntaxNode::Enclosing),
+            Action::SelectNextSibling => self.select_syntax_node(SyntaxNode::NextSibling),
+            Action::SelectPreviousSibling => self.select_syntax_node(SyntaxNode::PreviousSibling),
+            Action::SelectFunction => self.select_syntax_node(SyntaxNode::Function),
+            Action::ToggleFold => self.source_editor.toggle_fold(),
+            Action::FoldAll => self.source_editor.fold_all(),
+            Action::UnfoldAll => self.source_editor.unfold_all(),
+            Action::NextDiagnostic => self.source_editor.next_diagnostic(),
+            Action::PreviousDiagnostic => self.source_editor.previous_diagnostic(),
+            Action::NextHunk => self.source_editor.next_hunk(),
+            Action::PreviousHunk => self.source_editor.previous_hunk(),
+            Action::Copy => {
+                if let Some(clipboard) = self.source_editor.copy() {
+                    self.set_clipboard(clipboard)?;
+                }
+            }
+            Action::Cut => {
+                if let Some(clipboard) = self.source_editor.cut() {
+                    self.set_clipboard(clipboard)?;
+                }
+            }
+            Action::Paste => self.source_editor.paste(&self.clipboard),
+            Action::PasteReindented => self.source_editor.paste_reindented(&self.clipboard),
+            Action::SelectAll if self.source_editor.is_paged() => {
+                self.set_error("files this big can't all be selected");
+            }
+            Action::SelectAll => {
+                // what's still to be read is part of all of it too
+                self.source_editor.load_all();
+                self.source_editor.select_all();
+            }
+            Action::SetMark => {
+                self.source_editor.set_mark();
+                self.set_message("mark set");
+            }
+            Action::CutToMark => self.at_mark(Action::Cut)?,
+            Action::CopyToMark => self.at_mark(Action::Copy)?,
+            Action::IndentToMark => self.at_mark(Action::Indent)?,
+            Action::CommentToMark => self.at_mark(Action::ToggleComment)?,
+            Action::FilterToMark => self.at_mark(Action::FilterThroughShell)?,
+            Action::DuplicateSelection => {
+                if !self.source_editor.duplicate_selection() {
+                    self.set_message("nothing is selected");
+                }
+            }
+            Action::Indent => self.source_editor.tab(),
+            Action::Dedent => self.source_editor.dedent(),
+            Action::ScrollUp => self.source_editor.scroll_up(),
+            Action::ScrollDown => self.source_editor.scroll_down(),
+            Action::Undo if !self.source_editor.can_undo() => self.ring_bell(),
+            Action::Undo => self.source_editor.undo(),
+            Action::Redo if !self.source_editor.can_redo() => self.ring_bell(),
+            Action::Redo => self.source_editor.redo(),
+            Action::Earlier if !self.source_editor.can_undo() => self.ring_bell(),
+            Action::Earlier => {
+                self.source_editor.earlier();
+                self.show_history_position();
+            }
+            Action::Later if !self.source_editor.can_go_later() => self.ring_bell(),
+            Action::Later => {
+                self.source_editor.later();
+                self.show_history_position();
+            }
+            Action::RepeatLastChange => self.source_editor.repeat_last_change(),
+            Action::ToggleTrailingWhitespace => {
+                self.source_editor.settings.trailing_whitespace =
+                    !self.source_editor.settings.trailing_whitespace;
+            }
+            Action::ToggleRainbowBrackets => {
+                self.source_editor.settings.rainbow_brackets =
+                    !self.source_editor.settings.rainbow_brackets;
+            }
+            Action::ChangeTabWidth => {
+                let settings = &mut self.source_editor.settings;
+                settings.tab_width = TAB_WIDTHS
+                    .iter()
+                    .copied()
+                    .find(|width| *width > settings.tab_width)
+                    .unwrap_or(TAB_WIDTHS[0]);
+                let message = format!("tabs are now {} columns wide", settings.tab_width);
+                self.source_editor.h_scroll_to_show_cursor();
+                self.set_message(message);
+            }
+            Action::ToggleAutoIndent => {
+                let settings = &mut self.source_editor.settings;
+                settings.auto_indent = !settings.auto_indent;
+                if settings.auto_indent {
+                    self.set_message("auto-indenting");
+                } else {
+                    self.set_message("not auto-indenting");
+                }
+            }
+            Action::ToggleSoftTabs => {
+                let settings = &mut self.source_editor.settings;
+                settings.soft_tabs = !settings.soft_tabs;
+                if settings.soft_tabs {
+                    self.set_message("indenting with spaces");
+                } else {
+                    self.set_message("indenting with tabs");
+                }
+            }
+            Action::ToggleVirtualSpace => {
+                let settings = &mut self.source_editor.settings;
+                settings.virtual_space = !settings.virtual_space;
+                self.source_editor.clamp_column();
+                self.source_editor.h_scroll_to_show_cursor();
+            }
+            Action::ToggleOverwrite => {
+                let settings = &mut self.source_editor.settings;
+                settings.overwrite = !settings.overwrite;
+            }
+            Action::ToggleLineNumbers => {
+                let settings = &mut self.source_editor.settings;
+                settings.line_numbers = !settings.line_numbers;
+                self.source_editor.h_scroll_to_show_cursor();
+            }
+            Action::ToggleScrollbar => {
+                let settings = &mut self.source_editor.settings;
+                settings.scrollbar = !settings.scrollbar;
+                self.source_editor.h_scroll_to_show_cursor();
+            }
+            Action::ToggleClock => {
+                self.clock.shown = !self.clock.shown;
+                self.clock.update();
+            }
+            Action::ToggleReadOnly => {
+                self.source_editor.read_only = !self.source_editor.read_only;
+                if self.source_editor.read_only {
+                    self.set_message("buffer is now read-only");
+                } else {
+                    self.set_message("buffer is now editable");
+                }
+            }
+            Action::Split => self.split(SplitDirection::Horizontal),
+            Action::VerticalSplit => self.split(SplitDirection::Vertical),
+            Action::FocusOtherPane => self.focus_other_pane(),
+            Action::CloseSplit => self.close_split(),
+            Action::Save => self.save(),
+            Action::ShowUnsavedChanges => self.show_unsaved_changes(),
+            Action::ReopenWithEncoding => self.reopen_with_next_encoding(),
+            Action::ChangeSaveEncoding => self.change_save_encoding(),
+            Action::ToggleLineEnding => self.toggle_line_ending(),
+            Action::SetLanguage => {
+                self.text_prompt = Some(TextPrompt::new(TextPromptKind::Language));
+            }
+            Action::CommandPalette => self.palette = Some(Palette::commands(&self.keymap)),
+            Action::OpenRecent => {
+                let files = recent::load(self.state_dir.as_deref());
+                if files.is_empty() {
+                    self.set_message("no recent files");
+                } else {
+                    self.palette = Some(Palette::files(files));
+                }
+            }
+            Action::SaveSession => self.save_session(),
+            Action::RestoreSession => self.restore_session(),
+            Action::Complete => self.complete(),
+            Action::GoToDefinition => self.go_to_definition(),
+            Action::SpellingSuggestions => self.spelling_suggestions(),
+            Action::AddToDictionary => self.add_to_dictionary(),
+            Action::Hover => self.show_hover(),
+            Action::JumpBack => self.jump_back(),
+            Action::JumpForward => self.jump_forward(),
+            Action::FindFile => self.palette = Some(Palette::files(finder::files())),
+            Action::NewFile => self.new_file(),
+            Action::CloseFile => {
+                if self.source_editor.modified {
+                    self.char_prompt = Some(CharPrompt::CloseModified);
+                } else {
+                    self.close_file();
+                }
+            }
+            Action::AlternateFile => match self.alternate.clone() {
+                Some(alternate) => {
+                    self.jump(alternate);
+                }
+                None => self.set_message("there's no previous file to switch to"),
+            },
+            Action::BrowseDirectory => {
+                let dir = match self.file.as_deref().and_then(Path::parent) {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                    _ => PathBuf::from("."),
+                };
+                self.browse(&dir);
+            }
+            Action::ToggleMacroRecording => self.toggle_macro_recording(),
+            Action::ReplayMacro => self.replay_macro(1)?,
+            Action::ReplayMacroOnLines => self.replay_macro_on_lines()?,
+            Action::Cancel => {
+                self.cancel();
+            }
+            Action::Quit => {
+                if self.source_editor.modified {
+                    self.char_prompt = Some(CharPrompt::QuitModified);
+                } else {
+                    self.should_exit = true;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn save(&mut self) {
+        let path = match &self.file {
+            Some(path) => path.clone(),
+            None if self.source_editor.read_only => {
+                return self.set_error("the buffer is read-only")
+            }
+            None => {
+                self.text_prompt = Some(TextPrompt::new(TextPromptKind::SaveAs { quit: false }));
+                return;
+            }
+        };
+        if self.source_editor.read_only {
+            return self.set_error("the buffer is read-only");
+        }
+
+        // a file that's been deleted since can just be written again
+        if let (Some(old), Some(new)) = (self.disk_stamp, DiskStamp::read(&path)) {
+            if old != new {
+                self.char_prompt = Some(CharPrompt::ChangedOnDisk);
+                return;
+            }
+        }
+
+        self.write_file(&path);
+    }
+
+    /// Saves a buffer that hasn't got a file yet to `path`, as long as that's not a file already.
+    fn save_as(&mut self, path: PathBuf) {
+        if path.as_os_str().is_empty() {
+            return self.set_message("not saved");
+        }
+        if path.exists() {
+            return self.set_error(format!("{} already exists", path.display()));
+        }
+
+        self.write_file(&path);
+        if self.source_editor.modified {
+            return;
+        }
+
+        recent::add(self.state_dir.as_deref(), &path);
+        self.source_editor
+            .set_language(syntax::Language::detect(&path));
+        self.set_file(Some(path));
+        self.use_settings_for_file();
+        self.open_language_server();
+    }
+
+    /// Saves the buffer to `path`, whatever's there already.
+    ///
+    /// A paged file is written a page at a time from what's on disk and what's been edited,
+    /// and as it was read, since there's no changing its encoding or line endings.
+    fn write_file(&mut self, path: &Path) {
+        let written = if self.source_editor.is_paged() {
+            write_atomically_with(path, |file| {
+                let mut writer = BufWriter::new(file);
+                self.source_editor.write_to(&mut writer)?;
+                writer.flush()
+            })
+        } else {
+            self.source_editor.load_all();
+            let text = self.source_editor.buffer.to_string();
+            let text = self.format.line_ending.apply(text);
+            let bytes = match self.format.encoding.encode(&text) {
+                Ok(bytes) => bytes,
+                Err(e) => return self.set_error(e),
+            };
+            write_atomically(path, &bytes)
+        };
+
+        match written {
+            Ok(()) => {
+                self.disk_stamp = DiskStamp::read(path);
+                self.source_editor.modified = false;
+                self.set_message(format!("saved {}", path.display()));
+            }
+            Err(e) => self.set_error(format!("couldn't save {}: {e}", path.display())),
+        }
+    }
+
+    /// Changes the encoding the file will next be saved in, without changing the buffer.
+    fn change_save_encoding(&mut self) {
+        if self.source_editor.is_paged() {
+            return self.set_error("files this big are saved as they were read");
+        }
+        let current = encoding::CHOICES
+            .iter()
+            .position(|encoding| *encoding == self.format.encoding.encoding);
+        let next = current.map_or(0, |idx| (idx + 1) % encoding::CHOICES.len());
+
+        self.format.encoding = FileEncoding {
+            encoding: encoding::CHOICES[next],
+            // UTF-16 without a byte order mark would be read back as binary
+            bom: encoding::is_utf16(encoding::CHOICES[next]),
+        };
+        self.set_message(format!("will save as {}", self.format.encoding.name()));
+    }
+
+    fn toggle_line_ending(&mut self) {
+        if self.source_editor.is_paged() {
+            return self.set_error("files this big are saved as they were read");
+        }
+        self.format.line_ending = self.format.line_ending.toggle();
+        self.set_message(format!(
+            "will save with {} line endings",
+            self.format.line_ending.name()
+        ));
+    }
+
+    /// Reads the file again as it is on disk, throwing away any changes to the buffer.
+    fn reload_from_disk(&mut self) {
+        let Some(path) = self.file.clone() else {
+            return;
+        };
+        if self.source_editor.is_paged() {
+            return self.set_error("files this big can't be reloaded, only opened again");
+        }
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) => return self.set_error(format!("couldn't read {}: {e}", path.display())),
+        };
+
+        let (text, _) = encoding::decode_as(&bytes, self.format.encoding.encoding);
+        let (text, line_ending) = LineEnding::normalize(text);
+        self.source_editor.reload(&text);
+        self.format.line_ending = line_ending;
+        self.disk_stamp = DiskStamp::read(&path);
+        self.set_message(format!("reloaded {}", path.display()));
+    }
+
+    /// Shows how the file on disk would change if the buffer were saved now.
+    fn show_unsaved_changes(&mut self) {
+        let Some(path) = self.file.clone() else {
+            return self.set_message("the buffer hasn't been saved anywhere yet");
+        };
+        if self.binary {
+            return self.set_error("binary files can't be diffed");
+        }
+        if self.source_editor.is_paged() {
+            return self.set_error("files this big can't be diffed");
+        }
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return self.set_error(format!("couldn't read {}: {e}", path.display())),
+        };
+
+        let (_, encoding) = encoding::decode(&bytes);
+        let (text, _) = encoding::decode_as(&bytes, self.format.encoding.encoding);
+        let (text, line_ending) = LineEnding::normalize(text);
+        self.source_editor.load_all();
+        let buffer = self.source_editor.buffer.to_string();
+        let old: Vec<&str> = text.split('\n').collect();
+        let new: Vec<&str> = buffer.split('\n').collect();
+
+        // changes to how the file is written don't show up in the lines
+        let mut lines = Vec::new();
+        if !bytes.is_empty() && encoding.name() != self.format.encoding.name() {
+            let (from, to) = (encoding.name(), self.format.encoding.name());
+            lines.push(format!("saving changes the encoding from {from} to {to}"));
+        }
+        if !bytes.is_empty() && line_ending != self.format.line_ending {
+            let (from, to) = (line_ending.name(), self.format.line_ending.name());
+            lines.push(format!("saving changes line endings from {from} to {to}"));
+        }
+        lines.extend(diff::unified(&old, &new, DIFF_CONTEXT));
+
+        if lines.is_empty() {
+            return self.set_message("saving wouldn't change anything");
+        }
+        self.unsaved_changes = Some(UnsavedChanges { lines, scroll: 0 });
+        self.set_message("Up and Down scroll, and anything else closes");
+    }
+
+    fn handle_unsaved_changes_key(&mut self, key_event: event::KeyEvent) {
+        let Some(changes) = &mut self.unsaved_changes else {
+            return;
+        };
+        let page = self.height.saturating_sub(2).max(1);
+        let last = changes.lines.len().saturating_sub(page);
+
+        changes.scroll = match key_event.code {
+            event::KeyCode::Up => changes.scroll.saturating_sub(1),
+            event::KeyCode::Down => changes.scroll + 1,
+            event::KeyCode::PageUp => changes.scroll.saturating_sub(page),
+            event::KeyCode::PageDown => changes.scroll + page,
+            event::KeyCode::Home => 0,
+            event::KeyCode::End => last,
+            _ => {
+                self.unsaved_changes = None;
+                return;
+            }
+        }
+        .min(last);
+    }
+
+    /// Reads the file again as the next encoding, for when the guess was wrong.
+    fn reopen_with_next_encoding(&mut self) {
+        let path = match &self.file {
+            Some(path) => path.clone(),
+            None => return self.set_error("there's no file to reopen"),
+        };
+        if self.source_editor.modified {
+            return self.set_error("the buffer has unsaved changes");
+        }
+        if self.source_editor.loader.is_some() || self.source_editor.is_paged() {
+            return self.set_error("large files can only be opened as UTF-8");
+        }
+
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) => return self.set_error(format!("couldn't read {}: {e}", path.display())),
+        };
+
+        let current = encoding::CHOICES
+            .iter()
+            .position(|encoding| *encoding == self.format.encoding.encoding);
+        let next = current.map_or(0, |idx| (idx + 1) % encoding::CHOICES.len());
+        let (text, encoding) = encoding::decode_as(&bytes, encoding::CHOICES[next]);
+        let (text, line_ending) = LineEnding::normalize(text);
+
+        self.binary = !encoding.is_utf16() && is_binary(&bytes);
+        if self.binary {
+            self.source_editor.read_only = true;
+        }
+        self.source_editor.reload(&text);
+        self.disk_stamp = DiskStamp::read(&path);
+        self.format = FileFormat {
+            encoding,
+            line_ending,
+        };
+        self.set_message(format!("reopened as {}", encoding.name()));
+    }
+
+    fn set_clipboard(&mut self, clipboard: Clipboard) -> io::Result<()> {
+        if self.osc52 {
+            write!(self.stdout, "{}", osc52::copy_sequence(&clipboard.text))?;
+        }
+        self.clipboard = clipboard;
+
+        Ok(())
+    }
+
+    fn toggle_macro_recording(&mut self) {
+        match self.recording.take() {
+            Some(mut recording) => {
+                // don't include the keys that stopped the recording
+                let stop_keys = self
+                    .keymap
+                    .keys(Action::ToggleMacroRecording)
+                    .map_or(0, |keys| keys.len());
+                recording.truncate(recording.len().saturating_sub(stop_keys));
+                self.last_macro = recording;
+                self.set_message("recorded macro");
+            }
+            None => self.recording = Some(Vec::new()),
+        }
+    }
+
+    fn replay_macro(&mut self, count: usize) -> io::Result<()> {
+        // a macro that replays itself would never finish
+        if self.replaying {
+            return Ok(());
+        }
+
+        if self.last_macro.is_empty() {
+            self.set_error("no macro has been recorded");
+            return Ok(());
+        }
+
+        self.replaying = true;
+        let events = self.last_macro.clone();
+        for _ in 0..count {
+            for event in &events {
+                self.handle_event(event.clone())?;
+            }
+        }
+        self.replaying = false;
+
+        Ok(())
+    }
+
+    /// Replays the macro once on each of the selected lines, starting at the start of the line,
+    /// like vim's `:normal`.
+    fn replay_macro_on_lines(&mut self) -> io::Result<()> {
+        if self.replaying {
+            return Ok(());
+        }
+        if self.last_macro.is_empty() {
+            self.set_error("no macro has been recorded");
+            return Ok(());
+        }
+        let Some(rows) = self.source_editor.selected_lines() else {
+            self.set_message("select the lines to replay the macro on first");
+            return Ok(());
+        };
+
+        self.replaying = true;
+        let events = self.last_macro.clone();
+        let mut row = rows.start;
+        let mut replayed = 0;
+        while replayed < rows.len() && row < self.source_editor.buffer.len_lines() {
+            self.source_editor.selection = None;
+            self.source_editor.cursors.clear();
+            self.source_editor.jump_to_row(row);
+
+            // lines the macro adds or removes move the rest of the selected lines along
+            let lines = self.source_editor.buffer.len_lines();
+            for event in &events {
+                self.handle_event(event.clone())?;
+            }
+            let added = self.source_editor.buffer.len_lines() as isize - lines as isize;
+            row = (row + 1).saturating_add_signed(added).max(row);
+            replayed += 1;
+        }
+        self.replaying = false;
+
+        let lines = if replayed == 1 { "line" } else { "lines" };
+        self.set_message(format!("replayed the macro on {replayed} {lines}"));
+        Ok(())
+    }
+
+    /// Returns whether the key was used by the menu; any other key closes it.
+    fn handle_completion_key(&mut self, key_event: event::KeyEvent) -> bool {
+        let completion = match &mut self.completion {
+            Some(completion) => completion,
+            None => return false,
+        };
+
+        match key_event.code {
+            event::KeyCode::Up => completion.selected = completion.selected.saturating_sub(1),
+            event::KeyCode::Down => {
+                completion.selected = (completion.selected + 1).min(completion.items.len() - 1);
+            }
+            event::KeyCode::Enter | event::KeyCode::Tab => {
+                let item = completion.items.swap_remove(completion.selected);
+                self.completion = None;
+                self.accept_completion(item);
+            }
+            _ => {
+                self.completion = None;
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn handle_palette_key(&mut self, key_event: event::KeyEvent) -> io::Result<()> {
+        let palette = match &mut self.palette {
+            Some(palette) => palette,
+            None => return Ok(()),
+        };
+
+        match key_event.code {
+            event::KeyCode::Enter => {
+                let target = palette
+                    .matches()
+                    .get(palette.selected)
+                    .map(|item| item.target.clone());
+                self.palette = None;
+                match target {
+                    Some(PaletteTarget::Action(action)) => self.execute(action)?,
+                    Some(PaletteTarget::File(file)) if self.source_editor.modified => {
+                        self.char_prompt = Some(CharPrompt::OpenModified(file));
+                    }
+                    Some(PaletteTarget::File(file)) => {
+                        self.open(file);
+                    }
+                    Some(PaletteTarget::Directory(dir)) => self.browse(&dir),
+                    None => {}
+                }
+            }
+            event::KeyCode::Up => palette.selected = palette.selected.saturating_sub(1),
+            event::KeyCode::Down if palette.selected + 1 < palette.matches().len() => {
+                palette.selected += 1;
+            }
+            event::KeyCode::Backspace => {
+                palette.query.pop();
+                palette.selected = 0;
+            }
+            event::KeyCode::Char(c) => {
+                palette.query.push(c);
+                palette.selected = 0;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn draw_lines(
+    stdout: &mut impl Write,
+    lines: &[Vec<Span>],
+    rect: Rect,
+    theme: &Theme,
+) -> io::Result<()> {
+    for (idx, line) in lines.iter().enumerate() {
+        queue!(stdout, cursor::MoveTo(rect.x as u16, (rect.y + idx) as u16))?;
+
+        for span in line {
+            let mut styled = style::style(&span.text);
+            match span.highlight.kind {
+                HighlightKind::Text => {}
+                HighlightKind::Gutter | HighlightKind::FoldSummary => {
+                    styled = styled.with(theme.gutter);
+                }
+                HighlightKind::GutterSeparator => styled = styled.with(theme.gutter_separator),
+                HighlightKind::DiffAdded => styled = styled.with(theme.diff_added),
+                HighlightKind::DiffModified => styled = styled.with(theme.diff_modified),
+                HighlightKind::DiffRemoved => styled = styled.with(theme.diff_removed),
+                HighlightKind::TrailingWhitespace => styled = styled.on(theme.trailing_whitespace),
+                HighlightKind::ControlChar => styled = styled.with(theme.control_char),
+                HighlightKind::Syntax(token) => styled = styled.with(theme.token(token)),
+                HighlightKind::Marker => {
+                    styled = styled.with(theme.marker).on(theme.marker_background);
+                }
+                HighlightKind::Bracket(depth) => {
+                    if !theme.brackets.is_empty() {
+                        styled = styled.with(theme.brackets[depth % theme.brackets.len()]);
+                    }
+                }
+            }
+            if let Some(severity) = span.highlight.diagnostic {
+                styled = styled
+                    .attribute(style::Attribute::Undercurled)
+                    .underline(theme.diagnostic(severity));
+            } else if span.highlight.misspelled {
+                styled = styled
+                    .attribute(style::Attribute::Undercurled)
+                    .underline(theme.misspelled);
+            }
+            if span.highlight.over_limit {
+                styled = styled.on(theme.over_limit);
+            }
+            if span.highlight.occurrence {
+                styled = styled.on(theme.occurrence);
+            }
+            if span.highlight.selected {
+                styled = styled.on(theme.selection);
+            }
+            if span.highlight.cursor {
+                styled = styled.reverse();
+            }
+            write!(stdout, "{styled}")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Draws the scrollbar down the last column of `rect`, if there's one to draw.
+fn draw_scrollbar(
+    stdout: &mut impl Write,
+    thumb: Option<Range<usize>>,
+    rect: Rect,
+    theme: &Theme,
+) -> io::Result<()> {
+    let Some(thumb) = thumb else {
+        return Ok(());
+    };
+
+    for row in 0..rect.height {
+        let x = rect.x + rect.width - 1;
+        queue!(stdout, cursor::MoveTo(x as u16, (rect.y + row) as u16))?;
+        if thumb.contains(&row) {
+            write!(stdout, "{}", style::style('█').with(theme.scrollbar_thumb))?;
+        } else {
+            write!(stdout, "{}", style::style('│').with(theme.scrollbar))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+struct TextPrompt {
+    kind: TextPromptKind,
+    text: String,
+}
+
+impl TextPrompt {
+    fn new(kind: TextPromptKind) -> Self {
+        Self {
+            kind,
+            text: String::new(),
+        }
+    }
+
+    /// What's shown in the status bar, with the text typed so far at the end.
+    fn line(&self) -> String {
+        let label = match self.kind {
+            TextPromptKind::SaveAs { .. } => "save as",
+            TextPromptKind::Find { forward: true } => "find",
+            TextPromptKind::Find { forward: false } => "find backward",
+            TextPromptKind::Language => "language (empty for plain text)",
+            TextPromptKind::Filter { .. } => "filter through",
+        };
+        format!(" {label}: {}", self.text)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum TextPromptKind {
+    /// The path to save a new file to, and whether to quit once it's saved.
+    SaveAs { quit: bool },
+    /// Text to search for, and whether that's forward from the cursor.
+    Find { forward: bool },
+    /// The name of the language to highlight the buffer as, whatever its file is called.
+    Language,
+    /// A shell command to pipe the file's chars in `range` through, replacing them with what
+    /// it writes out.
+    Filter { range: Range<usize> },
+}
+
+#[derive(Debug, Clone)]
+enum CharPrompt {
+    /// Aligning the selected lines, on the character to be typed.
+    Align,
+    /// Closing a file with unsaved changes.
+    CloseModified,
+    /// Quitting with unsaved changes.
+    QuitModified,
+    /// Opening this file from the finder, recent files or a directory, with unsaved changes.
+    OpenModified(PathBuf),
+    /// Saving over a file that's been changed by something else since it was opened.
+    ChangedOnDisk,
+    /// Jumping to the character to be typed on the line, or just short of it if not `inclusive`.
+    FindChar { forward: bool, inclusive: bool },
+}
+
+impl CharPrompt {
+    fn question(&self) -> &'static str {
+        match self {
+            CharPrompt::Align => "align on which character?",
+            CharPrompt::CloseModified => "save changes first? [s]ave, [d]iscard, [c]ancel",
+            CharPrompt::QuitModified => "save changes before quitting? [s]ave, [d]iscard, [c]ancel",
+            CharPrompt::OpenModified(_) => {
+                "save changes before opening it? [s]ave, [d]iscard, [c]ancel"
+            }
+            CharPrompt::ChangedOnDisk => "changed on disk: [o]verwrite, [r]eload, [c]ancel?",
+            CharPrompt::FindChar {
+                inclusive: true, ..
+            } => "jump to which character?",
+            CharPrompt::FindChar { forward: true, .. } => "jump to before which character?",
+            CharPrompt::FindChar { forward: false, .. } => "jump to after which character?",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+    use event::{Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+
+    const TEXT: &str = "fn main() {\n\tlet s = \"héllo wörld\"; // TODO\n    if x { y(); }   \n}\n\n日本語のテキスト\n";
+
+    fn random_event(rng: &mut Rng) -> Event {
+        // along with some text, these cover the keys that finish the bindings starting with Ctrl+K
+        // and Ctrl+W
+        const CHARS: &[char] = &[
+            'a', 'b', ' ', '(', '}', '"', '\'', '_', '/', 'é', '語', '\t', 'f', 'j', '0', '[', ']',
+            'w', 's', 'v', 'q', 'l', 'c', ',', '.',
+        ];
+        const CODES: &[KeyCode] = &[
+            KeyCode::Enter,
+            KeyCode::Backspace,
+            KeyCode::Delete,
+            KeyCode::Insert,
+            KeyCode::Tab,
+            KeyCode::BackTab,
+            KeyCode::Left,
+            KeyCode::Right,
+            KeyCode::Up,
+            KeyCode::Down,
+            KeyCode::Home,
+            KeyCode::End,
+            KeyCode::PageUp,
+            KeyCode::PageDown,
+            KeyCode::Esc,
+            KeyCode::F(3),
+            KeyCode::F(4),
+            KeyCode::F(8),
+            KeyCode::F(12),
+        ];
+        const MODIFIERS: &[KeyModifiers] = &[
+            KeyModifiers::NONE,
+            KeyModifiers::NONE,
+            KeyModifiers::SHIFT,
+            KeyModifiers::CONTROL,
+            KeyModifiers::ALT,
+        ];
+
+        let key = |code, modifiers| Event::Key(KeyEvent::new(code, modifiers));
+        match rng.below(20) {
+            0..=7 => key(KeyCode::Char(rng.pick(CHARS)), KeyModifiers::NONE),
+            8..=13 => key(rng.pick(CODES), rng.pick(MODIFIERS)),
+            14..=16 => {
+                let c = (b'a' + rng.below(26) as u8) as char;
+                key(KeyCode::Char(c), rng.pick(&MODIFIERS[3..]))
+            }
+            17 => key(KeyCode::Char(rng.pick(&['0', '2', '9'])), KeyModifiers::ALT),
+            18 => Event::Resize(rng.below(100) as u16, rng.below(40) as u16),
+            _ => match rng.below(5) {
+                0 => Event::Paste("pasted\r\nline".to_string()),
+                kind => Event::Mouse(MouseEvent {
+                    kind: match kind {
+                        1 => MouseEventKind::ScrollUp,
+                        2 => MouseEventKind::ScrollDown,
+                        3 => MouseEventKind::Down(MouseButton::Left),
+                        _ => MouseEventKind::Drag(MouseButton::Left),
+                    },
+                    column: rng.below(100) as u16,
+                    row: rng.below(40) as u16,
+                    modifiers: KeyModifiers::NONE,
+                }),
+            },
+        }
+    }
+
+    fn ui(text: &str) -> Ui<io::Sink> {
+        let mut ui = Ui::new(
+            SourceEditor::new(text.to_string()),
+            None,
+            false,
+            FileFormat::default(),
+            Theme::default(),
+            false,
+            io::sink(),
+        );
+        ui.resize(60, 12);
+        ui
+    }
+
+    #[test]
+    fn random_keys_dont_panic() {
+        // replaying a macro can type a path to save to and press Enter before anything here
+        // can stop it, so anything written goes somewhere it can't do any harm
+        let dir = env::temp_dir().join(format!("editor-random-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for seed in 1..=100 {
+            eprintln!("seed {seed}");
+            let mut rng = Rng(seed);
+            let mut ui = ui(TEXT);
+            ui.state_dir = Some(dir.clone());
+            ui.prompt_dir = dir.clone();
+            // and filter commands could be anything at all
+            ui.shell = PathBuf::from("true");
+
+            for _ in 0..300 {
+                ui.handle_event(random_event(&mut rng)).unwrap();
+                // picking from a list of files could open and then save over one of them
+                let lists_files = ui.palette.as_ref().is_some_and(|palette| {
+                    palette
+                        .items
+                        .iter()
+                        .any(|item| !matches!(item.target, PaletteTarget::Action(_)))
+                });
+                if lists_files {
+                    ui.palette = None;
+                }
+                ui.should_exit = false;
+
+                ui.handle_timeouts();
+                ui.source_editor.update_diff();
+                ui.render().unwrap();
+            }
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn keys_are_handled_however_the_terminal_sends_them() {
+        use event::{KeyEventKind, KeyEventState};
+
+        let dedents = [
+            KeyEvent::new(KeyCode::BackTab, KeyModifiers::SHIFT),
+            KeyEvent::new(KeyCode::BackTab, KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Tab, KeyModifiers::SHIFT),
+        ];
+        for key_event in dedents {
+            let mut ui = ui("        x\n");
+            ui.handle_event(Event::Key(key_event)).unwrap();
+            assert_eq!(
+                ui.source_editor.buffer.to_string(),
+                "    x\n",
+                "{key_event:?}"
+            );
+        }
+
+        let deletes = [
+            KeyEvent::new(KeyCode::Delete, KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Delete, KeyModifiers::SHIFT),
+            KeyEvent::new_with_kind(KeyCode::Delete, KeyModifiers::NONE, KeyEventKind::Repeat),
+        ];
+        for key_event in deletes {
+            let mut ui = ui("ab\n");
+            ui.handle_event(Event::Key(key_event)).unwrap();
+            assert_eq!(ui.source_editor.buffer.to_string(), "b\n", "{key_event:?}");
+        }
+
+        let mut select_end = KeyEvent::new(KeyCode::End, KeyModifiers::SHIFT);
+        select_end.state = KeyEventState::CAPS_LOCK;
+        let mut ui = ui("abc\n");
+        ui.handle_event(Event::Key(select_end)).unwrap();
+        assert_eq!(ui.source_editor.copy().unwrap().text, "abc");
+    }
+
+    #[test]
+    fn filtering_replaces_text_with_what_the_command_writes_out() {
+        let mut ui = ui("one\ntwo\nthree\n");
+        ui.execute(Action::SetMark).unwrap();
+        ui.execute(Action::Down).unwrap();
+        ui.execute(Action::Down).unwrap();
+        let filter = |ui: &mut Ui<io::Sink>, command: &str| {
+            ui.execute(Action::FilterToMark).unwrap();
+            for c in command.chars() {
+                let key_event = KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+                ui.handle_event(Event::Key(key_event)).unwrap();
+            }
+            let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+            ui.handle_event(Event::Key(enter)).unwrap();
+        };
+
+        filter(&mut ui, "tr a-z A-Z");
+        assert_eq!(ui.source_editor.buffer.to_string(), "ONE\nTWO\nthree\n");
+        assert!(ui.source_editor.selection.is_none());
+
+        // a command that fails leaves the text as it was
+        ui.execute(Action::SetMark).unwrap();
+        ui.execute(Action::Up).unwrap();
+        filter(&mut ui, "echo oops >&2; exit 1");
+        assert_eq!(ui.source_editor.buffer.to_string(), "ONE\nTWO\nthree\n");
+        assert!(ui.status_message.unwrap().text.ends_with("oops"));
+    }
+
+    #[test]
+    fn tiny_windows_dont_panic() {
+        let mut ui = ui(TEXT);
+        for split in [Action::Split, Action::VerticalSplit] {
+            ui.execute(split).unwrap();
+            for width in 0..25 {
+                for height in 0..8 {
+                    ui.resize(width, height);
+                    ui.render().unwrap();
+                    ui.execute(Action::CommandPalette).unwrap();
+                    ui.render().unwrap();
+                    ui.palette = None;
+                }
+            }
+            ui.execute(Action::CloseSplit).unwrap();
+        }
+    }
+
+    #[test]
+    fn empty_buffer_doesnt_panic() {
+        let mut ui = ui("");
+        for action in Action::ALL {
+            if matches!(
+                action,
+                Action::OpenRecent
+                    | Action::FindFile
+                    | Action::BrowseDirectory
+                    | Action::Quit
+                    // these read and write the real session file
+                    | Action::SaveSession
+                    | Action::RestoreSession
+            ) {
+                continue;
+            }
+            ui.execute(*action).unwrap();
+            ui.palette = None;
+            ui.render().unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_opened_later_are_read_only_too_with_read_only() {
+        let dir = env::temp_dir().join(format!("editor-read-only-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("other.txt");
+        fs::write(&file, "other\n").unwrap();
+
+        let mut ui = ui(TEXT);
+        ui.state_dir = None;
+        ui.read_only_flag = true;
+        assert!(ui.open(file.clone()));
+        ui.handle_event(Event::Key(KeyEvent::new(
+            KeyCode::Char('x'),
+            KeyModifiers::NONE,
+        )))
+        .unwrap();
+        assert_eq!(ui.source_editor.buffer.to_string(), "other\n");
+
+        ui.execute(Action::NewFile).unwrap();
+        ui.handle_event(Event::Key(KeyEvent::new(
+            KeyCode::Char('x'),
+            KeyModifiers::NONE,
+        )))
+        .unwrap();
+        assert_eq!(ui.source_editor.buffer.to_string(), "");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn picking_a_file_with_unsaved_changes_asks_first() {
+        let dir = env::temp_dir().join(format!("editor-open-modified-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("other.txt");
+        fs::write(&file, "other\n").unwrap();
+        let pick = |ui: &mut Ui<io::Sink>| {
+            ui.palette = Some(Palette::files(vec![file.clone()]));
+            ui.handle_event(Event::Key(KeyEvent::new(
+                KeyCode::Enter,
+                KeyModifiers::NONE,
+            )))
+            .unwrap();
+        };
+
+        let mut ui = ui(TEXT);
+        ui.state_dir = None;
+        ui.source_editor.keypress('x');
+        pick(&mut ui);
+        assert!(matches!(ui.char_prompt, Some(CharPrompt::OpenModified(_))));
+
+        // cancelling keeps the changes where they are
+        ui.handle_event(Event::Key(KeyEvent::new(
+            KeyCode::Char('c'),
+            KeyModifiers::NONE,
+        )))
+        .unwrap();
+        assert!(ui.source_editor.modified);
+        assert_eq!(ui.file, None);
+
+        pick(&mut ui);
+        ui.handle_event(Event::Key(KeyEvent::new(
+            KeyCode::Char('d'),
+            KeyModifiers::NONE,
+        )))
+        .unwrap();
+        assert_eq!(ui.file.as_deref(), Some(file.as_path()));
+        assert_eq!(ui.source_editor.buffer.to_string(), "other\n");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn saving_before_quitting_waits_for_a_name() {
+        let dir = env::temp_dir().join(format!("editor-quit-save-as-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut ui = ui(TEXT);
+        ui.state_dir = None;
+        ui.prompt_dir = dir.clone();
+        ui.source_editor.keypress('x');
+        ui.execute(Action::Quit).unwrap();
+        for c in "snew.txt".chars() {
+            ui.handle_event(Event::Key(KeyEvent::new(
+                KeyCode::Char(c),
+                KeyModifiers::NONE,
+            )))
+            .unwrap();
+        }
+        assert!(!ui.should_exit);
+
+        ui.handle_event(Event::Key(KeyEvent::new(
+            KeyCode::Enter,
+            KeyModifiers::NONE,
+        )))
+        .unwrap();
+        assert!(ui.should_exit);
+        assert!(fs::read_to_string(dir.join("new.txt"))
+            .unwrap()
+            .starts_with('x'));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn keys_in_the_config_replace_the_default_bindings() {
+        let config: config::Config =
+            toml::from_str("[keys]\nquit = \"Ctrl+X Ctrl+C\"\ncancel = \"Ctrl+G\"\n").unwrap();
+        let keymap = config.keymap().unwrap();
+
+        let quit = keymap::parse_keys("Ctrl+X Ctrl+C").unwrap();
+        assert!(matches!(
+            keymap.lookup(&quit),
+            keymap::Lookup::Action(Action::Quit)
+        ));
+        let cancel = [KeyEvent::new(KeyCode::Char('g'), KeyModifiers::CONTROL)];
+        assert!(matches!(
+            keymap.lookup(&cancel),
+            keymap::Lookup::Action(Action::Cancel)
+        ));
+        for old in [
+            KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL),
+            KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE),
+        ] {
+            assert!(matches!(keymap.lookup(&[old]), keymap::Lookup::Unbound));
+        }
+
+        let config: config::Config = toml::from_str("[keys]\nfrobnicate = \"Ctrl+Q\"\n").unwrap();
+        assert!(config.keymap().is_err());
+    }
+
+    #[test]
+    fn saving_through_a_symlink_keeps_it() {
+        let dir = env::temp_dir().join(format!("editor-symlink-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        fs::write(&target, "old").unwrap();
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink("target.txt", &link).unwrap();
+
+        write_atomically(&link, b"new").unwrap();
+
+        let is_symlink = fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink();
+        let text = fs::read_to_string(&target).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(is_symlink);
+        assert_eq!(text, "new");
     }
 }
@@ -0,0 +1,294 @@
+//! Keeping most of a huge file on disk, with only the pages of it around where it's being
+//! looked at in the buffer, so that files larger than memory can still be edited.
+//!
+//! The file is split into pages of whole lines as it's read. A page that's paged out again goes
+//! back to being a range of the file if it hasn't been edited, and only pages that have been
+//! edited are kept in memory. Saving writes every page out in order.
+
+use ropey::{Rope, RopeSlice};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+/// About how much of a file is read at a time. Pages go on to the end of the line they'd
+/// otherwise stop in.
+pub const PAGE_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Debug)]
+pub struct Pager {
+    file: File,
+    page_size: usize,
+    /// The pages before the buffer, in order.
+    before: Vec<Page>,
+    /// The pages after the buffer that have been read, with the nearest one last.
+    after: Vec<Page>,
+    /// How many chars of each page in the buffer there are, kept up to date as it's edited.
+    loaded: Vec<Loaded>,
+    /// Where the part of the file that's never been read starts, which is after all the pages.
+    unread: u64,
+    len: u64,
+    chars_before: usize,
+    rows_before: usize,
+    bytes_before: u64,
+    bytes_after: u64,
+}
+
+/// A page that isn't in the buffer.
+#[derive(Debug)]
+struct Page {
+    /// Where it is in the file, unless it's been edited since it was read.
+    disk: Option<Range<u64>>,
+    /// What's in it, if it's been edited.
+    text: String,
+    chars: usize,
+    /// How many lines it has, which is how many newlines it has, since they're whole lines.
+    lines: usize,
+    bytes: u64,
+}
+
+#[derive(Debug)]
+struct Loaded {
+    disk: Option<Range<u64>>,
+    chars: usize,
+}
+
+impl Pager {
+    /// Starts paging `file`, returning the pager and the first page.
+    pub fn new(file: File, len: u64, page_size: usize) -> io::Result<(Self, String)> {
+        let mut pager = Self {
+            file,
+            page_size,
+            before: Vec::new(),
+            after: Vec::new(),
+            loaded: Vec::new(),
+            unread: 0,
+            len,
+            chars_before: 0,
+            rows_before: 0,
+            bytes_before: 0,
+            bytes_after: len,
+        };
+        // even an empty file has a page, so that there's always one in the buffer
+        let text = pager.next_page()?.unwrap_or_default();
+        if pager.loaded.is_empty() {
+            pager.loaded.push(Loaded {
+                disk: None,
+                chars: 0,
+            });
+        }
+
+        Ok((pager, text))
+    }
+
+    pub fn has_next(&self) -> bool {
+        !self.after.is_empty() || self.unread < self.len
+    }
+
+    /// How many chars of the file are paged out before the buffer.
+    pub fn chars_before(&self) -> usize {
+        self.chars_before
+    }
+
+    /// How many rows of the file are paged out before the buffer.
+    pub fn rows_before(&self) -> usize {
+        self.rows_before
+    }
+
+    pub fn bytes_before(&self) -> u64 {
+        self.bytes_before
+    }
+
+    /// How many bytes of the file are after the buffer, whether they've been read or not.
+    pub fn bytes_after(&self) -> u64 {
+        self.bytes_after
+    }
+
+    /// How many chars each page in the buffer has, in order.
+    pub fn pages(&self) -> Vec<usize> {
+        self.loaded.iter().map(|page| page.chars).collect()
+    }
+
+    /// Takes the page just before the buffer, for it to go at the start of it.
+    pub fn previous_page(&mut self) -> io::Result<Option<String>> {
+        let Some(page) = self.before.pop() else {
+            return Ok(None);
+        };
+        let text = match self.text(&page) {
+            Ok(text) => text,
+            Err(e) => {
+                self.before.push(page);
+                return Err(e);
+            }
+        };
+
+        self.chars_before -= page.chars;
+        self.rows_before -= page.lines;
+        self.bytes_before -= page.bytes;
+        self.loaded.insert(
+            0,
+            Loaded {
+                disk: page.disk,
+                chars: page.chars,
+            },
+        );
+        Ok(Some(text))
+    }
+
+    /// Takes the page just after the buffer, reading a new one if they've all been read, for it
+    /// to go at the end of it.
+    pub fn next_page(&mut self) -> io::Result<Option<String>> {
+        let (text, disk) = match self.after.pop() {
+            Some(page) => match self.text(&page) {
+                Ok(text) => (text, page.disk),
+                Err(e) => {
+                    self.after.push(page);
+                    return Err(e);
+                }
+            },
+            None if self.unread < self.len => {
+                let text = self.read_page()?;
+                let disk = self.unread..self.unread + text.len() as u64;
+                self.unread = disk.end;
+                (text, Some(disk))
+            }
+            None => return Ok(None),
+        };
+
+        self.bytes_after -= text.len() as u64;
+        self.loaded.push(Loaded {
+            disk,
+            chars: text.chars().count(),
+        });
+        Ok(Some(text))
+    }
+
+    /// Puts the buffer's first page, whose text is `text`, back with the pages before it.
+    pub fn page_out_first(&mut self, text: RopeSlice) {
+        let loaded = self.loaded.remove(0);
+        let page = Page::new(loaded, text);
+        self.chars_before += page.chars;
+        self.rows_before += page.lines;
+        self.bytes_before += page.bytes;
+        self.before.push(page);
+    }
+
+    /// Puts the buffer's last page, whose text is `text`, back with the pages after it.
+    pub fn page_out_last(&mut self, text: RopeSlice) {
+        let loaded = self.loaded.pop().unwrap();
+        let page = Page::new(loaded, text);
+        self.bytes_after += page.bytes;
+        self.after.push(page);
+    }
+
+    /// Notes that the buffer's chars in `range` have been replaced with `inserted` chars.
+    ///
+    /// The pages that the edit touches, including ones it's just at the edge of, become one,
+    /// so that they still start at the start of a line however the lines were joined up.
+    pub fn edited(&mut self, range: Range<usize>, inserted: usize) {
+        let mut start = 0;
+        let mut touched = Vec::new();
+        for (idx, page) in self.loaded.iter().enumerate() {
+            let end = start + page.chars;
+            if start <= range.end && range.start <= end {
+                touched.push(idx);
+            }
+            start = end;
+        }
+        let (Some(&first), Some(&last)) = (touched.first(), touched.last()) else {
+            return;
+        };
+
+        let chars: usize = self
+            .loaded
+            .drain(first + 1..=last)
+            .map(|page| page.chars)
+            .sum();
+        let page = &mut self.loaded[first];
+        page.chars = page.chars + chars + inserted - range.len();
+        page.disk = None;
+        // an empty page would stop the ones after it being paged out
+        if page.chars == 0 && self.loaded.len() > 1 {
+            self.loaded.remove(first);
+        }
+    }
+
+    /// Writes the whole file as it is now, with `buffer` in place of the pages in it.
+    pub fn write(&self, buffer: &Rope, out: &mut impl Write) -> io::Result<()> {
+        for page in &self.before {
+            self.write_page(page, out)?;
+        }
+        for chunk in buffer.chunks() {
+            out.write_all(chunk.as_bytes())?;
+        }
+        for page in self.after.iter().rev() {
+            self.write_page(page, out)?;
+        }
+        self.copy(self.unread..self.len, out)
+    }
+
+    fn write_page(&self, page: &Page, out: &mut impl Write) -> io::Result<()> {
+        match &page.disk {
+            Some(range) => self.copy(range.clone(), out),
+            None => out.write_all(page.text.as_bytes()),
+        }
+    }
+
+    fn copy(&self, range: Range<u64>, out: &mut impl Write) -> io::Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(range.start))?;
+        let copied = io::copy(&mut file.take(range.end - range.start), out)?;
+        if copied < range.end - range.start {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+
+    fn text(&self, page: &Page) -> io::Result<String> {
+        let Some(range) = &page.disk else {
+            return Ok(page.text.clone());
+        };
+
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(range.start))?;
+        let mut bytes = vec![0; (range.end - range.start) as usize];
+        file.read_exact(&mut bytes)?;
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Reads the next page from the part of the file that hasn't been read yet.
+    fn read_page(&self) -> io::Result<String> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.unread))?;
+        let mut reader = BufReader::new(file);
+        let mut bytes = Vec::new();
+        (&mut reader)
+            .take(self.page_size as u64)
+            .read_to_end(&mut bytes)?;
+        if bytes.last().is_some_and(|byte| *byte != b'\n') {
+            reader.read_until(b'\n', &mut bytes)?;
+        }
+        // the file could have got shorter since it was opened
+        bytes.truncate((self.len - self.unread) as usize);
+        if bytes.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        // pages end at newlines, so they never split a char in two
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl Page {
+    fn new(loaded: Loaded, text: RopeSlice) -> Self {
+        Self {
+            text: match loaded.disk {
+                Some(_) => String::new(),
+                None => text.to_string(),
+            },
+            disk: loaded.disk,
+            chars: text.len_chars(),
+            lines: text.len_lines() - 1,
+            bytes: text.len_bytes() as u64,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Where the cursor was in files that have been open before, so that reopening one
+//! goes back to where it was left.
+//!
+//! Like the list of recent files, errors are ignored, since they're just not worth
+//! getting in the way of editing for.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many files to remember positions in.
+const MAX_LEN: usize = 100;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Position {
+    pub row: usize,
+    pub column: usize,
+    pub scroll: usize,
+}
+
+/// The position remembered for `path` in `state_dir`, which is usually
+/// [`crate::config::state_dir`].
+pub fn get(state_dir: Option<&Path>, path: &Path) -> Option<Position> {
+    let path = path.canonicalize().ok()?;
+    load(state_dir)
+        .into_iter()
+        .find(|(file, _)| *file == path)
+        .map(|(_, position)| position)
+}
+
+/// Remembers `position` for `path`, as the most recently left file.
+pub fn set(state_dir: Option<&Path>, path: &Path, position: Position) {
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+
+    let mut positions = load(state_dir);
+    positions.retain(|(file, _)| *file != path);
+    positions.insert(0, (path, position));
+    positions.truncate(MAX_LEN);
+    save(state_dir, &positions);
+}
+
+fn load(state_dir: Option<&Path>) -> Vec<(PathBuf, Position)> {
+    let text = match path(state_dir).and_then(|path| fs::read_to_string(path).ok()) {
+        Some(text) => text,
+        None => return Vec::new(),
+    };
+
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            let mut number = || fields.next()?.parse().ok();
+            let position = Position {
+                row: number()?,
+                column: number()?,
+                scroll: number()?,
+            };
+            Some((PathBuf::from(fields.next()?), position))
+        })
+        .collect()
+}
+
+fn save(state_dir: Option<&Path>, positions: &[(PathBuf, Position)]) {
+    let path = match path(state_dir) {
+        Some(path) => path,
+        None => return,
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+
+    let mut text = String::new();
+    for (file, position) in positions {
+        if let Some(file) = file.to_str().filter(|file| !file.contains('\n')) {
+            let Position {
+                row,
+                column,
+                scroll,
+            } = position;
+            text.push_str(&format!("{row}\t{column}\t{scroll}\t{file}\n"));
+        }
+    }
+
+    let _ = crate::write_atomically(&path, text.as_bytes());
+}
+
+fn path(state_dir: Option<&Path>) -> Option<PathBuf> {
+    Some(state_dir?.join("positions"))
+}
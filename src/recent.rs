@@ -0,0 +1,68 @@
+//! The list of recently opened files, most recent first, kept between sessions.
+//!
+//! Failing to read or write the list never gets in the way of editing,
+//! so errors here are ignored and the list just starts out empty.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many files to remember.
+const MAX_LEN: usize = 20;
+
+/// The list kept in `state_dir`, which is usually [`crate::config::state_dir`].
+pub fn load(state_dir: Option<&Path>) -> Vec<PathBuf> {
+    let text = match path(state_dir).and_then(|path| fs::read_to_string(path).ok()) {
+        Some(text) => text,
+        None => return Vec::new(),
+    };
+
+    text.lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Moves `path` to the front of the list, adding it if it isn't there already.
+pub fn add(state_dir: Option<&Path>, path: &Path) {
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+
+    let mut files = load(state_dir);
+    files.retain(|file| *file != path);
+    files.insert(0, path);
+    files.truncate(MAX_LEN);
+    save(state_dir, &files);
+}
+
+pub fn remove(state_dir: Option<&Path>, path: &Path) {
+    let mut files = load(state_dir);
+    files.retain(|file| file != path);
+    save(state_dir, &files);
+}
+
+fn save(state_dir: Option<&Path>, files: &[PathBuf]) {
+    let path = match path(state_dir) {
+        Some(path) => path,
+        None => return,
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+
+    let mut text = String::new();
+    for file in files {
+        // a path with a newline in it would turn into two bogus entries
+        if let Some(file) = file.to_str().filter(|file| !file.contains('\n')) {
+            text.push_str(file);
+            text.push('\n');
+        }
+    }
+
+    let _ = crate::write_atomically(&path, text.as_bytes());
+}
+
+fn path(state_dir: Option<&Path>) -> Option<PathBuf> {
+    Some(state_dir?.join("recent_files"))
+}
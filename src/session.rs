@@ -0,0 +1,120 @@
+//! The files that are open, where the cursor is in them and how the window is split, saved
+//! when asked to be so that they can be opened again later with `--session`.
+//!
+//! Unlike the list of recent files, this is only read and written when asked for,
+//! so errors are reported rather than ignored.
+
+use crate::positions::Position;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// The file being edited, unless it hasn't been saved anywhere.
+    pub file: Option<(PathBuf, Position)>,
+    /// The file that was open before it, to switch back to.
+    pub alternate: Option<(PathBuf, Position)>,
+    pub split: Option<Split>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Split {
+    pub vertical: bool,
+    pub focus_first: bool,
+    /// Where the pane without focus is in the file.
+    pub other: Position,
+}
+
+/// The session saved in `state_dir`, which is usually [`crate::config::state_dir`].
+pub fn load(state_dir: Option<&Path>) -> io::Result<Session> {
+    let path = path(state_dir)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+    let text = fs::read_to_string(path)?;
+
+    let mut session = Session::default();
+    for line in text.lines() {
+        let Some((kind, rest)) = line.split_once('\t') else {
+            continue;
+        };
+        // the path goes last in a line, since it's the only thing that could have a tab in it
+        let mut fields = rest.splitn(4, '\t');
+        match kind {
+            "file" => session.file = file(&mut fields),
+            "alternate" => session.alternate = file(&mut fields),
+            "split" => {
+                let mut fields = rest.split('\t');
+                let vertical = fields.next() == Some("vertical");
+                let focus_first = fields.next() != Some("second");
+                session.split = position(&mut fields).map(|other| Split {
+                    vertical,
+                    focus_first,
+                    other,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(session)
+}
+
+fn file<'a>(fields: &mut impl Iterator<Item = &'a str>) -> Option<(PathBuf, Position)> {
+    let position = position(fields)?;
+    Some((PathBuf::from(fields.next()?), position))
+}
+
+fn position<'a>(fields: &mut impl Iterator<Item = &'a str>) -> Option<Position> {
+    let mut number = || fields.next()?.parse().ok();
+    Some(Position {
+        row: number()?,
+        column: number()?,
+        scroll: number()?,
+    })
+}
+
+pub fn save(state_dir: Option<&Path>, session: &Session) -> io::Result<()> {
+    let path = path(state_dir)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut text = String::new();
+    for (kind, file) in [("file", &session.file), ("alternate", &session.alternate)] {
+        let Some((file, position)) = file else {
+            continue;
+        };
+        let Position {
+            row,
+            column,
+            scroll,
+        } = position;
+        // a path with a newline in it would turn into a bogus line
+        if let Some(file) = file.to_str().filter(|file| !file.contains('\n')) {
+            text.push_str(&format!("{kind}\t{row}\t{column}\t{scroll}\t{file}\n"));
+        }
+    }
+    if let Some(split) = &session.split {
+        let direction = if split.vertical {
+            "vertical"
+        } else {
+            "horizontal"
+        };
+        let focus = if split.focus_first { "first" } else { "second" };
+        let Position {
+            row,
+            column,
+            scroll,
+        } = split.other;
+        text.push_str(&format!(
+            "split\t{direction}\t{focus}\t{row}\t{column}\t{scroll}\n"
+        ));
+    }
+
+    crate::write_atomically(&path, text.as_bytes())
+}
+
+fn path(state_dir: Option<&Path>) -> Option<PathBuf> {
+    Some(state_dir?.join("session"))
+}
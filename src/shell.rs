@@ -0,0 +1,40 @@
+//! Running text through shell commands, like vim's `!` filters.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// What `command` writes out when `input` is piped into it, run with `shell -c`.
+/// A command that fails is an error, saying what the command wrote about it.
+pub fn filter(shell: &Path, command: &str, input: &str) -> io::Result<String> {
+    let mut child = Command::new(shell)
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let output = thread::scope(|scope| {
+        // written while the output is being read, since a command can fill up the pipe it
+        // writes to before it's read all of its input
+        scope.spawn(move || {
+            // a command doesn't have to read all of its input, like `head`
+            let _ = stdin.write_all(input.as_bytes());
+        });
+        child.wait_with_output()
+    })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = match stderr.lines().find(|line| !line.trim().is_empty()) {
+            Some(line) => line.trim().to_string(),
+            None => output.status.to_string(),
+        };
+        return Err(io::Error::other(message));
+    }
+
+    String::from_utf8(output.stdout).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
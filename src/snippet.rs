@@ -0,0 +1,81 @@
+//! Parses snippet templates like `fn ${1:name}($2) {\n    $0\n}`.
+//!
+//! `$1`, `${1}` and `${1:default}` are tab stops, visited in order of their numbers, with `$0`
+//! visited last (or the end of the snippet, if there isn't one). `\$` is a literal dollar sign.
+
+use std::ops::Range;
+
+/// The text of the snippet and the chars of each tab stop in it, in the order they're visited.
+pub fn parse(template: &str) -> (String, Vec<Range<usize>>) {
+    let chars: Vec<char> = template.chars().collect();
+    let mut text = String::new();
+    let mut len = 0;
+    let mut stops = Vec::new();
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        let rest = &chars[idx + 1..];
+
+        if chars[idx] == '\\' && rest.first() == Some(&'$') {
+            text.push('$');
+            len += 1;
+            idx += 2;
+        } else if chars[idx] == '$' && rest.first().is_some_and(char::is_ascii_digit) {
+            let (number, digits) = number(rest);
+            stops.push((number, len..len));
+            idx += 1 + digits;
+        } else if let Some((number, default, used)) = braced(chars[idx], rest) {
+            let start = len;
+            text.extend(default);
+            len += default.len();
+            stops.push((number, start..len));
+            idx += 1 + used;
+        } else {
+            text.push(chars[idx]);
+            len += 1;
+            idx += 1;
+        }
+    }
+
+    if !stops.iter().any(|(number, _)| *number == 0) {
+        stops.push((0, len..len));
+    }
+    // the sort is stable, so stops with the same number are visited in the order they appear
+    stops.sort_by_key(|(number, _)| if *number == 0 { usize::MAX } else { *number });
+
+    (text, stops.into_iter().map(|(_, range)| range).collect())
+}
+
+/// Parses `{1}` or `{1:default}` after a `$`,
+/// returning the number, the default text and how many chars were used.
+fn braced(c: char, rest: &[char]) -> Option<(usize, &[char], usize)> {
+    if c != '$' || rest.first() != Some(&'{') {
+        return None;
+    }
+
+    let (number, digits) = number(&rest[1..]);
+    if digits == 0 {
+        return None;
+    }
+
+    let after = 1 + digits;
+    let close = after + rest[after..].iter().position(|c| *c == '}')?;
+    let default = match rest[after] {
+        '}' => &rest[after..after],
+        ':' => &rest[after + 1..close],
+        _ => return None,
+    };
+
+    Some((number, default, close + 1))
+}
+
+/// Parses the digits at the start of `chars`, returning the number and how many there were.
+fn number(chars: &[char]) -> (usize, usize) {
+    let digits = chars.iter().take_while(|c| c.is_ascii_digit()).count();
+    let number = chars[..digits].iter().fold(0usize, |n, c| {
+        n.saturating_mul(10)
+            .saturating_add(c.to_digit(10).unwrap() as usize)
+    });
+
+    (number, digits)
+}
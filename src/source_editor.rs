@@ -0,0 +1,4933 @@
+//! A buffer being edited, along with everything about editing it that doesn't depend on the
+//! terminal it's shown in: the cursors and selections, folds, undo history, searching and so on.
+//!
+//! It's drawn by [`SourceEditor::render`] as lines of [`Span`]s for a frontend to style.
+
+use crate::diff::{self, Hunk};
+use crate::paging::{self, Pager};
+#[cfg(feature = "tree-sitter")]
+use crate::tree;
+use crate::{lsp, snippet, spell, syntax};
+use ropey::{Rope, RopeSlice};
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::iter;
+use std::ops::{ControlFlow, Range};
+
+/// How many columns at the start of each line are used for fold markers.
+const FOLD_GUTTER_WIDTH: usize = 2;
+/// Line numbers are at least this wide, so that the text doesn't move as short files grow.
+const MIN_LINE_NUMBER_DIGITS: usize = 3;
+
+#[derive(Debug)]
+pub struct SourceEditor {
+    pub buffer: Rope,
+    pub width: usize,
+    pub height: usize,
+    pub row: usize,
+    pub column: usize,
+    pub scroll: usize,
+    /// How many columns the view is scrolled to the right.
+    h_scroll: usize,
+    pub selection: Option<Selection>,
+    /// Ranges of rows that are collapsed into their first row, sorted and non-overlapping.
+    folds: Vec<Range<usize>>,
+    /// The lines of the file as of the last commit, to show what's changed since.
+    diff_base: Option<Vec<String>>,
+    hunks: Vec<Hunk>,
+    /// Whether the buffer has been edited since `hunks` was computed.
+    hunks_stale: bool,
+    /// Problems found by a language server, sorted by where they start.
+    diagnostics: Vec<Diagnostic>,
+    pub highlighter: syntax::Highlighter,
+    /// How deeply nested in brackets the start of each row is, as far down as it's been worked
+    /// out, which is cut short whenever a row before the end of it is edited.
+    bracket_depths: Vec<usize>,
+    /// A syntax tree of the buffer, if there's a grammar for its language.
+    #[cfg(feature = "tree-sitter")]
+    pub tree: Option<tree::SyntaxTree>,
+    /// The tab stops left to visit in the snippet that was last expanded.
+    snippet: Option<SnippetStops>,
+    /// The chars that cursors other than the main one are before, sorted and kept up to date
+    /// as the buffer is edited. Typing, deleting and moving happen at all of them.
+    pub cursors: Vec<usize>,
+    /// The char the mark was set before, kept up to date as the buffer is edited, for
+    /// working on the text between it and the cursor without selecting it.
+    mark: Option<usize>,
+    /// The last thing searched for and which way, for searching again.
+    pub last_search: Option<Search>,
+    /// What was selected before each time the selection was expanded, with empty ranges for
+    /// just the cursor, and then what's selected now.
+    expansions: Vec<Range<usize>>,
+    search_matches: Option<SearchMatches>,
+    long_lines: Option<LongLines>,
+    /// Whether an edit is being made at the other cursors after the main one,
+    /// so that it goes into the same undo step.
+    at_other_cursors: bool,
+    pub settings: Settings,
+    /// Whether the buffer has been edited since it was opened or saved.
+    pub modified: bool,
+    /// How many times the buffer has been changed, for telling when it's been edited.
+    pub changes: usize,
+    pub read_only: bool,
+    pub loader: Option<LazyLoader>,
+    /// The rest of a file too big to have all of in memory, for paging in around the view.
+    pager: Option<Pager>,
+    history: History,
+    /// The kind of change made by the last editing command and where it left the cursor,
+    /// so that we know when the next one can be merged into the same undo step.
+    change_end: Option<(ChangeKind, usize)>,
+    last_change: Option<Change>,
+    repeating: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// The chars it covers, kept up to date as the buffer is edited.
+    range: Range<usize>,
+    pub severity: lsp::Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Search {
+    pub query: String,
+    pub forward: bool,
+}
+
+/// How a search went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Found {
+    Match,
+    /// A match, after going around past the end of the buffer.
+    Wrapped,
+    /// There are matches, but none further on, and searching doesn't wrap.
+    End,
+    Nothing,
+}
+
+/// Where the matches for a search are as of some number of changes to the buffer.
+#[derive(Debug)]
+struct SearchMatches {
+    query: String,
+    changes: usize,
+    starts: Vec<usize>,
+}
+
+/// How many lines were over the line length limit, and what that was counted against.
+#[derive(Debug)]
+struct LongLines {
+    changes: usize,
+    limit: usize,
+    tab_width: usize,
+    count: usize,
+}
+
+#[derive(Debug)]
+struct SnippetStops {
+    /// The chars of each tab stop, kept up to date as the buffer is edited.
+    stops: Vec<Range<usize>>,
+    /// The index of the stop that Tab goes to next.
+    next: usize,
+}
+
+/// Preferences that carry over when another file is opened.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// How many columns apart tab stops are, both for showing tabs and for indenting.
+    pub tab_width: usize,
+    /// Whether indenting inserts spaces rather than tabs, with Backspace in the indentation
+    /// deleting back to the previous tab stop.
+    pub soft_tabs: bool,
+    /// Whether Left and Right move a tab stop at a time through indentation made of spaces,
+    /// as if it were tabs.
+    pub soft_tab_movement: bool,
+    /// Whether Enter keeps the line's indentation, indenting further after an opening bracket,
+    /// and closing brackets typed at the start of a line line up with what they close.
+    pub auto_indent: bool,
+    /// Whether to color brackets by how deeply they're nested.
+    pub rainbow_brackets: bool,
+    /// Words like TODO to draw attention to.
+    pub markers: Vec<String>,
+    pub trailing_whitespace: bool,
+    /// How many columns lines can be before the rest of them is marked, if there's a limit.
+    pub line_length_limit: Option<usize>,
+    /// Whether the cursor can move past the ends of lines, with typing there padding them out.
+    pub virtual_space: bool,
+    /// Whether typing replaces the character after the cursor rather than pushing it along,
+    /// with Backspace blanking out the one before it.
+    pub overwrite: bool,
+    pub line_numbers: bool,
+    /// Whether the last column shows where the view is in the buffer and how much of it is in
+    /// view.
+    pub scrollbar: bool,
+    /// How many spaces go on either side of line numbers.
+    pub gutter_padding: usize,
+    /// What goes between the line numbers and the text.
+    pub gutter_separator: Option<char>,
+    /// Whether searching past the last match goes back around to the first.
+    pub search_wraps: bool,
+    /// How many columns wide paragraphs are made when they're reflowed.
+    pub text_width: usize,
+    /// Templates that a word expands into when Tab is pressed after it, by that word.
+    pub snippets: HashMap<String, String>,
+    /// The words to check spelling against, if it's being checked.
+    pub dictionary: Option<spell::Dictionary>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            tab_width: 4,
+            soft_tabs: true,
+            soft_tab_movement: false,
+            auto_indent: true,
+            rainbow_brackets: true,
+            markers: Vec::new(),
+            trailing_whitespace: true,
+            line_length_limit: None,
+            search_wraps: true,
+            text_width: 80,
+            line_numbers: false,
+            scrollbar: false,
+            gutter_padding: 1,
+            gutter_separator: None,
+            virtual_space: false,
+            overwrite: false,
+            snippets: HashMap::new(),
+            dictionary: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Selection {
+    /// Where the selection was started from; the cursor is the other end.
+    anchor: (usize, usize),
+    kind: SelectionKind,
+}
+
+/// How comments are written in a language.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Comments {
+    /// What starts a comment that goes to the end of the line, like `//`.
+    pub line: Option<String>,
+    /// What starts and ends a comment around some text, like `/*` and `*/`.
+    pub block: Option<(String, String)>,
+}
+
+/// A node of the syntax tree to select, relative to the one at the cursor or selection.
+#[derive(Debug, Clone, Copy)]
+pub enum SyntaxNode {
+    Enclosing,
+    NextSibling,
+    PreviousSibling,
+    Function,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionKind {
+    /// Everything from the anchor to the cursor.
+    Char,
+    /// Whole lines, from the anchor's line to the cursor's.
+    Line,
+    /// The rectangle with the anchor and cursor at opposite corners.
+    Block,
+}
+
+#[derive(Debug, Clone)]
+pub struct Clipboard {
+    pub text: String,
+    /// What was selected when the text was copied, which decides how it's pasted.
+    kind: SelectionKind,
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            kind: SelectionKind::Char,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub text: String,
+    pub highlight: Highlight,
+}
+
+/// What a piece of rendered text is, so the UI can decide how to draw it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Highlight {
+    pub selected: bool,
+    /// Whether one of the cursors other than the main one is here.
+    pub cursor: bool,
+    /// Whether this is another occurrence of the word under the cursor.
+    pub occurrence: bool,
+    /// The most severe diagnostic this is part of.
+    pub diagnostic: Option<lsp::Severity>,
+    pub misspelled: bool,
+    /// Whether this is past the line length limit.
+    pub over_limit: bool,
+    pub kind: HighlightKind,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HighlightKind {
+    #[default]
+    Text,
+    Gutter,
+    GutterSeparator,
+    FoldSummary,
+    DiffAdded,
+    DiffModified,
+    DiffRemoved,
+    /// A bracket nested inside this many others.
+    Bracket(usize),
+    Marker,
+    Syntax(syntax::Token),
+    TrailingWhitespace,
+    /// A control character, shown in caret notation so the terminal doesn't act on it.
+    ControlChar,
+}
+
+/// The parts of an editor that can differ between views of the same buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct View {
+    pub row: usize,
+    pub column: usize,
+    pub scroll: usize,
+    pub h_scroll: usize,
+}
+
+/// Every state the buffer has been in since it was opened, as a tree, so that making a change
+/// after undoing doesn't throw away what was undone.
+#[derive(Debug, Default)]
+struct History {
+    /// In the order they were made.
+    steps: Vec<UndoStep>,
+    /// The last step made and not undone, or `None` at the text as it was opened.
+    current: Option<usize>,
+    /// The step that redoing goes to from the text as it was opened.
+    first_redo: Option<usize>,
+}
+
+#[derive(Debug)]
+struct UndoStep {
+    /// The step this one was made after.
+    parent: Option<usize>,
+    /// Which of the steps made after this one redoing goes to: the last one undone, or else the
+    /// last one made.
+    redo: Option<usize>,
+    edits: Vec<Edit>,
+    /// Where the cursor was, with rows counted from the start of the file like edits are.
+    cursor_before: (usize, usize),
+    cursor_after: (usize, usize),
+    /// What was selected before and after, so that undoing or redoing selects it again.
+    selection_before: Option<Selection>,
+    selection_after: Option<Selection>,
+}
+
+#[derive(Debug)]
+struct Edit {
+    /// Counted from the start of the file, rather than the buffer, so that it still means the
+    /// same after paging.
+    start: usize,
+    removed: String,
+    inserted: String,
+}
+
+impl History {
+    fn current_mut(&mut self) -> Option<&mut UndoStep> {
+        self.steps.get_mut(self.current?)
+    }
+
+    fn push(&mut self, mut step: UndoStep) {
+        let parent = self.current;
+        step.parent = parent;
+        self.steps.push(step);
+        self.current = Some(self.steps.len() - 1);
+        self.set_redo(parent, self.steps.len() - 1);
+    }
+
+    fn redo_target(&self) -> Option<usize> {
+        match self.current {
+            Some(idx) => self.steps[idx].redo,
+            None => self.first_redo,
+        }
+    }
+
+    fn set_redo(&mut self, parent: Option<usize>, step: usize) {
+        match parent {
+            Some(idx) => self.steps[idx].redo = Some(step),
+            None => self.first_redo = Some(step),
+        }
+    }
+
+    /// Which state the buffer is in, numbered in the order they were first reached, with 0 being
+    /// the text as it was opened.
+    fn state(&self) -> usize {
+        self.current.map_or(0, |idx| idx + 1)
+    }
+
+    /// The steps to undo and then redo to get from the current state to just after `target`.
+    fn path_to(&self, target: Option<usize>) -> (Vec<usize>, Vec<usize>) {
+        let mut ancestors = vec![target];
+        while let Some(idx) = *ancestors.last().unwrap() {
+            ancestors.push(self.steps[idx].parent);
+        }
+
+        let mut undo = Vec::new();
+        let mut at = self.current;
+        while !ancestors.contains(&at) {
+            let idx = at.unwrap();
+            undo.push(idx);
+            at = self.steps[idx].parent;
+        }
+        let common = ancestors.iter().position(|step| *step == at).unwrap();
+        let redo = ancestors[..common]
+            .iter()
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+
+        (undo, redo)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChangeKind {
+    Insert,
+    DeleteBackward,
+    /// Changes that are never merged with others and can't be repeated.
+    Other,
+}
+
+/// A change that can be repeated at the cursor.
+#[derive(Debug, Clone)]
+enum Change {
+    Insert(String),
+    DeleteBackward(usize),
+}
+
+/// Reads the rest of a large file into the buffer on demand.
+#[derive(Debug)]
+pub struct LazyLoader {
+    reader: BufReader<File>,
+    /// How many bytes of the file have been read into the buffer so far.
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl SourceEditor {
+    pub fn new(buffer: String) -> Self {
+        let buffer = Rope::from_str(&buffer);
+        Self {
+            highlighter: syntax::Highlighter::new(None, buffer.len_lines()),
+            bracket_depths: Vec::new(),
+            #[cfg(feature = "tree-sitter")]
+            tree: None,
+            buffer,
+            width: 0,
+            height: 0,
+            row: 0,
+            column: 0,
+            scroll: 0,
+            h_scroll: 0,
+            selection: None,
+            folds: Vec::new(),
+            diff_base: None,
+            hunks: Vec::new(),
+            hunks_stale: false,
+            diagnostics: Vec::new(),
+            snippet: None,
+            cursors: Vec::new(),
+            mark: None,
+            last_search: None,
+            expansions: Vec::new(),
+            search_matches: None,
+            long_lines: None,
+            at_other_cursors: false,
+            settings: Settings::default(),
+            modified: false,
+            changes: 0,
+            read_only: false,
+            loader: None,
+            pager: None,
+            history: History::default(),
+            change_end: None,
+            last_change: None,
+            repeating: false,
+        }
+    }
+
+    pub fn lazy(reader: BufReader<File>, len: u64) -> Self {
+        Self {
+            loader: Some(LazyLoader {
+                reader,
+                offset: 0,
+                len,
+            }),
+            ..Self::new(String::new())
+        }
+    }
+
+    /// Opens a file too big to read all of, starting with its first page. The rest is paged in
+    /// and out around the view by [`SourceEditor::settle_pages`].
+    pub fn paged(file: File, len: u64) -> io::Result<Self> {
+        Self::paged_by(file, len, paging::PAGE_SIZE)
+    }
+
+    fn paged_by(file: File, len: u64, page_size: usize) -> io::Result<Self> {
+        let (pager, text) = Pager::new(file, len, page_size)?;
+        Ok(Self {
+            pager: Some(pager),
+            ..Self::new(text)
+        })
+    }
+
+    pub fn render(&self) -> (Vec<Vec<Span>>, usize, usize) {
+        let empty = vec![Span {
+            text: "~".to_string(),
+            highlight: Highlight::default(),
+        }];
+        let mut lines = vec![empty; self.height];
+
+        let kind = self.selection.map(|selection| selection.kind);
+        let selection = self.selection_range();
+        let selected_lines = self.selected_lines().unwrap_or(0..0);
+        let (_, columns) = self.block().unwrap_or((0..0, 0..0));
+        let width = self.text_width();
+        let word = self.word_at_cursor();
+        let markers: Vec<Vec<char>> = self
+            .settings
+            .markers
+            .iter()
+            .map(|marker| marker.chars().collect())
+            .filter(|marker: &Vec<char>| !marker.is_empty())
+            .collect();
+        let selected = Highlight {
+            selected: true,
+            ..Highlight::default()
+        };
+
+        // how deeply nested in brackets the start of `bracket_row` is, starting from the
+        // nearest row it's already been worked out for
+        let mut bracket_row = self.scroll.min(self.bracket_depths.len().saturating_sub(1));
+        let mut bracket_depth = self.bracket_depths.get(bracket_row).copied().unwrap_or(0);
+
+        for (idx, row) in self.rows_on_screen() {
+            let line = self.line(row);
+            let line_start = self.buffer.line_to_char(row);
+            // the chars shown, with one more at the end in case it's a tab that's partly in view
+            let start = self
+                .column_at_display(row, self.h_scroll)
+                .min(line.len_chars());
+            let end =
+                (self.column_at_display(row, self.h_scroll + width) + 1).min(line.len_chars());
+
+            let mut text: Vec<char> = line.slice(start..end).chars().collect();
+            let mut highlights = vec![Highlight::default(); text.len()];
+            let chars: Vec<char> = line.chars().collect();
+
+            let tokens = self.tokens(row, &chars);
+            for (columns, token) in &tokens {
+                for column in columns.start.max(start)..columns.end.min(end) {
+                    highlights[column - start].kind = HighlightKind::Syntax(*token);
+                }
+            }
+
+            if let Some(dictionary) = &self.settings.dictionary {
+                for word in spell::words(&chars) {
+                    // in source files, only comments and strings are prose
+                    let prose = self.highlighter.language().is_none()
+                        || in_string_or_comment(&tokens, word.start);
+                    // a word that's still being typed probably isn't finished
+                    let typing = row == self.row && word.end == self.column;
+                    let text: String = chars[word.clone()].iter().collect();
+                    if prose && !typing && !dictionary.contains(&text) {
+                        for column in word.start.max(start)..word.end.min(end) {
+                            highlights[column - start].misspelled = true;
+                        }
+                    }
+                }
+            }
+
+            if self.settings.rainbow_brackets {
+                // brackets in folds still count towards nesting
+                for skipped in bracket_row..row {
+                    bracket_depth = self.nest_row(skipped, bracket_depth);
+                }
+
+                for (column, c) in chars.iter().enumerate() {
+                    if in_string_or_comment(&tokens, column) {
+                        continue;
+                    }
+                    let depth = bracket_depth;
+                    bracket_depth = nest(bracket_depth, *c);
+                    if (start..end).contains(&column) && depth != bracket_depth {
+                        highlights[column - start].kind =
+                            HighlightKind::Bracket(depth.min(bracket_depth));
+                    }
+                }
+
+                bracket_row = row + 1;
+            }
+
+            // in source files, markers like TODO are only left in comments
+            let in_comment = |column: usize| {
+                self.highlighter.language().is_none()
+                    || tokens.iter().any(|(columns, token)| {
+                        columns.contains(&column) && matches!(token, syntax::Token::Comment)
+                    })
+            };
+            for marker in &markers {
+                for marker_start in
+                    word_occurrences(&chars, marker).filter(|start| in_comment(*start))
+                {
+                    for column in marker_start..marker_start + marker.len() {
+                        if (start..end).contains(&column) {
+                            highlights[column - start].kind = HighlightKind::Marker;
+                        }
+                    }
+                }
+            }
+
+            if let Some(limit) = self.settings.line_length_limit {
+                for column in self.column_at_display(row, limit).max(start)..end {
+                    highlights[column - start].over_limit = true;
+                }
+            }
+
+            if self.settings.trailing_whitespace {
+                let trailing_start = chars
+                    .iter()
+                    .rposition(|c| !matches!(c, ' ' | '\t'))
+                    .map_or(0, |idx| idx + 1);
+                // whitespace that's just been typed probably isn't going to stay trailing
+                let typing = row == self.row && self.column >= trailing_start;
+                if !typing {
+                    for column in trailing_start.max(start)..end {
+                        highlights[column - start].kind = HighlightKind::TrailingWhitespace;
+                    }
+                }
+            }
+
+            let line_end = line_start + chars.len();
+            for diagnostic in &self.diagnostics {
+                // empty ranges still need to show up, so they cover the char they're before
+                let range =
+                    diagnostic.range.start..diagnostic.range.end.max(diagnostic.range.start + 1);
+                if range.start > line_end || range.end <= line_start {
+                    continue;
+                }
+                let columns = range.start.max(line_start) - line_start
+                    ..range.end.min(line_end + 1) - line_start;
+                for column in columns.start.max(start)..columns.end.min(end) {
+                    let highlight = &mut highlights[column - start].diagnostic;
+                    if highlight.is_none_or(|severity| diagnostic.severity < severity) {
+                        *highlight = Some(diagnostic.severity);
+                    }
+                }
+            }
+
+            if let Some((word, cursor_word_start)) = &word {
+                for word_start in word_occurrences(&chars, word) {
+                    if row == self.row && word_start == *cursor_word_start {
+                        continue;
+                    }
+                    for column in word_start..word_start + word.len() {
+                        if (start..end).contains(&column) {
+                            highlights[column - start].occurrence = true;
+                        }
+                    }
+                }
+            }
+
+            let line_end = line_start + line.len_chars();
+            for cursor in &self.cursors {
+                if !(line_start..=line_end).contains(cursor) {
+                    continue;
+                }
+                let column = cursor - line_start;
+                if (start..end).contains(&column) {
+                    highlights[column - start].cursor = true;
+                } else if column == line.len_chars() && end == column && text.len() < width {
+                    text.push(' ');
+                    highlights.push(Highlight {
+                        cursor: true,
+                        ..Highlight::default()
+                    });
+                }
+            }
+
+            if kind == Some(SelectionKind::Line) && selected_lines.contains(&row) {
+                // make it clear that whole lines are selected
+                text.resize(width, ' ');
+                highlights = vec![selected; text.len()];
+            } else if kind == Some(SelectionKind::Block) && selected_lines.contains(&row) {
+                for (offset, highlight) in highlights.iter_mut().enumerate() {
+                    let column = start + offset;
+                    // an empty block still shows where typing will go on each line
+                    highlight.selected =
+                        columns.contains(&column) || columns.is_empty() && column == columns.start;
+                }
+            } else if let Some(selection) = &selection {
+                for (offset, highlight) in highlights.iter_mut().enumerate() {
+                    highlight.selected = selection.contains(&(line_start + start + offset));
+                }
+
+                // show when the line break is selected too
+                let newline = line_start + line.len_chars();
+                if selection.contains(&newline) && end == line.len_chars() && text.len() < width {
+                    text.push(' ');
+                    highlights.push(selected);
+                }
+            }
+
+            let view = self.h_scroll..self.h_scroll + width;
+            let (mut text, mut highlights) = expand_tabs(
+                &text,
+                &highlights,
+                self.display_column(row, start),
+                view,
+                self.settings.tab_width,
+            );
+
+            let fold = self.fold_at(row);
+
+            if let Some(fold) = &fold {
+                let hidden = fold.len() - 1;
+                let summary = format!(" … ({hidden} line{})", if hidden == 1 { "" } else { "s" });
+                let room = width.saturating_sub(text.len());
+                let summary = summary.chars().take(room).collect::<Vec<_>>();
+                highlights.extend(summary.iter().map(|_| Highlight {
+                    kind: HighlightKind::FoldSummary,
+                    ..Highlight::default()
+                }));
+                text.extend(summary);
+            }
+
+            let marker = if fold.is_some() {
+                '▸'
+            } else if self.indented_block(row).is_some() {
+                '▾'
+            } else {
+                ' '
+            };
+            let mut line = Vec::new();
+            if self.diff_base.is_some() {
+                let (marker, kind) = match self.diff_marker(row) {
+                    Some(kind @ HighlightKind::DiffAdded) => ('▎', kind),
+                    Some(kind @ HighlightKind::DiffModified) => ('▎', kind),
+                    Some(kind @ HighlightKind::DiffRemoved) if row == 0 => ('▔', kind),
+                    Some(kind) => ('▁', kind),
+                    None => (' ', HighlightKind::Gutter),
+                };
+                line.push(Span {
+                    text: marker.to_string(),
+                    highlight: Highlight {
+                        kind,
+                        ..Highlight::default()
+                    },
+                });
+            }
+            if self.settings.line_numbers {
+                let padding = " ".repeat(self.settings.gutter_padding);
+                let digits = self.line_number_digits();
+                // the row after a final newline isn't a line of its own
+                let number = if row < self.line_count() {
+                    (self.rows_before() + row + 1).to_string()
+                } else {
+                    String::new()
+                };
+                line.push(Span {
+                    text: format!("{padding}{number:>digits$}{padding}"),
+                    highlight: Highlight {
+                        kind: HighlightKind::Gutter,
+                        ..Highlight::default()
+                    },
+                });
+                if let Some(separator) = self.settings.gutter_separator {
+                    line.push(Span {
+                        text: separator.to_string(),
+                        highlight: Highlight {
+                            kind: HighlightKind::GutterSeparator,
+                            ..Highlight::default()
+                        },
+                    });
+                }
+            }
+            line.push(Span {
+                text: format!("{marker} "),
+                highlight: Highlight {
+                    kind: HighlightKind::Gutter,
+                    ..Highlight::default()
+                },
+            });
+            line.extend(spans(&text, &highlights));
+
+            lines[idx] = line;
+        }
+
+        let (x, y) = self.screen_position(self.row, self.column);
+        (lines, x, y)
+    }
+
+    /// The word the cursor is in or just after, and the column it starts at.
+    /// Single characters aren't counted, since they'd match too often to be useful.
+    fn word_at_cursor(&self) -> Option<(Vec<char>, usize)> {
+        let line: Vec<char> = self.line(self.row).chars().collect();
+
+        let mut start = self.column.min(line.len());
+        while start > 0 && is_word_char(line[start - 1]) {
+            start -= 1;
+        }
+        let mut end = start;
+        while end < line.len() && is_word_char(line[end]) {
+            end += 1;
+        }
+
+        (end - start > 1).then(|| (line[start..end].to_vec(), start))
+    }
+
+    /// The columns of the word the cursor is in or just after, if it's one worth spell checking.
+    pub fn spelled_word_at_cursor(&self) -> Option<Range<usize>> {
+        let line: Vec<char> = self.line(self.row).chars().collect();
+        spell::words(&line)
+            .into_iter()
+            .find(|word| word.start <= self.column && self.column <= word.end)
+    }
+
+    /// How much of the width is left for the buffer's contents after the gutter and scrollbar.
+    fn text_width(&self) -> usize {
+        self.width
+            .saturating_sub(self.gutter_width() + usize::from(self.settings.scrollbar))
+    }
+
+    /// The rows of the view that the scrollbar's thumb covers, if there's a scrollbar.
+    pub fn scrollbar_thumb(&self) -> Option<Range<usize>> {
+        if !self.settings.scrollbar || self.height == 0 {
+            return None;
+        }
+
+        // a paged file is measured in bytes, since how many lines it has isn't known
+        let (start, end, len) = match self.view_bytes() {
+            Some((bytes, len)) => (bytes.start, bytes.end, len.max(1)),
+            None => {
+                let lines = self.line_count();
+                let end = self
+                    .rows_on_screen()
+                    .last()
+                    .map_or(self.scroll, |(_, row)| row + 1)
+                    .min(lines);
+                (self.scroll as u64, end as u64, lines as u64)
+            }
+        };
+        let height = self.height as u64;
+        let start = (start * height / len).min(height - 1);
+        // however little is in view, the thumb still shows where it is
+        let end = (end * height).div_ceil(len).clamp(start + 1, height);
+        Some(start as usize..end as usize)
+    }
+
+    /// Scrolls so that the scrollbar's thumb starts at row `y` of the view, such as when it's
+    /// clicked there, keeping the cursor in view.
+    pub fn scroll_to_scrollbar(&mut self, y: usize) {
+        let height = self.height.max(1);
+        let mut row = match self.view_bytes() {
+            Some((_, len)) => self.show_file_byte(y as u64 * len / height as u64),
+            None => {
+                let lines = self.line_count();
+                (y * lines / height).min(lines - 1)
+            }
+        };
+        if let Some(fold) = self.fold_hiding(row) {
+            row = fold.start;
+        }
+        self.scroll = row;
+        self.load_lines(self.scroll + self.height);
+
+        let last = self.rows_on_screen().last().map_or(row, |(_, row)| row);
+        if self.row < self.scroll || self.row > last {
+            self.row = self.row.clamp(self.scroll, last);
+            self.clamp_column();
+            self.h_scroll_to_show_cursor();
+        }
+    }
+
+    fn gutter_width(&self) -> usize {
+        FOLD_GUTTER_WIDTH + usize::from(self.diff_base.is_some()) + self.line_numbers_width()
+    }
+
+    fn line_numbers_width(&self) -> usize {
+        if !self.settings.line_numbers {
+            return 0;
+        }
+
+        self.line_number_digits()
+            + 2 * self.settings.gutter_padding
+            + usize::from(self.settings.gutter_separator.is_some())
+    }
+
+    fn line_number_digits(&self) -> usize {
+        let digits = (self.rows_before() + self.line_count()).to_string().len();
+        digits.max(MIN_LINE_NUMBER_DIGITS)
+    }
+
+    /// Starts showing how the buffer differs from `base`, such as the last commit.
+    pub fn set_diff_base(&mut self, base: &str) {
+        self.diff_base = Some(base.split('\n').map(str::to_string).collect());
+        self.hunks_stale = true;
+        self.update_diff();
+        self.h_scroll_to_show_cursor();
+    }
+
+    /// Recomputes the diff against the base if the buffer has changed since.
+    pub fn update_diff(&mut self) {
+        let base = match &self.diff_base {
+            Some(base) if self.hunks_stale => base,
+            _ => return,
+        };
+
+        let lines: Vec<String> = (0..self.buffer.len_lines())
+            .map(|row| self.line(row).to_string())
+            .collect();
+        self.hunks = diff::diff(base, &lines);
+        self.hunks_stale = false;
+    }
+
+    pub fn next_hunk(&mut self) {
+        self.update_diff();
+        let row = self.row;
+        let next = self.hunk_rows().find(|hunk_row| *hunk_row > row);
+        if let Some(hunk_row) = next {
+            self.jump_to_row(hunk_row);
+        }
+    }
+
+    pub fn previous_hunk(&mut self) {
+        self.update_diff();
+        let row = self.row;
+        let previous = self.hunk_rows().rev().find(|hunk_row| *hunk_row < row);
+        if let Some(hunk_row) = previous {
+            self.jump_to_row(hunk_row);
+        }
+    }
+
+    /// The first row each hunk is marked on in the gutter.
+    fn hunk_rows(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.hunks.iter().map(|hunk| {
+            if hunk.new.is_empty() {
+                hunk.new.start.saturating_sub(1)
+            } else {
+                hunk.new.start
+            }
+        })
+    }
+
+    pub fn set_language(&mut self, language: Option<&'static syntax::Language>) {
+        self.highlighter = syntax::Highlighter::new(language, self.buffer.len_lines());
+        // which brackets are in strings and comments might have changed
+        self.bracket_depths.clear();
+    }
+
+    /// Works out how the lines in view start, so that they can be highlighted.
+    pub fn update_highlighting(&mut self) {
+        let last_row = self
+            .visible_rows(self.scroll)
+            .take(self.height)
+            .last()
+            .unwrap_or(0);
+        self.update_tokens(last_row);
+        // brackets in strings and comments are left out, so this needs the tokens first
+        if self.settings.rainbow_brackets {
+            self.update_bracket_depths(last_row);
+        }
+    }
+
+    /// Brings the syntax highlighting of the rows down to `last_row` up to date.
+    fn update_tokens(&mut self, last_row: usize) {
+        #[cfg(feature = "tree-sitter")]
+        if let Some(tree) = &mut self.tree {
+            return tree.update(&self.buffer);
+        }
+
+        let buffer = &self.buffer;
+        self.highlighter
+            .update(last_row, |row| buffer.line(row).chars().collect());
+    }
+
+    /// Works out how deeply nested in brackets the rows down to `last_row` start, carrying on
+    /// from the last row that's known.
+    fn update_bracket_depths(&mut self, last_row: usize) {
+        if self.bracket_depths.is_empty() {
+            self.bracket_depths.push(0);
+        }
+        while self.bracket_depths.len() <= last_row {
+            let row = self.bracket_depths.len() - 1;
+            let depth = self.nest_row(row, self.bracket_depths[row]);
+            self.bracket_depths.push(depth);
+        }
+    }
+
+    /// How deeply nested in brackets the end of `row` is when its start is nested `depth` deep,
+    /// leaving out brackets in strings and comments.
+    fn nest_row(&self, row: usize, depth: usize) -> usize {
+        let chars: Vec<char> = self.line(row).chars().collect();
+        let tokens = self.tokens(row, &chars);
+        chars
+            .iter()
+            .enumerate()
+            .filter(|(column, _)| !in_string_or_comment(&tokens, *column))
+            .fold(depth, |depth, (_, c)| nest(depth, *c))
+    }
+
+    /// The syntax highlighting of `row`, whose chars are `line`.
+    fn tokens(&self, row: usize, line: &[char]) -> Vec<(Range<usize>, syntax::Token)> {
+        #[cfg(feature = "tree-sitter")]
+        if let Some(tree) = &self.tree {
+            return tree.line(&self.buffer, row);
+        }
+
+        self.highlighter.line(row, line)
+    }
+
+    /// Selects the smallest word, quoted string, brackets, line or paragraph around the selection
+    /// or cursor, or the whole buffer. Returns whether there was anything bigger to select.
+    pub fn expand_selection(&mut self) -> bool {
+        self.load_all();
+        let current = self
+            .selection_range()
+            .unwrap_or(self.cursor_char()..self.cursor_char());
+        // anything else since the last expansion starts again from here
+        if self.expansions.last() != Some(&current) {
+            self.expansions = vec![current.clone()];
+        }
+
+        let Some(found) = self
+            .enclosing_units(current.clone())
+            .into_iter()
+            .filter(|unit| unit.start <= current.start && current.end <= unit.end)
+            .filter(|unit| unit.len() > current.len())
+            .min_by_key(|unit| unit.len())
+        else {
+            return false;
+        };
+
+        self.expansions.push(found.clone());
+        self.select_chars(found);
+        true
+    }
+
+    /// Goes back to what was selected before the selection was last expanded.
+    pub fn shrink_selection(&mut self) -> bool {
+        let current = self
+            .selection_range()
+            .unwrap_or(self.cursor_char()..self.cursor_char());
+        if self.expansions.len() < 2 || self.expansions.last() != Some(&current) {
+            return false;
+        }
+
+        self.expansions.pop();
+        let previous = self.expansions.last().unwrap().clone();
+        if previous.is_empty() {
+            self.jump_to_char(previous.start);
+        } else {
+            self.select_chars(previous);
+        }
+        true
+    }
+
+    /// The ranges that could be selected around `range` when expanding it, in no order.
+    fn enclosing_units(&mut self, range: Range<usize>) -> Vec<Range<usize>> {
+        let mut in_line = Vec::new();
+        let row = self.buffer.char_to_line(range.start);
+        let line_start = self.buffer.line_to_char(row);
+        let line: Vec<char> = self.line(row).chars().collect();
+        let columns = range.start - line_start..range.end - line_start;
+        if columns.end <= line.len() {
+            if line[columns.clone()].iter().all(|c| is_word_char(*c)) {
+                let mut word = columns.clone();
+                while word.start > 0 && is_word_char(line[word.start - 1]) {
+                    word.start -= 1;
+                }
+                while word.end < line.len() && is_word_char(line[word.end]) {
+                    word.end += 1;
+                }
+                in_line.push(word);
+            }
+
+            for quote in ['"', '\'', '`'] {
+                let quotes: Vec<usize> =
+                    (0..line.len()).filter(|idx| line[*idx] == quote).collect();
+                let around = quotes
+                    .chunks_exact(2)
+                    .find(|pair| pair[0] < columns.start && columns.end <= pair[1]);
+                if let Some(&[open, close]) = around {
+                    in_line.push(open + 1..close);
+                    in_line.push(open..close + 1);
+                }
+            }
+
+            let indent = line.iter().take_while(|c| c.is_whitespace()).count();
+            in_line.push(indent..line.len());
+            in_line.push(0..line.len());
+        }
+        let mut units: Vec<Range<usize>> = in_line
+            .into_iter()
+            .map(|unit| line_start + unit.start..line_start + unit.end)
+            .collect();
+        // a paged file's buffer is only part of it
+        if self.pager.is_none() {
+            units.push(0..self.buffer.len_chars());
+        }
+
+        if let Some(brackets) = self.enclosing_brackets(range.clone()) {
+            units.push(brackets.start + 1..brackets.end - 1);
+            units.push(brackets);
+        }
+
+        let end_row = self.buffer.char_to_line(range.end);
+        if !self.is_blank(row) {
+            let mut rows = row..end_row + 1;
+            while rows.start > 0 && !self.is_blank(rows.start - 1) {
+                rows.start -= 1;
+            }
+            while rows.end < self.buffer.len_lines() && !self.is_blank(rows.end) {
+                rows.end += 1;
+            }
+            let last = rows.end - 1;
+            units.push(
+                self.buffer.line_to_char(rows.start)
+                    ..self.buffer.line_to_char(last) + self.line_len(last),
+            );
+        }
+
+        #[cfg(feature = "tree-sitter")]
+        if let Some(tree) = &mut self.tree {
+            tree.update(&self.buffer);
+            units.extend(tree.enclosing_node(&self.buffer, range));
+        }
+
+        units
+    }
+
+    /// The innermost brackets around `range`, including the brackets themselves.
+    fn enclosing_brackets(&self, range: Range<usize>) -> Option<Range<usize>> {
+        let mut depth = 0usize;
+        let mut chars = self.buffer.chars_at(range.start);
+        let mut start = range.start;
+        loop {
+            let c = chars.prev()?;
+            start -= 1;
+            match c {
+                ')' | ']' | '}' => depth += 1,
+                '(' | '[' | '{' if depth == 0 => break,
+                '(' | '[' | '{' => depth -= 1,
+                _ => {}
+            }
+        }
+
+        for (end, c) in (range.end..).zip(self.buffer.chars_at(range.end)) {
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' if depth == 0 => return Some(start..end + 1),
+                ')' | ']' | '}' => depth -= 1,
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Selects the given chars, with the cursor at the start.
+    fn select_chars(&mut self, range: Range<usize>) {
+        self.jump_to_char(range.end);
+        let anchor = (self.row, self.column);
+        self.jump_to_char(range.start);
+        self.selection = Some(Selection {
+            anchor,
+            kind: SelectionKind::Char,
+        });
+    }
+
+    /// Selects a node of the syntax tree relative to the one at the cursor or selection,
+    /// with the cursor at its start.
+    #[cfg(feature = "tree-sitter")]
+    pub fn select_syntax_node(&mut self, node: SyntaxNode) -> Result<(), &'static str> {
+        let range = match self.selection_ends() {
+            Some((start, end)) => self.position_to_char(start)..self.position_to_char(end),
+            None => self.cursor_char()..self.cursor_char(),
+        };
+        let tree = self.tree.as_mut().ok_or("no syntax tree for this file")?;
+        tree.update(&self.buffer);
+
+        let found = match node {
+            SyntaxNode::Enclosing => tree.enclosing_node(&self.buffer, range),
+            SyntaxNode::NextSibling => tree.next_sibling(&self.buffer, range),
+            SyntaxNode::PreviousSibling => tree.previous_sibling(&self.buffer, range),
+            SyntaxNode::Function => tree.function(&self.buffer, range),
+        };
+        let found = found.ok_or(match node {
+            SyntaxNode::Enclosing => "nothing encloses this",
+            SyntaxNode::NextSibling => "no next sibling",
+            SyntaxNode::PreviousSibling => "no previous sibling",
+            SyntaxNode::Function => "not inside a function",
+        })?;
+
+        self.select_chars(found);
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "tree-sitter"))]
+    pub fn select_syntax_node(&mut self, _node: SyntaxNode) -> Result<(), &'static str> {
+        Err("the editor was built without tree-sitter")
+    }
+
+    pub fn set_diagnostics(&mut self, diagnostics: Vec<lsp::Diagnostic>) {
+        let to_char = |(row, column): (usize, usize)| {
+            if row >= self.buffer.len_lines() {
+                return self.buffer.len_chars();
+            }
+            let line = self.line(row).to_string();
+            self.buffer.line_to_char(row) + lsp::char_column(&line, column)
+        };
+
+        self.diagnostics = diagnostics
+            .into_iter()
+            .map(|diagnostic| Diagnostic {
+                range: to_char(diagnostic.start)
+                    ..to_char(diagnostic.end).max(to_char(diagnostic.start)),
+                severity: diagnostic.severity,
+                message: diagnostic.message,
+            })
+            .collect();
+        self.diagnostics
+            .sort_by_key(|diagnostic| diagnostic.range.start);
+    }
+
+    /// The most severe diagnostic the cursor is on, if any.
+    pub fn diagnostic_at_cursor(&self) -> Option<&Diagnostic> {
+        let cursor = self.cursor_char();
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| {
+                diagnostic.range.contains(&cursor) || diagnostic.range.start == cursor
+            })
+            .min_by_key(|diagnostic| diagnostic.severity)
+    }
+
+    pub fn next_diagnostic(&mut self) {
+        let cursor = self.cursor_char();
+        let next = self.diagnostics.iter().find(|d| d.range.start > cursor);
+        if let Some(start) = next.map(|diagnostic| diagnostic.range.start) {
+            self.jump_to_char(start);
+        }
+    }
+
+    pub fn previous_diagnostic(&mut self) {
+        let cursor = self.cursor_char();
+        let previous = self
+            .diagnostics
+            .iter()
+            .rev()
+            .find(|d| d.range.start < cursor);
+        if let Some(start) = previous.map(|diagnostic| diagnostic.range.start) {
+            self.jump_to_char(start);
+        }
+    }
+
+    pub fn jump_to_char(&mut self, idx: usize) {
+        self.selection = None;
+        self.set_cursor_char(idx);
+        let row = self.row;
+        // a fold would hide where we've jumped to
+        self.folds
+            .retain(|fold| !(fold.start < row && row < fold.end));
+        self.scroll_to_show_cursor();
+        self.h_scroll_to_show_cursor();
+    }
+
+    pub fn jump_to_row(&mut self, row: usize) {
+        self.row = row;
+        self.column = 0;
+        self.scroll_to_show_cursor();
+    }
+
+    /// How `row` has changed from the diff base, given as the highlight to show it with.
+    fn diff_marker(&self, row: usize) -> Option<HighlightKind> {
+        self.hunks.iter().find_map(|hunk| {
+            if hunk.new.contains(&row) {
+                Some(if hunk.old.is_empty() {
+                    HighlightKind::DiffAdded
+                } else {
+                    HighlightKind::DiffModified
+                })
+            } else if hunk.new.is_empty() && hunk.new.start.max(1) - 1 == row {
+                // removed lines are marked on the line before where they were
+                Some(HighlightKind::DiffRemoved)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.scroll_to_show_cursor();
+    }
+
+    pub fn view(&self) -> View {
+        View {
+            row: self.row,
+            column: self.column,
+            scroll: self.scroll,
+            h_scroll: self.h_scroll,
+        }
+    }
+
+    pub fn set_view(&mut self, view: View) {
+        // the buffer might have been edited from another view since this one was shown
+        self.row = view.row.min(self.buffer.len_lines() - 1);
+        self.column = view.column;
+        self.scroll = view.scroll;
+        self.h_scroll = view.h_scroll;
+        self.clamp_column();
+        self.scroll_to_show_cursor();
+    }
+
+    /// The view with its rows counted from the start of the file, which in a paged file isn't
+    /// where the buffer starts, for remembering where it was.
+    pub fn file_view(&self) -> View {
+        let rows = self.rows_before();
+        View {
+            row: rows + self.row,
+            scroll: rows + self.scroll,
+            ..self.view()
+        }
+    }
+
+    /// Goes back to a view from [`SourceEditor::file_view`], reading or paging as far as it.
+    pub fn set_file_view(&mut self, view: View) {
+        let row = self.show_file_row(view.row);
+        self.load_lines(row + self.height);
+        let scroll = view.scroll.saturating_sub(self.rows_before());
+        self.set_view(View {
+            row,
+            scroll,
+            ..view
+        });
+    }
+
+    /// Temporarily switches to `view` at the given size, such as to render another pane.
+    pub fn with_view<T>(
+        &mut self,
+        view: &mut View,
+        width: usize,
+        height: usize,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let (current, current_width, current_height) = (self.view(), self.width, self.height);
+
+        self.resize(width, height);
+        self.set_view(*view);
+        let result = f(self);
+        *view = self.view();
+
+        self.resize(current_width, current_height);
+        self.set_view(current);
+
+        result
+    }
+
+    pub fn keypress(&mut self, c: char) {
+        if self.read_only {
+            return;
+        }
+
+        if self.block().is_some() {
+            self.block_keypress(c);
+            return;
+        }
+
+        self.begin_change(ChangeKind::Insert);
+        self.delete_selection();
+        if self.settings.auto_indent && matches!(c, ')' | ']' | '}') {
+            self.line_up_closer();
+        }
+        if self.settings.overwrite && self.column < self.line_len(self.row) {
+            let idx = self.cursor_char();
+            self.edit(idx..idx + 1, c.encode_utf8(&mut [0; 4]));
+        } else {
+            // past the end of the line in virtual space, this pads it out to the cursor
+            self.insert_at_column(self.row, self.column, c.encode_utf8(&mut [0; 4]));
+        }
+        self.column += 1;
+        self.end_change(ChangeKind::Insert);
+        // replacing a selection can move the cursor back above the view
+        self.scroll_to_show_cursor();
+        self.h_scroll_to_show_cursor();
+    }
+
+    pub fn backspace(&mut self) {
+        if self.read_only {
+            return;
+        }
+
+        if self.block().is_some() {
+            self.block_backspace();
+            return;
+        }
+
+        if self.selection.is_some() {
+            self.begin_change(ChangeKind::Other);
+            self.delete_selection();
+            self.end_change(ChangeKind::Other);
+            self.scroll_to_show_cursor();
+            return;
+        }
+
+        if self.column == 0 && self.row == 0 {
+            return;
+        }
+        if self.column > self.line_len(self.row) {
+            // there's nothing to delete in virtual space
+            self.column -= 1;
+            self.h_scroll_to_show_cursor();
+            return;
+        }
+
+        self.begin_change(ChangeKind::DeleteBackward);
+
+        // at the start of a line this removes the newline at the end of the previous line
+        let idx = self.cursor_char();
+        let len = self.line_len(self.row);
+        if self.settings.overwrite && self.column > 0 && self.column < len {
+            // the rest of the line stays where it is, so what's deleted is left as a space
+            self.column -= 1;
+            self.edit(idx - 1..idx, " ");
+        } else if self.column == 0 {
+            self.row -= 1;
+            self.column = self.line_len(self.row);
+            self.edit(idx - 1..idx, "");
+        } else {
+            let len = self.indent_to_delete();
+            self.column -= len;
+            self.edit(idx - len..idx, "");
+        }
+
+        self.end_change(ChangeKind::DeleteBackward);
+        self.scroll_to_show_cursor();
+    }
+
+    /// Deletes the character after the cursor, or the selection like Backspace does.
+    pub fn delete(&mut self) {
+        if self.read_only {
+            return;
+        }
+
+        match self.block() {
+            Some((_, columns)) if columns.is_empty() => return,
+            _ if self.selection.is_some() => return self.backspace(),
+            _ => {}
+        }
+
+        // at the end of a line this joins the next line onto it
+        let idx = self.cursor_char();
+        if idx == self.buffer.len_chars() {
+            return;
+        }
+
+        self.begin_change(ChangeKind::Other);
+        self.edit(idx..idx + 1, "");
+        self.end_change(ChangeKind::Other);
+        self.scroll_to_show_cursor();
+    }
+
+    pub fn enter(&mut self) {
+        if self.read_only {
+            return;
+        }
+
+        self.begin_change(ChangeKind::Insert);
+        self.delete_selection();
+        let indent = if self.settings.auto_indent {
+            self.new_line_indent()
+        } else {
+            String::new()
+        };
+        let idx = self.cursor_char();
+        self.edit(idx..idx, &format!("\n{indent}"));
+        self.row += 1;
+        self.column = indent.chars().count();
+        self.end_change(ChangeKind::Insert);
+        self.h_scroll_to_show_cursor();
+        self.scroll_to_show_cursor();
+    }
+
+    /// The indentation for a line broken off at the cursor: the same as the cursor's line,
+    /// and a level more after an opening bracket.
+    fn new_line_indent(&self) -> String {
+        let before: String = self.line(self.row).chars().take(self.column).collect();
+        let mut indent: String = before
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect();
+        if before.trim_end().ends_with(['(', '[', '{']) {
+            if self.settings.soft_tabs {
+                indent.push_str(&" ".repeat(self.settings.tab_width));
+            } else {
+                indent.push('\t');
+            }
+        }
+
+        indent
+    }
+
+    /// Gives the cursor's line the same indentation as the line with the bracket that a closing
+    /// bracket typed at the cursor would close, if there's only indentation before the cursor.
+    fn line_up_closer(&mut self) {
+        let line = self.line(self.row);
+        let in_indent = self.column != 0
+            && self.column <= line.len_chars()
+            && line
+                .chars()
+                .take(self.column)
+                .all(|c| c == ' ' || c == '\t');
+        if !in_indent {
+            return;
+        }
+
+        let line_start = self.buffer.line_to_char(self.row);
+        let mut depth = 0usize;
+        let mut chars = self.buffer.chars_at(line_start);
+        let mut idx = line_start;
+        let opener = loop {
+            let Some(c) = chars.prev() else {
+                return;
+            };
+            idx -= 1;
+            match c {
+                ')' | ']' | '}' => depth += 1,
+                '(' | '[' | '{' if depth == 0 => break idx,
+                '(' | '[' | '{' => depth -= 1,
+                _ => {}
+            }
+        };
+
+        let indent: String = self
+            .line(self.buffer.char_to_line(opener))
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect();
+        self.edit(line_start..line_start + self.column, &indent);
+        self.column = indent.chars().count();
+    }
+
+    /// Inserts spaces up to the next tab stop, or indents the selected lines.
+    pub fn tab(&mut self) {
+        if self.read_only {
+            return;
+        }
+
+        if self.next_tab_stop() || self.expand_snippet() {
+            return;
+        }
+
+        if self.selection.is_some() {
+            self.indent();
+            return;
+        }
+
+        if !self.settings.soft_tabs {
+            self.keypress('\t');
+            return;
+        }
+
+        let spaces = self.settings.tab_width - self.column % self.settings.tab_width;
+        for _ in 0..spaces {
+            self.keypress(' ');
+        }
+    }
+
+    /// How many chars Backspace deletes before the cursor: back to the previous tab stop
+    /// if it's in indentation made of spaces, and otherwise just one.
+    fn indent_to_delete(&self) -> usize {
+        let in_indent = self
+            .line(self.row)
+            .chars()
+            .take(self.column)
+            .all(|c| c == ' ');
+        if self.settings.soft_tabs && in_indent && self.column <= self.line_len(self.row) {
+            (self.column - 1) % self.settings.tab_width + 1
+        } else {
+            1
+        }
+    }
+
+    /// Expands the word before the cursor if it's a snippet's trigger,
+    /// returning whether it was.
+    fn expand_snippet(&mut self) -> bool {
+        if self.selection.is_some() {
+            return false;
+        }
+
+        let chars: Vec<char> = self.line(self.row).chars().take(self.column).collect();
+        let trigger_len = chars.iter().rev().take_while(|c| is_word_char(**c)).count();
+        let trigger: String = chars[chars.len() - trigger_len..].iter().collect();
+        let template = match self.settings.snippets.get(&trigger) {
+            Some(template) if trigger_len > 0 => template,
+            _ => return false,
+        };
+
+        let (text, stops) = snippet::parse(template);
+        // continuation lines line up with the line the snippet was expanded on
+        let indent: String = chars
+            .iter()
+            .take_while(|c| matches!(c, ' ' | '\t'))
+            .collect();
+        let shift = |idx| idx + line_breaks_before(&text, idx) * indent.chars().count();
+        let stops: Vec<_> = stops
+            .into_iter()
+            .map(|stop| shift(stop.start)..shift(stop.end))
+            .collect();
+        let text = text.replace('\n', &format!("\n{indent}"));
+
+        self.begin_change(ChangeKind::Other);
+        let end = self.cursor_char();
+        let start = end - trigger_len;
+        self.edit(start..end, &text);
+        self.set_cursor_char(start + text.chars().count());
+        self.end_change(ChangeKind::Other);
+
+        let stops = stops
+            .into_iter()
+            .map(|stop| start + stop.start..start + stop.end)
+            .collect();
+        self.snippet = Some(SnippetStops { stops, next: 0 });
+        self.next_tab_stop();
+
+        true
+    }
+
+    /// Moves to the next tab stop of the last snippet, returning whether there was one.
+    fn next_tab_stop(&mut self) -> bool {
+        let snippet = match &mut self.snippet {
+            Some(snippet) if snippet.next < snippet.stops.len() => snippet,
+            _ => return false,
+        };
+
+        let stop = snippet.stops[snippet.next].clone();
+        snippet.next += 1;
+        if snippet.next == snippet.stops.len() {
+            self.snippet = None;
+        }
+        self.select_tab_stop(stop);
+
+        true
+    }
+
+    fn previous_tab_stop(&mut self) -> bool {
+        let snippet = match &mut self.snippet {
+            Some(snippet) if snippet.next >= 2 => snippet,
+            _ => return false,
+        };
+
+        snippet.next -= 1;
+        let stop = snippet.stops[snippet.next - 1].clone();
+        self.select_tab_stop(stop);
+
+        true
+    }
+
+    /// Selects the placeholder text of a tab stop, so that typing replaces it.
+    fn select_tab_stop(&mut self, stop: Range<usize>) {
+        let stop = stop.start.min(self.buffer.len_chars())..stop.end.min(self.buffer.len_chars());
+        self.set_cursor_char(stop.start);
+        self.selection = (!stop.is_empty()).then_some(Selection {
+            anchor: (self.row, self.column),
+            kind: SelectionKind::Char,
+        });
+        self.set_cursor_char(stop.end);
+        self.scroll_to_show_cursor();
+        self.h_scroll_to_show_cursor();
+    }
+
+    fn indent(&mut self) {
+        let rows = self.selected_lines().unwrap_or(self.row..self.row + 1);
+        let (indent, width) = if self.settings.soft_tabs {
+            (" ".repeat(self.settings.tab_width), self.settings.tab_width)
+        } else {
+            ("\t".to_string(), 1)
+        };
+
+        self.begin_change(ChangeKind::Other);
+        for row in rows.clone() {
+            // don't leave trailing whitespace on empty lines
+            if self.line_len(row) != 0 {
+                let idx = self.buffer.line_to_char(row);
+                self.edit(idx..idx, &indent);
+            }
+        }
+        self.shift_columns(rows, width as isize);
+        self.end_change(ChangeKind::Other);
+        // indenting folded lines opens them, which can push the cursor off the bottom
+        self.scroll_to_show_cursor();
+    }
+
+    /// Adds `amount` to the number the cursor is on or before on its line, keeping any leading
+    /// zeros, and returns whether there was one.
+    pub fn add_to_number(&mut self, amount: i128) -> bool {
+        let line: Vec<char> = self.line(self.row).chars().collect();
+        let Some(digits) = number_at(&line, self.column) else {
+            return false;
+        };
+
+        let negative = digits.start > 0 && line[digits.start - 1] == '-';
+        let text: String = line[digits.clone()].iter().collect();
+        let Ok(magnitude) = text.parse::<i128>() else {
+            return false;
+        };
+        let value = if negative { -magnitude } else { magnitude };
+        let new = value.saturating_add(amount);
+
+        // keep numbers like 007 the same width
+        let width = if text.len() > 1 && text.starts_with('0') {
+            text.len()
+        } else {
+            0
+        };
+        let sign = if new < 0 { "-" } else { "" };
+        let new_text = format!("{sign}{:0width$}", new.unsigned_abs());
+
+        let start = if negative {
+            digits.start - 1
+        } else {
+            digits.start
+        };
+        self.replace_columns(start..digits.end, &new_text);
+        // leave the cursor on the number, so that it can be changed again
+        self.column -= 1;
+        self.h_scroll_to_show_cursor();
+
+        true
+    }
+
+    /// Pads `rows` with spaces so that the first `delimiter` on each of them lines up.
+    pub fn align(&mut self, rows: Range<usize>, delimiter: char) {
+        if self.read_only {
+            return;
+        }
+
+        let positions: Vec<(usize, usize)> = rows
+            .filter_map(|row| {
+                let column = self.line(row).chars().position(|c| c == delimiter)?;
+                Some((row, column))
+            })
+            .collect();
+        let Some(target) = positions
+            .iter()
+            .map(|(row, column)| self.display_column(*row, *column))
+            .max()
+        else {
+            return;
+        };
+
+        self.begin_change(ChangeKind::Other);
+        for (row, column) in positions {
+            let padding = target - self.display_column(row, column);
+            if padding != 0 {
+                let idx = self.buffer.line_to_char(row) + column;
+                self.edit(idx..idx, &" ".repeat(padding));
+            }
+        }
+        self.end_change(ChangeKind::Other);
+        self.clamp_column();
+        self.h_scroll_to_show_cursor();
+    }
+
+    /// Wraps the selection in a block comment, or comments out the selected lines or the
+    /// cursor's line, or undoes either if they're already commented out.
+    pub fn toggle_comment(&mut self, comments: &Comments) -> Result<(), &'static str> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        let mut rows = self.selected_lines().unwrap_or(self.row..self.row + 1);
+        let chars = match (
+            self.selection.map(|selection| selection.kind),
+            self.selection_ends(),
+        ) {
+            // selecting from the start of one line to the start of another is selecting lines
+            (Some(SelectionKind::Char), Some(((_, 0), (end, 0)))) => {
+                rows.end = end.max(rows.start + 1);
+                None
+            }
+            (Some(SelectionKind::Char), _) => self.selection_range(),
+            _ => None,
+        };
+
+        match (&comments.line, &comments.block, chars) {
+            (_, Some((start, end)), Some(chars)) => self.toggle_block_comment(chars, start, end),
+            (Some(line), _, _) => self.toggle_line_comment(rows, line),
+            // with only block comments, one goes around each of the lines
+            (None, Some((start, end)), None) => {
+                let last = rows.end - 1;
+                let indent = self
+                    .line(rows.start)
+                    .chars()
+                    .take_while(|c| c.is_whitespace());
+                let chars = self.buffer.line_to_char(rows.start) + indent.count()
+                    ..self.buffer.line_to_char(last) + self.line_len(last);
+                self.toggle_block_comment(chars, start, end);
+            }
+            (None, None, _) => return Err("this kind of file doesn't have comments"),
+        }
+
+        self.clamp_column();
+        self.h_scroll_to_show_cursor();
+        Ok(())
+    }
+
+    /// Puts `start` and `end` around `chars`, or takes them away if they're
+    /// already there, leaving what's between them selected.
+    fn toggle_block_comment(&mut self, chars: Range<usize>, start: &str, end: &str) {
+        let (start_len, end_len) = (start.chars().count(), end.chars().count());
+        let is = |range: Range<usize>, text: &str| {
+            range.end <= self.buffer.len_chars() && self.buffer.slice(range) == text
+        };
+
+        let around = chars.start >= start_len
+            && is(chars.start - start_len..chars.start, start)
+            && is(chars.end..chars.end + end_len, end);
+        let inside = chars.len() >= start_len + end_len
+            && is(chars.start..chars.start + start_len, start)
+            && is(chars.end - end_len..chars.end, end);
+
+        self.begin_change(ChangeKind::Other);
+        let selected = if around {
+            self.edit(chars.end..chars.end + end_len, "");
+            self.edit(chars.start - start_len..chars.start, "");
+            chars.start - start_len..chars.end - start_len
+        } else if inside {
+            self.edit(chars.end - end_len..chars.end, "");
+            self.edit(chars.start..chars.start + start_len, "");
+            chars.start..chars.end - start_len - end_len
+        } else {
+            self.edit(chars.end..chars.end, end);
+            self.edit(chars.start..chars.start, start);
+            chars.start..chars.end + start_len + end_len
+        };
+        self.select_chars(selected);
+        self.end_change(ChangeKind::Other);
+    }
+
+    /// Puts `marker` at the start of the text on each of `rows`, lined up with the least indented
+    /// one, or takes it away if they already all start with it. Blank lines are left alone.
+    fn toggle_line_comment(&mut self, rows: Range<usize>, marker: &str) {
+        let rows: Vec<(usize, usize)> = rows
+            .filter(|row| !self.is_blank(*row))
+            .map(|row| {
+                let indent = self.line(row).chars().take_while(|c| c.is_whitespace());
+                (row, indent.count())
+            })
+            .collect();
+        let Some(min_indent) = rows.iter().map(|(_, indent)| *indent).min() else {
+            return;
+        };
+        let commented = rows
+            .iter()
+            .all(|(row, _)| self.line(*row).to_string().trim_start().starts_with(marker));
+
+        self.begin_change(ChangeKind::Other);
+        for (row, indent) in rows {
+            let line_start = self.buffer.line_to_char(row);
+            if commented {
+                let mut len = marker.chars().count();
+                if self.line(row).get_char(indent + len) == Some(' ') {
+                    len += 1;
+                }
+                self.edit(line_start + indent..line_start + indent + len, "");
+                self.shift_columns_after(row, indent, -(len as isize));
+            } else {
+                let text = format!("{marker} ");
+                self.edit(line_start + min_indent..line_start + min_indent, &text);
+                self.shift_columns_after(row, min_indent, text.chars().count() as isize);
+            }
+        }
+        self.end_change(ChangeKind::Other);
+    }
+
+    /// Keeps the cursor and selection anchor on the same text after text has been inserted or
+    /// removed at `column` of `row`.
+    fn shift_columns_after(&mut self, row: usize, column: usize, by: isize) {
+        let shift = |position: &mut (usize, usize)| {
+            if position.0 == row && position.1 >= column {
+                position.1 = position.1.saturating_add_signed(by).max(column);
+            }
+        };
+
+        let mut cursor = (self.row, self.column);
+        shift(&mut cursor);
+        (self.row, self.column) = cursor;
+        if let Some(selection) = &mut self.selection {
+            shift(&mut selection.anchor);
+        }
+    }
+
+    /// Reverses the order of `rows`, leaving the cursor at the start of them.
+    pub fn reverse_lines(&mut self, rows: Range<usize>) {
+        if self.read_only || rows.len() < 2 {
+            return;
+        }
+
+        let last = rows.end - 1;
+        let start = self.buffer.line_to_char(rows.start);
+        let end = self.buffer.line_to_char(last) + self.line_len(last);
+        let lines: Vec<String> = rows
+            .clone()
+            .rev()
+            .map(|row| self.line(row).to_string())
+            .collect();
+
+        self.begin_change(ChangeKind::Other);
+        self.edit(start..end, &lines.join("\n"));
+        self.end_change(ChangeKind::Other);
+
+        self.selection = None;
+        self.row = rows.start;
+        self.column = 0;
+        self.scroll_to_show_cursor();
+        self.h_scroll_to_show_cursor();
+    }
+
+    /// Removes lines in `rows` that are the same as the line before them, like `uniq`,
+    /// returning how many were removed.
+    pub fn unique_lines(&mut self, rows: Range<usize>) -> usize {
+        if self.read_only || rows.len() < 2 {
+            return 0;
+        }
+
+        let last = rows.end - 1;
+        let start = self.buffer.line_to_char(rows.start);
+        let end = self.buffer.line_to_char(last) + self.line_len(last);
+        let mut lines: Vec<String> = rows.clone().map(|row| self.line(row).to_string()).collect();
+        lines.dedup();
+
+        let removed = rows.len() - lines.len();
+        if removed == 0 {
+            return 0;
+        }
+
+        self.begin_change(ChangeKind::Other);
+        self.edit(start..end, &lines.join("\n"));
+        // the cursor might have been on one of the lines that's gone
+        self.selection = None;
+        self.row = rows.start;
+        self.column = 0;
+        self.end_change(ChangeKind::Other);
+
+        self.scroll_to_show_cursor();
+        self.h_scroll_to_show_cursor();
+
+        removed
+    }
+
+    /// Removes the blank lines at the end of the buffer, keeping the newline that ends the last
+    /// line of text, and returns how many were removed.
+    pub fn trim_trailing_blank_lines(&mut self) -> usize {
+        let lines = self.line_count();
+        let kept = (0..lines)
+            .rev()
+            .find(|row| !self.is_blank(*row))
+            .map_or(0, |row| row + 1);
+        if self.read_only || kept == lines {
+            return 0;
+        }
+
+        self.begin_change(ChangeKind::Other);
+        let start = self.buffer.line_to_char(kept);
+        self.edit(start..self.buffer.len_chars(), "");
+        self.selection = None;
+        self.cursors.clear();
+        self.row = self.row.min(self.buffer.len_lines() - 1);
+        self.clamp_column();
+        self.end_change(ChangeKind::Other);
+
+        self.scroll_to_show_cursor();
+
+        lines - kept
+    }
+
+    /// Rewraps the paragraph the cursor is in to fit within `settings.text_width`, keeping its
+    /// indentation and the line comment it's in if it's in one, with the lines after a list
+    /// item's first line lined up after its marker. The cursor stays on the same word.
+    ///
+    /// Paragraphs end at blank lines, at lines that aren't commented like the cursor's one and
+    /// at the start of a list item.
+    pub fn reflow_paragraph(&mut self, comments: &Comments) -> Result<(), &'static str> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        let leader = comments
+            .line
+            .as_deref()
+            .map(str::trim)
+            .filter(|l| !l.is_empty());
+        let quoted = |row: usize| quoted_text(&self.line(row).to_string(), leader);
+        // whether a line is in a comment, or `None` if there's nothing on it to reflow
+        let commented = |row: usize| {
+            let (prefix, text) = quoted(row);
+            let in_comment = leader.is_some_and(|leader| prefix.contains(leader));
+            (!text.is_empty()).then_some(in_comment)
+        };
+        let in_comment = commented(self.row).ok_or("there's no paragraph here")?;
+        let in_paragraph = |row: usize| commented(row) == Some(in_comment);
+        let starts_item = |row: usize| list_marker(&quoted(row).1) > 0;
+
+        let mut rows = self.row..self.row + 1;
+        while rows.start > 0 && !starts_item(rows.start) && in_paragraph(rows.start - 1) {
+            rows.start -= 1;
+        }
+        while rows.end < self.line_count() && in_paragraph(rows.end) && !starts_item(rows.end) {
+            rows.end += 1;
+        }
+
+        // the words, and which one the cursor is in or before and how far into it
+        let mut words = Vec::new();
+        let mut cursor = None;
+        for row in rows.clone() {
+            let line: Vec<char> = self.line(row).chars().collect();
+            let mut column = quoted(row).0.chars().count();
+            loop {
+                while column < line.len() && line[column].is_whitespace() {
+                    column += 1;
+                }
+                let start = column;
+                while column < line.len() && !line[column].is_whitespace() {
+                    column += 1;
+                }
+                if start == column {
+                    break;
+                }
+                if cursor.is_none() && (row, column) >= (self.row, self.column) {
+                    let offset = self.column.saturating_sub(start);
+                    cursor = Some((words.len(), offset.min(column - start)));
+                }
+                words.push(line[start..column].iter().collect::<String>());
+            }
+        }
+        let (cursor_word, cursor_offset) =
+            cursor.unwrap_or_else(|| (words.len() - 1, words[words.len() - 1].chars().count()));
+
+        let (prefix, text) = quoted(rows.start);
+        let marker: String = text.chars().take(list_marker(&text)).collect();
+        // a list marker counts as a word above, but stays where it is
+        let marker_words = marker.split_whitespace().count();
+        let first = format!("{prefix}{marker}");
+        let continuation = format!("{prefix}{}", " ".repeat(marker.chars().count()));
+        let width_of = |text: &str| {
+            text.chars()
+                .fold(0, |width, c| advance(width, c, self.settings.tab_width))
+        };
+
+        let mut lines = vec![first.clone()];
+        let mut positions = Vec::new();
+        for (idx, word) in words[marker_words..].iter().enumerate() {
+            let len = word.chars().count();
+            let mut line = lines.pop().unwrap();
+            if idx > 0 && width_of(&line) + 1 + len > self.settings.text_width {
+                lines.push(line);
+                line = continuation.clone();
+            } else if idx > 0 {
+                line.push(' ');
+            }
+            positions.push((lines.len(), line.chars().count()));
+            line.push_str(word);
+            lines.push(line);
+        }
+
+        let (row, column) = match cursor_word.checked_sub(marker_words) {
+            Some(word) => {
+                let (line, column) = positions[word];
+                (rows.start + line, column + cursor_offset)
+            }
+            None => (rows.start, self.column.min(first.chars().count())),
+        };
+        let start = self.buffer.line_to_char(rows.start);
+        let end = self.buffer.line_to_char(rows.end - 1) + self.line_len(rows.end - 1);
+        let text = lines.join("\n");
+        if self.buffer.slice(start..end) != text.as_str() {
+            self.begin_change(ChangeKind::Other);
+            self.edit(start..end, &text);
+            self.selection = None;
+            self.cursors.clear();
+            (self.row, self.column) = (row, column);
+            self.end_change(ChangeKind::Other);
+        } else {
+            (self.row, self.column) = (row, column);
+        }
+
+        self.scroll_to_show_cursor();
+        Ok(())
+    }
+
+    /// The selected lines, or every line if nothing's selected.
+    pub fn selected_lines_or_all(&self) -> Range<usize> {
+        self.selected_lines()
+            .unwrap_or_else(|| 0..self.line_count())
+    }
+
+    pub fn dedent(&mut self) {
+        if self.read_only || self.previous_tab_stop() {
+            return;
+        }
+
+        let rows = self.selected_lines().unwrap_or(self.row..self.row + 1);
+
+        self.begin_change(ChangeKind::Other);
+        for row in rows {
+            let line = self.line(row);
+            let spaces = line
+                .chars()
+                .take(self.settings.tab_width)
+                .take_while(|c| *c == ' ')
+                .count();
+            let width = if spaces == 0 && line.chars().next() == Some('\t') {
+                1
+            } else {
+                spaces
+            };
+
+            let idx = self.buffer.line_to_char(row);
+            self.edit(idx..idx + width, "");
+            self.shift_columns(row..row + 1, -(width as isize));
+        }
+        self.end_change(ChangeKind::Other);
+        self.scroll_to_show_cursor();
+    }
+
+    /// Keeps the cursor and selection anchor on the same text after the given lines
+    /// have had text inserted or removed at their start.
+    fn shift_columns(&mut self, rows: Range<usize>, by: isize) {
+        let shift = |(row, column): (usize, usize)| {
+            if rows.contains(&row) {
+                (row, column.saturating_add_signed(by))
+            } else {
+                (row, column)
+            }
+        };
+
+        (self.row, self.column) = shift((self.row, self.column));
+        if let Some(selection) = &mut self.selection {
+            selection.anchor = shift(selection.anchor);
+        }
+        self.clamp_column();
+    }
+
+    /// Runs a cursor movement, either extending the selection to the new position
+    /// or dropping a character selection.
+    pub fn move_cursor(&mut self, extend: bool, mut movement: impl FnMut(&mut Self)) {
+        match self.selection {
+            None if extend => {
+                self.selection = Some(Selection {
+                    anchor: (self.row, self.column),
+                    kind: SelectionKind::Char,
+                });
+            }
+            // line selections stay until they're cancelled, like vim's visual line mode
+            Some(Selection {
+                kind: SelectionKind::Char,
+                ..
+            }) if !extend => self.selection = None,
+            _ => {}
+        }
+
+        if extend {
+            self.cursors.clear();
+            movement(self);
+        } else {
+            self.at_each_cursor(movement);
+        }
+    }
+
+    /// Runs `command` at the main cursor and then at each of the others, as one undo step.
+    pub fn at_each_cursor(&mut self, mut command: impl FnMut(&mut Self)) {
+        let before = self.file_cursor();
+        let unchanged = self.changes;
+        command(self);
+        if self.cursors.is_empty() {
+            return;
+        }
+
+        // if the main cursor's command didn't change anything, it didn't start an undo step
+        // either, so the first of the others to change something starts a new one
+        let opened = self.changes != unchanged;
+        if !opened {
+            self.change_end = None;
+        }
+        let changes = self.changes;
+        let (scroll, h_scroll) = (self.scroll, self.h_scroll);
+        let selection = self.selection.take();
+        // the main cursor goes last in the list so that the others' edits move it along
+        self.cursors.push(self.cursor_char());
+        for idx in 0..self.cursors.len() - 1 {
+            self.at_other_cursors = self.changes != unchanged;
+            self.set_cursor_char(self.cursors[idx]);
+            command(self);
+            self.cursors[idx] = self.cursor_char();
+        }
+        self.at_other_cursors = false;
+
+        let main = self.cursors.pop().unwrap();
+        self.set_cursor_char(main);
+        self.selection = selection;
+        if self.changes != changes {
+            let (cursor, selection) = self.file_cursor();
+            if let Some(step) = self.history.current_mut() {
+                if !opened {
+                    // undoing goes back to the main cursor, not the one that started the step
+                    (step.cursor_before, step.selection_before) = before;
+                }
+                step.cursor_after = cursor;
+                step.selection_after = selection;
+            }
+            if let Some((kind, _)) = self.change_end {
+                self.change_end = Some((kind, main));
+            }
+        }
+        (self.scroll, self.h_scroll) = (scroll, h_scroll);
+        self.scroll_to_show_cursor();
+        self.merge_cursors();
+    }
+
+    /// Sorts the other cursors, dropping any that have ended up in the same place.
+    fn merge_cursors(&mut self) {
+        let main = self.cursor_char();
+        let len = self.buffer.len_chars();
+        for cursor in &mut self.cursors {
+            *cursor = (*cursor).min(len);
+        }
+        self.cursors.sort_unstable();
+        self.cursors.dedup();
+        self.cursors.retain(|cursor| *cursor != main);
+    }
+
+    /// Adds a cursor at the next occurrence of the word the cursor is in, at the same place
+    /// in it, and makes that the main cursor. Returns whether there was one to add.
+    pub fn add_cursor_at_next_occurrence(&mut self) -> bool {
+        let Some((word, word_start)) = self.word_at_cursor() else {
+            return false;
+        };
+        // past the end of the line in virtual space, it's the end of the word
+        let offset = (self.column - word_start).min(word.len());
+        let main = self.cursor_char();
+
+        let mut candidates = Vec::new();
+        for row in 0..self.buffer.len_lines() {
+            let line: Vec<char> = self.line(row).chars().collect();
+            let line_start = self.buffer.line_to_char(row);
+            candidates
+                .extend(word_occurrences(&line, &word).map(|start| line_start + start + offset));
+        }
+
+        // the first one after the main cursor, going around to the top if there isn't one
+        let is_free = |idx: &usize| *idx != main && !self.cursors.contains(idx);
+        let next = candidates
+            .iter()
+            .find(|idx| **idx > main && is_free(idx))
+            .or_else(|| candidates.iter().find(|idx| is_free(idx)));
+        let Some(&next) = next else {
+            return false;
+        };
+
+        self.selection = None;
+        self.cursors.push(main);
+        self.set_cursor_char(next);
+        self.merge_cursors();
+        self.scroll_to_show_cursor();
+        true
+    }
+
+    /// Adds a cursor on the line above or below the main one, and makes that the main cursor.
+    pub fn add_cursor_vertically(&mut self, down: bool) {
+        let (row, main) = (self.row, self.cursor_char());
+        self.selection = None;
+        if down {
+            self.down();
+        } else {
+            self.up();
+        }
+
+        if self.row != row {
+            self.cursors.push(main);
+            self.merge_cursors();
+        }
+    }
+
+    /// Starts selecting whole lines, or stops if that's already happening.
+    pub fn select_lines(&mut self) {
+        self.toggle_selection(SelectionKind::Line);
+    }
+
+    /// Starts selecting a rectangular block, or stops if that's already happening.
+    pub fn select_block(&mut self) {
+        self.toggle_selection(SelectionKind::Block);
+    }
+
+    fn toggle_selection(&mut self, kind: SelectionKind) {
+        self.selection = match self.selection {
+            Some(selection) if selection.kind == kind => None,
+            Some(selection) => Some(Selection { kind, ..selection }),
+            None => Some(Selection {
+                anchor: (self.row, self.column),
+                kind,
+            }),
+        };
+    }
+
+    /// Returns whether there was a selection to clear.
+    pub fn clear_selection(&mut self) -> bool {
+        let snippet = self.snippet.take().is_some();
+        let cursors = !self.cursors.is_empty();
+        self.cursors.clear();
+        self.selection.take().is_some() || snippet || cursors
+    }
+
+    /// The ends of the selection in document order.
+    fn selection_ends(&self) -> Option<((usize, usize), (usize, usize))> {
+        let anchor = self.clamp_position(self.selection?.anchor);
+        let cursor = (self.row, self.column);
+
+        Some(if anchor <= cursor {
+            (anchor, cursor)
+        } else {
+            (cursor, anchor)
+        })
+    }
+
+    /// The lines the selection touches.
+    pub fn selected_lines(&self) -> Option<Range<usize>> {
+        let (start, end) = self.selection_ends()?;
+        Some(start.0..end.0 + 1)
+    }
+
+    /// The lines and columns of the selected block.
+    fn block(&self) -> Option<(Range<usize>, Range<usize>)> {
+        if self.selection?.kind != SelectionKind::Block {
+            return None;
+        }
+
+        let (start, end) = self.selection_ends()?;
+        let columns = start.1.min(end.1)..start.1.max(end.1);
+        Some((start.0..end.0 + 1, columns))
+    }
+
+    /// The selected characters as a range into the buffer.
+    /// Blocks aren't contiguous, so they don't have one.
+    fn selection_range(&self) -> Option<Range<usize>> {
+        let (start, end) = self.selection_ends()?;
+
+        match self.selection?.kind {
+            SelectionKind::Char => Some(self.position_to_char(start)..self.position_to_char(end)),
+            SelectionKind::Line => {
+                let start = self.buffer.line_to_char(start.0);
+                if end.0 + 1 < self.buffer.len_lines() {
+                    Some(start..self.buffer.line_to_char(end.0 + 1))
+                } else {
+                    // there's no newline after the last line,
+                    // so take the one before the selected lines instead
+                    Some(start.saturating_sub(1)..self.buffer.len_chars())
+                }
+            }
+            SelectionKind::Block => None,
+        }
+    }
+
+    pub fn copy(&self) -> Option<Clipboard> {
+        let selection = self.selection?;
+
+        Some(match selection.kind {
+            SelectionKind::Char => Clipboard {
+                text: self.buffer.slice(self.selection_range()?).to_string(),
+                kind: SelectionKind::Char,
+            },
+            SelectionKind::Line => {
+                let mut text = String::new();
+                for row in self.selected_lines()? {
+                    text.extend(self.line(row).chars());
+                    text.push('\n');
+                }
+                Clipboard {
+                    text,
+                    kind: SelectionKind::Line,
+                }
+            }
+            SelectionKind::Block => {
+                let (rows, columns) = self.block()?;
+                let lines: Vec<String> = rows
+                    .map(|row| {
+                        let line = self.line(row);
+                        let len = line.len_chars();
+                        line.slice(columns.start.min(len)..columns.end.min(len))
+                            .to_string()
+                    })
+                    .collect();
+                Clipboard {
+                    text: lines.join("\n"),
+                    kind: SelectionKind::Block,
+                }
+            }
+        })
+    }
+
+    pub fn cut(&mut self) -> Option<Clipboard> {
+        let clipboard = self.copy()?;
+        self.backspace();
+        Some(clipboard)
+    }
+
+    pub fn set_mark(&mut self) {
+        self.mark = Some(self.cursor_char());
+    }
+
+    /// Selects from the mark to the cursor, so that anything that works on the selection
+    /// works on that, returning whether there's a mark.
+    pub fn select_to_mark(&mut self) -> bool {
+        let Some(mark) = self.mark else {
+            return false;
+        };
+
+        self.cursors.clear();
+        let row = self.buffer.char_to_line(mark);
+        self.selection = Some(Selection {
+            anchor: (row, mark - self.buffer.line_to_char(row)),
+            kind: SelectionKind::Char,
+        });
+        true
+    }
+
+    /// Where what's selected is in the whole file, counting what's paged out before the buffer,
+    /// with selected lines taking the newline after them. A block isn't one range, so there's
+    /// none for it.
+    pub fn selected_file_chars(&self) -> Option<Range<usize>> {
+        let range = match self.selection?.kind {
+            SelectionKind::Line => {
+                let rows = self.selected_lines()?;
+                self.buffer.line_to_char(rows.start)..self.buffer.line_to_char(rows.end)
+            }
+            _ => self.selection_range()?,
+        };
+        let before = self.chars_before();
+        Some(before + range.start..before + range.end)
+    }
+
+    /// The file's chars in `range`, paging them in if they aren't in the buffer.
+    pub fn file_text(&mut self, range: Range<usize>) -> String {
+        let range = self.show_file_chars(range);
+        self.buffer.slice(range).to_string()
+    }
+
+    /// Replaces the file's chars in `range` with `text`, leaving the cursor after it.
+    pub fn replace_file_chars(&mut self, range: Range<usize>, text: &str) {
+        if self.read_only {
+            return;
+        }
+
+        let range = self.show_file_chars(range);
+        self.begin_change(ChangeKind::Other);
+        self.cursors.clear();
+        self.selection = None;
+        self.edit(range.clone(), text);
+        self.set_cursor_char(range.start + text.chars().count());
+        self.end_change(ChangeKind::Other);
+        self.scroll_to_show_cursor();
+    }
+
+    pub fn select_all(&mut self) {
+        self.cursors.clear();
+        self.selection = Some(Selection {
+            anchor: (0, 0),
+            kind: SelectionKind::Char,
+        });
+        self.row = self.buffer.len_lines() - 1;
+        self.column = self.line_len(self.row);
+        self.scroll_to_show_cursor();
+    }
+
+    /// Puts a copy of the selection right after it, and selects the copy instead,
+    /// returning whether there was anything selected.
+    pub fn duplicate_selection(&mut self) -> bool {
+        let (Some(selection), Some(copy)) = (self.selection, self.copy()) else {
+            return false;
+        };
+        if self.read_only {
+            return true;
+        }
+
+        self.cursors.clear();
+        self.begin_change(ChangeKind::Other);
+        match selection.kind {
+            SelectionKind::Char => {
+                let end = self.selection_range().unwrap().end;
+                self.edit(end..end, &copy.text);
+                self.set_cursor_char(end);
+                let anchor = (self.row, self.column);
+                self.set_cursor_char(end + copy.text.chars().count());
+                self.selection = Some(Selection {
+                    anchor,
+                    ..selection
+                });
+            }
+            SelectionKind::Line => {
+                let rows = self.selected_lines().unwrap();
+                if rows.end < self.buffer.len_lines() {
+                    let idx = self.buffer.line_to_char(rows.end);
+                    self.edit(idx..idx, &copy.text);
+                } else {
+                    // the last line has no newline of its own to go after
+                    let end = self.buffer.len_chars();
+                    self.edit(end..end, &format!("\n{}", copy.text.trim_end_matches('\n')));
+                }
+                self.row += rows.len();
+                if let Some(selection) = &mut self.selection {
+                    selection.anchor.0 += rows.len();
+                }
+            }
+            SelectionKind::Block => {
+                let (rows, columns) = self.block().unwrap();
+                for (row, text) in rows.zip(copy.text.split('\n')) {
+                    self.insert_at_column(row, columns.end, text);
+                }
+                self.shift_block(columns.len() as isize);
+            }
+        }
+        self.end_change(ChangeKind::Other);
+        self.scroll_to_show_cursor();
+
+        true
+    }
+
+    /// Replaces the given columns of the cursor's line with `text`, leaving the cursor after it.
+    pub fn replace_columns(&mut self, columns: Range<usize>, text: &str) {
+        if self.read_only {
+            return;
+        }
+
+        self.begin_change(ChangeKind::Other);
+        self.selection = None;
+        let start = self.position_to_char((self.row, columns.start));
+        let end = self.position_to_char((self.row, columns.end));
+        self.edit(start..end, text);
+        self.set_cursor_char(start + text.chars().count());
+        self.end_change(ChangeKind::Other);
+        self.scroll_to_show_cursor();
+    }
+
+    /// Inserts text as is, such as when it's pasted from outside the editor.
+    pub fn insert_text(&mut self, text: &str) {
+        self.paste(&Clipboard {
+            text: text.to_string(),
+            kind: SelectionKind::Char,
+        });
+    }
+
+    pub fn paste(&mut self, clipboard: &Clipboard) {
+        if self.read_only || clipboard.text.is_empty() {
+            return;
+        }
+
+        self.begin_change(ChangeKind::Other);
+        self.delete_selection();
+
+        match clipboard.kind {
+            SelectionKind::Char => {
+                let (row, column) = (self.row, self.column);
+                self.insert_at_column(row, column, &clipboard.text);
+                let idx = self.position_to_char((row, column));
+                self.set_cursor_char(idx + clipboard.text.chars().count());
+            }
+            SelectionKind::Line => {
+                // lines go above the cursor's line, with the cursor at the start of them
+                let idx = self.buffer.line_to_char(self.row);
+                self.edit(idx..idx, &clipboard.text);
+                self.column = 0;
+            }
+            SelectionKind::Block => {
+                // each line of the block goes into the same column on successive lines
+                for (offset, text) in clipboard.text.split('\n').enumerate() {
+                    let row = self.row + offset;
+                    if row == self.buffer.len_lines() {
+                        let end = self.buffer.len_chars();
+                        self.edit(end..end, "\n");
+                    }
+                    self.insert_at_column(row, self.column, text);
+                }
+            }
+        }
+
+        self.end_change(ChangeKind::Other);
+        self.scroll_to_show_cursor();
+    }
+
+    /// Pastes lines with their indentation moved to match where they're going,
+    /// keeping how they're indented relative to each other.
+    pub fn paste_reindented(&mut self, clipboard: &Clipboard) {
+        let line = self.line(self.row);
+        // lines go above the cursor's line, and anything else starts at the cursor
+        let before = match clipboard.kind {
+            SelectionKind::Line => line.len_chars(),
+            SelectionKind::Char => self.column,
+            SelectionKind::Block => return self.paste(clipboard),
+        };
+        let target: String = line
+            .chars()
+            .take(before)
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect();
+
+        let lines: Vec<&str> = clipboard.text.split('\n').collect();
+        // the first line of text copied from the middle of a line doesn't have its indentation
+        let first = usize::from(clipboard.kind == SelectionKind::Char);
+        let common = lines[first..]
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| &line[..line.len() - line.trim_start_matches([' ', '\t']).len()])
+            .reduce(|common, indent| {
+                let len = common
+                    .bytes()
+                    .zip(indent.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                &common[..len]
+            })
+            .unwrap_or_default();
+
+        let mut text = String::new();
+        for (idx, line) in lines.iter().enumerate() {
+            if idx > 0 {
+                text.push('\n');
+            }
+            if idx < first {
+                text.push_str(line);
+            } else if !line.trim().is_empty() {
+                // blank lines are left empty, rather than with trailing whitespace
+                text.push_str(&target);
+                text.push_str(line.strip_prefix(common).unwrap_or(line));
+            }
+        }
+
+        self.paste(&Clipboard {
+            text,
+            kind: clipboard.kind,
+        });
+    }
+
+    /// Inserts text at a column, padding the line with spaces if it's too short.
+    fn insert_at_column(&mut self, row: usize, column: usize, text: &str) {
+        let len = self.line_len(row);
+        let idx = self.buffer.line_to_char(row) + len.min(column);
+
+        if len < column && !text.is_empty() {
+            let padded = " ".repeat(column - len) + text;
+            self.edit(idx..idx, &padded);
+        } else {
+            self.edit(idx..idx, text);
+        }
+    }
+
+    /// Types a character on every line of the block, replacing what it covers.
+    fn block_keypress(&mut self, c: char) {
+        self.begin_change(ChangeKind::Other);
+        self.delete_block();
+
+        let (rows, columns) = self.block().unwrap();
+        for row in rows {
+            self.insert_at_column(row, columns.start, c.encode_utf8(&mut [0; 4]));
+        }
+        self.shift_block(1);
+
+        self.end_change(ChangeKind::Other);
+        self.h_scroll_to_show_cursor();
+    }
+
+    /// Deletes the block, or the character before it on every line if it's empty.
+    fn block_backspace(&mut self) {
+        let (rows, columns) = self.block().unwrap();
+
+        self.begin_change(ChangeKind::Other);
+        if !columns.is_empty() {
+            self.delete_block();
+        } else if columns.start > 0 {
+            for row in rows {
+                // lines that don't reach the block are left alone
+                if self.line_len(row) >= columns.start {
+                    let idx = self.buffer.line_to_char(row) + columns.start;
+                    self.edit(idx - 1..idx, "");
+                }
+            }
+            self.shift_block(-1);
+        }
+        self.end_change(ChangeKind::Other);
+        self.h_scroll_to_show_cursor();
+    }
+
+    /// Removes the text inside the block as part of the current change,
+    /// leaving an empty block at its left edge.
+    fn delete_block(&mut self) {
+        let (rows, columns) = self.block().unwrap();
+
+        for row in rows {
+            let len = self.line_len(row);
+            if len > columns.start {
+                let line_start = self.buffer.line_to_char(row);
+                self.edit(
+                    line_start + columns.start..line_start + columns.end.min(len),
+                    "",
+                );
+            }
+        }
+
+        self.column = columns.start;
+        if let Some(selection) = &mut self.selection {
+            selection.anchor.1 = columns.start;
+        }
+    }
+
+    /// Moves both edges of the block, without clamping them to the cursor's line
+    /// since other lines in the block might be longer.
+    fn shift_block(&mut self, by: isize) {
+        self.column = self.column.saturating_add_signed(by);
+        if let Some(selection) = &mut self.selection {
+            selection.anchor.1 = selection.anchor.1.saturating_add_signed(by);
+        }
+    }
+
+    /// Removes the selected text as part of the current change,
+    /// returning whether there was anything selected.
+    fn delete_selection(&mut self) -> bool {
+        if self.block().is_some() {
+            self.delete_block();
+            self.selection = None;
+            return true;
+        }
+
+        let range = match self.selection_range() {
+            Some(range) => range,
+            None => return false,
+        };
+        let (start, _) = self.selection_ends().unwrap();
+        let kind = self.selection.take().unwrap().kind;
+
+        self.edit(range, "");
+
+        match kind {
+            SelectionKind::Char => (self.row, self.column) = start,
+            SelectionKind::Line => {
+                self.row = start.0.min(self.buffer.len_lines() - 1);
+                self.column = 0;
+            }
+            SelectionKind::Block => unreachable!(),
+        }
+
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.current.is_some()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.redo_target().is_some()
+    }
+
+    /// Whether there's a state after the current one in the order they were made.
+    pub fn can_go_later(&self) -> bool {
+        self.history.state() < self.history.steps.len()
+    }
+
+    /// The current state of the buffer, numbered in the order they were made, and how many
+    /// there are after the text as it was opened.
+    pub fn history_position(&self) -> (usize, usize) {
+        (self.history.state(), self.history.steps.len())
+    }
+
+    pub fn undo(&mut self) {
+        if self.read_only {
+            return;
+        }
+
+        if let Some(step) = self.history.current {
+            self.undo_step(step);
+            self.finish_history_move();
+        }
+    }
+
+    /// Redoes the step that was last undone from here, or else the last one made from here.
+    pub fn redo(&mut self) {
+        if self.read_only {
+            return;
+        }
+
+        if let Some(step) = self.history.redo_target() {
+            self.redo_step(step);
+            self.finish_history_move();
+        }
+    }
+
+    /// Goes back to the state before the current one in the order they were made, like vim's
+    /// `g-`, which may be on another branch of what was undone.
+    pub fn earlier(&mut self) {
+        if self.read_only {
+            return;
+        }
+
+        if let Some(state) = self.history.state().checked_sub(1) {
+            self.go_to_state(state);
+        }
+    }
+
+    /// Goes forward to the state after the current one in the order they were made, like vim's
+    /// `g+`.
+    pub fn later(&mut self) {
+        if self.read_only || !self.can_go_later() {
+            return;
+        }
+
+        self.go_to_state(self.history.state() + 1);
+    }
+
+    fn go_to_state(&mut self, state: usize) {
+        let (undo, redo) = self.history.path_to(state.checked_sub(1));
+        for step in undo {
+            self.undo_step(step);
+        }
+        for step in redo {
+            self.redo_step(step);
+        }
+        self.finish_history_move();
+    }
+
+    fn undo_step(&mut self, idx: usize) {
+        let step = &self.history.steps[idx];
+        let parent = step.parent;
+        let cursor = step.cursor_before;
+        let selection = step.selection_before;
+        let edits: Vec<_> = step
+            .edits
+            .iter()
+            .rev()
+            .map(|edit| {
+                let inserted = edit.start..edit.start + edit.inserted.chars().count();
+                (inserted, edit.removed.clone())
+            })
+            .collect();
+        for (inserted, removed) in edits {
+            let inserted = self.show_file_chars(inserted);
+            self.replace(inserted, &removed);
+        }
+
+        self.set_file_cursor(cursor, selection);
+        self.history.current = parent;
+        self.history.set_redo(parent, idx);
+    }
+
+    fn redo_step(&mut self, idx: usize) {
+        let step = &self.history.steps[idx];
+        let parent = step.parent;
+        let cursor = step.cursor_after;
+        let selection = step.selection_after;
+        let edits: Vec<_> = step
+            .edits
+            .iter()
+            .map(|edit| {
+                let removed = edit.start..edit.start + edit.removed.chars().count();
+                (removed, edit.inserted.clone())
+            })
+            .collect();
+        for (removed, inserted) in edits {
+            let removed = self.show_file_chars(removed);
+            self.replace(removed, &inserted);
+        }
+
+        self.set_file_cursor(cursor, selection);
+        self.history.current = Some(idx);
+        self.history.set_redo(parent, idx);
+    }
+
+    fn finish_history_move(&mut self) {
+        self.change_end = None;
+        self.scroll_to_show_cursor();
+    }
+
+    /// The cursor and selection with their rows counted from the start of the file, for the
+    /// undo history.
+    fn file_cursor(&self) -> ((usize, usize), Option<Selection>) {
+        let rows = self.rows_before();
+        let selection = self.selection.map(|selection| Selection {
+            anchor: (selection.anchor.0 + rows, selection.anchor.1),
+            ..selection
+        });
+        ((self.row + rows, self.column), selection)
+    }
+
+    /// Puts the cursor and selection back where the undo history says, paging to them.
+    fn set_file_cursor(&mut self, (row, column): (usize, usize), selection: Option<Selection>) {
+        self.row = self.show_file_row(row);
+        self.column = column;
+        let rows = self.rows_before();
+        self.selection = selection.and_then(|selection| {
+            let row = selection.anchor.0.checked_sub(rows)?;
+            (row < self.buffer.len_lines()).then_some(Selection {
+                anchor: (row, selection.anchor.1),
+                ..selection
+            })
+        });
+    }
+
+    pub fn repeat_last_change(&mut self) {
+        let change = match self.last_change.clone() {
+            Some(change) => change,
+            None => return,
+        };
+
+        // each repeat is its own undo step, even straight after the change itself
+        self.change_end = None;
+        self.repeating = true;
+
+        match change {
+            Change::Insert(text) => {
+                let mut chars = text.chars().peekable();
+                while let Some(c) = chars.next() {
+                    if c != '\n' {
+                        self.keypress(c);
+                        continue;
+                    }
+
+                    self.enter();
+                    // the indentation that was typed comes from auto-indenting again
+                    if self.settings.auto_indent {
+                        while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+                    }
+                }
+            }
+            Change::DeleteBackward(count) => {
+                for _ in 0..count {
+                    self.backspace();
+                }
+            }
+        }
+
+        self.repeating = false;
+        self.change_end = None;
+    }
+
+    /// Starts recording an editing command's edits into an undo step,
+    /// continuing the previous step if this command carries on from the last one
+    /// (such as typing a run of characters).
+    fn begin_change(&mut self, kind: ChangeKind) {
+        if self.at_other_cursors {
+            return;
+        }
+
+        let continues = self.change_end == Some((kind, self.cursor_char()));
+        if continues && kind != ChangeKind::Other && self.selection.is_none() {
+            return;
+        }
+
+        let (cursor, selection) = self.file_cursor();
+        self.history.push(UndoStep {
+            parent: None,
+            redo: None,
+            edits: Vec::new(),
+            cursor_before: cursor,
+            cursor_after: cursor,
+            selection_before: selection,
+            selection_after: selection,
+        });
+    }
+
+    fn end_change(&mut self, kind: ChangeKind) {
+        if self.at_other_cursors {
+            return;
+        }
+
+        let (cursor, selection) = self.file_cursor();
+        let step = self
+            .history
+            .current_mut()
+            .expect("end_change is always preceded by begin_change");
+        step.cursor_after = cursor;
+        step.selection_after = selection;
+
+        if !self.repeating {
+            self.last_change = match kind {
+                ChangeKind::Insert => Some(Change::Insert(
+                    step.edits.iter().map(|e| e.inserted.as_str()).collect(),
+                )),
+                ChangeKind::DeleteBackward => Some(Change::DeleteBackward(
+                    step.edits.iter().map(|e| e.removed.chars().count()).sum(),
+                )),
+                ChangeKind::Other => None,
+            };
+        }
+
+        self.change_end = Some((kind, self.cursor_char()));
+    }
+
+    /// Replaces the given range of characters with `text`, recording it in the undo step.
+    fn edit(&mut self, range: Range<usize>, text: &str) {
+        let edit = Edit {
+            start: self.chars_before() + range.start,
+            removed: self.buffer.slice(range.clone()).to_string(),
+            inserted: text.to_string(),
+        };
+        self.replace(range, text);
+
+        if let Some(step) = self.history.current_mut() {
+            step.edits.push(edit);
+        }
+    }
+
+    fn replace(&mut self, range: Range<usize>, text: &str) {
+        let start_row = self.buffer.char_to_line(range.start);
+        let end_row = self.buffer.char_to_line(range.end);
+        #[cfg(feature = "tree-sitter")]
+        if let Some(tree) = &mut self.tree {
+            tree.edit(&self.buffer, range.clone(), text);
+        }
+
+        if let Some(pager) = &mut self.pager {
+            pager.edited(range.clone(), text.chars().count());
+        }
+
+        self.buffer.remove(range.clone());
+        self.buffer.insert(range.start, text);
+        let inserted_rows = text.matches('\n').count();
+        self.highlighter
+            .edited(start_row..end_row + 1, inserted_rows + 1);
+        self.bracket_depths.truncate(start_row + 1);
+        self.hunks_stale = true;
+        self.modified = true;
+        self.changes += 1;
+
+        // diagnostics after the edit move with the text, and ones inside it shrink
+        let inserted = text.chars().count();
+        let map = |idx: usize| {
+            if idx <= range.start {
+                idx
+            } else if idx >= range.end {
+                idx - range.len() + inserted
+            } else {
+                range.start
+            }
+        };
+        for diagnostic in &mut self.diagnostics {
+            diagnostic.range = map(diagnostic.range.start)..map(diagnostic.range.end);
+        }
+
+        // unlike diagnostics, tab stops are pushed along by text inserted right before them,
+        // so that typing into one stop doesn't swallow the next
+        let map = |idx: usize| {
+            if idx < range.start {
+                idx
+            } else if idx >= range.end {
+                idx - range.len() + inserted
+            } else {
+                range.start
+            }
+        };
+        if let Some(snippet) = &mut self.snippet {
+            for stop in &mut snippet.stops {
+                *stop = map(stop.start)..map(stop.end);
+            }
+        }
+        for cursor in &mut self.cursors {
+            *cursor = map(*cursor);
+        }
+        self.mark = self.mark.map(map);
+
+        // keep folds on the same lines, and open any that were edited inside
+        let only_header = start_row == end_row && inserted_rows == 0;
+        self.folds.retain_mut(|fold| {
+            if fold.end <= start_row || only_header && fold.start == start_row {
+                true
+            } else if fold.start > end_row {
+                *fold = fold.start + inserted_rows - (end_row - start_row)
+                    ..fold.end + inserted_rows - (end_row - start_row);
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    /// Folds the selected lines, or the indented block the cursor is in,
+    /// or unfolds the fold at the cursor.
+    pub fn toggle_fold(&mut self) {
+        if let Some(rows) = self.selected_lines() {
+            self.selection = None;
+            if rows.len() > 1 {
+                self.fold(rows);
+            }
+            return;
+        }
+
+        if let Some(idx) = self.folds.iter().position(|fold| fold.start == self.row) {
+            self.folds.remove(idx);
+            self.scroll_to_show_cursor();
+            return;
+        }
+
+        if let Some(block) = self.indented_block(self.row) {
+            self.fold(block);
+            return;
+        }
+
+        // otherwise fold the block that contains the cursor
+        if let Some(indent) = self.indent_width(self.row) {
+            let header = (0..self.row)
+                .rev()
+                .find(|row| self.indent_width(*row).is_some_and(|i| i < indent));
+            if let Some(block) = header.and_then(|row| self.indented_block(row)) {
+                self.fold(block);
+            }
+        }
+    }
+
+    /// Folds every outermost indented block.
+    pub fn fold_all(&mut self) {
+        let mut row = 0;
+        while row < self.buffer.len_lines() {
+            match self.indented_block(row) {
+                Some(block) => {
+                    row = block.end;
+                    self.fold(block);
+                }
+                None => row += 1,
+            }
+        }
+    }
+
+    pub fn unfold_all(&mut self) {
+        self.folds.clear();
+        self.scroll_to_show_cursor();
+    }
+
+    fn fold(&mut self, rows: Range<usize>) {
+        self.folds
+            .retain(|fold| fold.end <= rows.start || fold.start >= rows.end);
+        let idx = self.folds.partition_point(|fold| fold.start < rows.start);
+        self.folds.insert(idx, rows.clone());
+
+        if rows.contains(&self.row) {
+            self.row = rows.start;
+            self.clamp_column();
+        }
+        self.scroll_to_show_cursor();
+    }
+
+    /// The fold whose first row is `row`.
+    fn fold_at(&self, row: usize) -> Option<Range<usize>> {
+        self.folds.iter().find(|fold| fold.start == row).cloned()
+    }
+
+    /// The fold that `row` is hidden inside of.
+    fn fold_hiding(&self, row: usize) -> Option<Range<usize>> {
+        self.folds
+            .iter()
+            .find(|fold| fold.start < row && row < fold.end)
+            .cloned()
+    }
+
+    /// The rows from `row` onwards that aren't hidden inside a fold.
+    fn visible_rows(&self, row: usize) -> impl Iterator<Item = usize> + '_ {
+        iter::successors(Some(row), |row| Some(self.next_visible_row(*row)))
+            .take_while(|row| *row < self.buffer.len_lines())
+    }
+
+    /// The row shown after `row`, which might be past the end of the buffer.
+    fn next_visible_row(&self, row: usize) -> usize {
+        match self.fold_at(row) {
+            Some(fold) => fold.end,
+            None => row + 1,
+        }
+    }
+
+    fn previous_visible_row(&self, row: usize) -> usize {
+        match self.fold_hiding(row - 1) {
+            Some(fold) => fold.start,
+            None => row - 1,
+        }
+    }
+
+    /// The rows shown in the view, along with how far down it they are.
+    ///
+    /// Everything that maps between rows of the buffer and rows of the screen goes through this,
+    /// so that they agree about what's hidden.
+    fn rows_on_screen(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.visible_rows(self.scroll).take(self.height).enumerate()
+    }
+
+    /// The rows shown from `row` back to the start of the buffer.
+    fn visible_rows_back(&self, row: usize) -> impl Iterator<Item = usize> + '_ {
+        iter::successors(Some(row), |row| {
+            (*row > 0).then(|| self.previous_visible_row(*row))
+        })
+    }
+
+    /// Where `row` and `column` of the buffer are shown in the view, counting the gutter.
+    /// Positions outside of the view are shown as if they were at its nearest edge.
+    pub fn screen_position(&self, row: usize, column: usize) -> (usize, usize) {
+        let x = self
+            .display_column(row, column)
+            .saturating_sub(self.h_scroll)
+            .min(self.text_width().saturating_sub(1));
+        (self.gutter_width() + x, self.screen_row(row))
+    }
+
+    /// The row and column of the buffer shown at `(x, y)` in the view, if there's a line there.
+    /// Clicking the gutter or past the end of a line goes to the nearest column.
+    pub fn buffer_position(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        let (_, row) = self
+            .rows_on_screen()
+            .find(|(screen_row, _)| *screen_row == y)?;
+        let display = x.saturating_sub(self.gutter_width()) + self.h_scroll;
+        let mut column = self.column_at_display(row, display);
+        if !self.settings.virtual_space {
+            column = column.min(self.line_len(row));
+        }
+        Some((row, column))
+    }
+
+    /// How far down the view `row` is shown, which differs from its distance
+    /// from the top row when folds are in between. Rows above the view are shown as if they
+    /// were at the top, and rows below it give the view's height.
+    fn screen_row(&self, row: usize) -> usize {
+        // a row hidden in a fold is shown where the fold is
+        self.rows_on_screen()
+            .find(|(_, shown)| self.next_visible_row(*shown) > row)
+            .map_or(self.height, |(screen_row, _)| screen_row)
+    }
+
+    /// The lines after `row` that are indented further than it,
+    /// together with `row` itself.
+    fn indented_block(&self, row: usize) -> Option<Range<usize>> {
+        let indent = self.indent_width(row)?;
+        let mut end = row + 1;
+
+        for next in row + 1..self.buffer.len_lines() {
+            match self.indent_width(next) {
+                Some(i) if i > indent => end = next + 1,
+                Some(_) => break,
+                // blank lines don't end a block, but aren't included at the end of one
+                None => {}
+            }
+        }
+
+        (end > row + 1).then_some(row..end)
+    }
+
+    /// How wide the leading whitespace on a line is, or `None` if the line is blank.
+    fn indent_width(&self, row: usize) -> Option<usize> {
+        let mut width = 0;
+
+        for c in self.line(row).chars() {
+            match c {
+                ' ' | '\t' => width = advance(width, c, self.settings.tab_width),
+                _ => return Some(width),
+            }
+        }
+
+        None
+    }
+
+    pub fn left(&mut self) {
+        if self.column != 0 {
+            self.column -= 1;
+            if self.settings.soft_tab_movement && self.in_space_indent(self.column + 1) {
+                self.column -= self.column % self.settings.tab_width;
+            }
+        }
+        self.h_scroll_to_show_cursor();
+    }
+    pub fn right(&mut self) {
+        let next_stop = (self.column / self.settings.tab_width + 1) * self.settings.tab_width;
+        if self.settings.soft_tab_movement && self.in_space_indent(next_stop) {
+            self.column = next_stop;
+        } else if self.settings.virtual_space || self.column < self.line_len(self.row) {
+            self.column += 1;
+        }
+        self.h_scroll_to_show_cursor();
+    }
+
+    /// Whether the cursor's line starts with at least `columns` spaces.
+    fn in_space_indent(&self, columns: usize) -> bool {
+        let line = self.line(self.row);
+        columns <= line.len_chars() && line.chars().take(columns).all(|c| c == ' ')
+    }
+    pub fn up(&mut self) {
+        let column = self.display_column(self.row, self.column);
+        if self.row != 0 {
+            self.row = self.previous_visible_row(self.row);
+        }
+        // stay lined up with where the cursor was on screen, even with tabs in the way
+        self.column = self.column_at_display(self.row, column);
+        self.clamp_column();
+        self.scroll_to_show_cursor();
+    }
+    pub fn down(&mut self) {
+        let column = self.display_column(self.row, self.column);
+        let next = self.next_visible_row(self.row);
+        self.load_lines(next);
+        if next < self.buffer.len_lines() {
+            self.row = next;
+        }
+        self.column = self.column_at_display(self.row, column);
+        self.clamp_column();
+        self.scroll_to_show_cursor();
+    }
+    pub fn home(&mut self) {
+        self.column = 0;
+        self.h_scroll_to_show_cursor();
+    }
+    pub fn end(&mut self) {
+        self.column = self.line_len(self.row);
+        self.h_scroll_to_show_cursor();
+    }
+
+    /// Moves to the next blank line after some text, or the last line if there isn't one,
+    /// like vim's `}`.
+    pub fn next_blank_line(&mut self) {
+        let mut row = self.row;
+        let mut seen_text = !self.is_blank(row);
+        loop {
+            let next = self.next_visible_row(row);
+            self.load_lines(next);
+            if next >= self.buffer.len_lines() {
+                break;
+            }
+            row = next;
+            if !self.is_blank(row) {
+                seen_text = true;
+            } else if seen_text {
+                break;
+            }
+        }
+        self.jump_to_row(row);
+    }
+
+    /// Moves to the previous blank line before some text, or the first line if there isn't one,
+    /// like vim's `{`.
+    pub fn previous_blank_line(&mut self) {
+        let mut row = self.row;
+        let mut seen_text = !self.is_blank(row);
+        while row != 0 {
+            row = self.previous_visible_row(row);
+            if !self.is_blank(row) {
+                seen_text = true;
+            } else if seen_text {
+                break;
+            }
+        }
+        self.jump_to_row(row);
+    }
+
+    /// Moves to the start of the text on the next line that's indented as much as the cursor's
+    /// one, skipping over blank lines and ones indented more. Doesn't move if a line indented
+    /// less comes first, so it stays within the same block.
+    pub fn next_sibling_line(&mut self) {
+        let mut row = self.row;
+        let Some(indent) = self.indent_width(row) else {
+            return;
+        };
+        loop {
+            let next = self.next_visible_row(row);
+            self.load_lines(next);
+            if next >= self.buffer.len_lines() {
+                return;
+            }
+            row = next;
+            match self.indent_width(row) {
+                Some(width) if width == indent => break,
+                Some(width) if width < indent => return,
+                _ => {}
+            }
+        }
+        self.jump_to_text(row);
+    }
+
+    /// Like `next_sibling_line`, but backwards.
+    pub fn previous_sibling_line(&mut self) {
+        let mut row = self.row;
+        let Some(indent) = self.indent_width(row) else {
+            return;
+        };
+        while row != 0 {
+            row = self.previous_visible_row(row);
+            match self.indent_width(row) {
+                Some(width) if width == indent => return self.jump_to_text(row),
+                Some(width) if width < indent => return,
+                _ => {}
+            }
+        }
+    }
+
+    fn jump_to_text(&mut self, row: usize) {
+        self.jump_to_row(row);
+        self.column = self
+            .line(row)
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count();
+        self.h_scroll_to_show_cursor();
+    }
+
+    /// Moves to the next `c` after the cursor on its line, or just before it if not `inclusive`,
+    /// like vim's `f` and `t`. Returns whether there was one.
+    pub fn find_char_forward(&mut self, c: char, inclusive: bool) -> bool {
+        let line = self.line(self.row);
+        let from = self.column + 1;
+        let Some(column) = (from..line.len_chars()).find(|column| line.char(*column) == c) else {
+            return false;
+        };
+
+        self.column = if inclusive { column } else { column - 1 };
+        self.h_scroll_to_show_cursor();
+        true
+    }
+
+    /// Moves to the previous `c` before the cursor on its line, or just after it if not
+    /// `inclusive`, like vim's `F` and `T`. Returns whether there was one.
+    pub fn find_char_backward(&mut self, c: char, inclusive: bool) -> bool {
+        let line = self.line(self.row);
+        let before = self.column.min(line.len_chars());
+        let Some(column) = (0..before).rev().find(|column| line.char(*column) == c) else {
+            return false;
+        };
+
+        self.column = if inclusive { column } else { column + 1 };
+        self.h_scroll_to_show_cursor();
+        true
+    }
+
+    /// Selects the next match for `search`, going around the buffer if there isn't one before
+    /// its end and searching wraps, and remembers it for searching again.
+    ///
+    /// Searching forward through a file that's still loading only reads as far as the next
+    /// match, which in a huge log can be much less than all of it. Searching backward or going
+    /// around to the start needs the whole file.
+    pub fn search(&mut self, search: Search) -> Found {
+        if self.pager.is_some() {
+            return self.search_pages(search);
+        }
+
+        let cursor = self.cursor_char();
+        let matches = if search.forward {
+            self.load_until_match(&search.query, cursor)
+        } else {
+            self.load_all();
+            self.matches(&search.query)
+        };
+        let len = search.query.chars().count();
+        let further = if search.forward {
+            matches.iter().find(|idx| **idx > cursor)
+        } else {
+            matches.iter().rev().find(|idx| **idx < cursor)
+        };
+        let around = if search.forward {
+            matches.first()
+        } else {
+            matches.last()
+        };
+        self.last_search = Some(search);
+
+        let (start, found) = match (further, around) {
+            (Some(start), _) => (*start, Found::Match),
+            (None, Some(start)) if self.settings.search_wraps => (*start, Found::Wrapped),
+            (None, Some(_)) => return Found::End,
+            (None, None) => return Found::Nothing,
+        };
+
+        // with the cursor at the start, searching again either way doesn't find this match again
+        self.cursors.clear();
+        self.select_chars(start..start + len);
+        found
+    }
+
+    /// Searches a paged file a page at a time from the cursor, with only the pages around where
+    /// it's got to in the buffer. Going around carries on from the other end of the file, as
+    /// far as the cursor. If there's no match, it goes back to where it started.
+    fn search_pages(&mut self, search: Search) -> Found {
+        let query = search.query.clone();
+        let forward = search.forward;
+        let len = query.chars().count();
+        let from = self.chars_before() + self.cursor_char();
+        let view = self.file_view();
+        self.last_search = Some(search);
+
+        // matches that pages cut in two are found in the next one, since the last is kept
+        let next_match = |editor: &Self| {
+            let before = editor.chars_before();
+            let mut matches = editor
+                .matches(&query)
+                .into_iter()
+                .map(move |idx| before + idx);
+            if forward {
+                matches.find(|idx| *idx > from)
+            } else {
+                matches.rfind(|idx| *idx < from)
+            }
+        };
+        let mut found = self.scan_pages(forward, |editor| match next_match(editor) {
+            Some(idx) => ControlFlow::Break(Some(idx)),
+            None => ControlFlow::Continue(()),
+        });
+        let mut outcome = Found::Match;
+
+        if found.is_none() && self.settings.search_wraps {
+            let end = if forward { 0 } else { usize::MAX };
+            self.show_file_chars(end..end);
+            outcome = Found::Wrapped;
+            found = self.scan_pages(forward, |editor| {
+                let matches = editor.matches(&query);
+                let start = editor.chars_before();
+                let end = start + editor.buffer.len_chars();
+                let idx = if forward {
+                    matches.first()
+                } else {
+                    matches.last()
+                };
+                match idx {
+                    Some(idx) => ControlFlow::Break(Some(start + idx)),
+                    // what's past the cursor was searched the first time around
+                    None if forward && start > from || !forward && end < from => {
+                        ControlFlow::Break(None)
+                    }
+                    None => ControlFlow::Continue(()),
+                }
+            });
+        }
+
+        let Some(start) = found else {
+            self.set_file_view(view);
+            return if self.settings.search_wraps {
+                Found::Nothing
+            } else {
+                Found::End
+            };
+        };
+
+        let start = self.show_file_chars(start..start + len).start;
+        self.cursors.clear();
+        self.select_chars(start..start + len);
+        outcome
+    }
+
+    /// Pages on through a paged file one way until `check` stops, returning what it stopped
+    /// with, or `None` at the end of the file. Only the last couple of pages are kept as it goes.
+    fn scan_pages(
+        &mut self,
+        forward: bool,
+        check: impl Fn(&Self) -> ControlFlow<Option<usize>>,
+    ) -> Option<usize> {
+        loop {
+            if let ControlFlow::Break(found) = check(self) {
+                return found;
+            }
+            let paged = if forward {
+                self.page_in_after()
+            } else {
+                self.page_in_before()
+            };
+            if !paged {
+                return None;
+            }
+            let keep = if forward { self.buffer.len_chars() } else { 0 };
+            self.trim_pages(keep..keep);
+        }
+    }
+
+    /// Reads more of a file that's still loading until there's a match for `query` after
+    /// `cursor` or there's nothing left to read, returning the matches.
+    fn load_until_match(&mut self, query: &str, cursor: usize) -> Vec<usize> {
+        loop {
+            // a match that's only partly been read would start after all of these
+            let matches = self.matches(query);
+            if self.loader.is_none() || matches.iter().any(|idx| *idx > cursor) {
+                return matches;
+            }
+            self.load_lines(self.buffer.len_lines() * 2);
+        }
+    }
+
+    /// Where each match for `query` starts, only matching case if it has capitals in it.
+    fn matches(&self, query: &str) -> Vec<usize> {
+        let mut text = self.buffer.to_string();
+        let mut query = query.to_string();
+        if !query.chars().any(char::is_uppercase) {
+            // only ASCII, so that byte offsets stay the same
+            text.make_ascii_lowercase();
+            query.make_ascii_lowercase();
+        }
+
+        text.match_indices(&query)
+            .map(|(idx, _)| self.buffer.byte_to_char(idx))
+            .collect()
+    }
+
+    /// Which match for the last search the cursor is at the start of, counting from 1,
+    /// and how many there are.
+    pub fn search_position(&mut self) -> Option<(usize, usize)> {
+        let query = &self.last_search.as_ref()?.query;
+        let cursor = self.cursor_char();
+        let len = query.chars().count();
+        if cursor + len > self.buffer.len_chars() {
+            return None;
+        }
+        // so as not to count them all after every edit when nowhere near a match
+        let at_cursor = self.buffer.slice(cursor..cursor + len).to_string();
+        let matching = if query.chars().any(char::is_uppercase) {
+            at_cursor == *query
+        } else {
+            at_cursor.eq_ignore_ascii_case(query)
+        };
+        if !matching {
+            return None;
+        }
+
+        let counted = self
+            .search_matches
+            .as_ref()
+            .is_some_and(|matches| matches.query == *query && matches.changes == self.changes);
+        if !counted {
+            self.search_matches = Some(SearchMatches {
+                query: query.clone(),
+                changes: self.changes,
+                starts: self.matches(query),
+            });
+        }
+
+        let starts = &self.search_matches.as_ref()?.starts;
+        let idx = starts.binary_search(&cursor).ok()?;
+        Some((idx + 1, starts.len()))
+    }
+
+    fn is_blank(&self, row: usize) -> bool {
+        self.line(row).chars().all(char::is_whitespace)
+    }
+
+    pub fn scroll_up(&mut self) {
+        if self.scroll == 0 {
+            return;
+        }
+
+        self.scroll = self.previous_visible_row(self.scroll);
+
+        if self.screen_row(self.row) >= self.height {
+            let last_row = self.visible_rows(self.scroll).take(self.height).last();
+            self.row = last_row.unwrap_or(self.scroll);
+            self.clamp_column();
+            self.h_scroll_to_show_cursor();
+        }
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.load_lines(self.scroll + self.height + 1);
+
+        // half height overscroll
+        if self.scroll >= self.buffer.len_lines().saturating_sub(self.height / 2) {
+            return;
+        }
+
+        // a fold that goes to the end of the buffer leaves nothing after it to scroll to
+        let next = self.next_visible_row(self.scroll);
+        self.load_lines(next);
+        if next >= self.buffer.len_lines() {
+            return;
+        }
+        self.scroll = next;
+
+        if self.row < self.scroll {
+            self.row = self.scroll;
+            self.clamp_column();
+            self.h_scroll_to_show_cursor();
+        }
+    }
+
+    fn scroll_to_show_cursor(&mut self) {
+        // the cursor can end up inside a fold from a fold being made in another view
+        if let Some(fold) = self.fold_hiding(self.row) {
+            self.row = fold.start;
+            self.clamp_column();
+        }
+        if let Some(fold) = self.fold_hiding(self.scroll) {
+            self.scroll = fold.start;
+        }
+
+        if self.row < self.scroll {
+            self.scroll = self.row;
+        } else if self.screen_row(self.row) >= self.height {
+            // just enough to bring the cursor's row in at the bottom
+            let rows = self.visible_rows_back(self.row).take(self.height.max(1));
+            self.scroll = rows.last().unwrap();
+        }
+
+        self.load_lines(self.scroll + self.height);
+        self.h_scroll_to_show_cursor();
+    }
+
+    pub fn h_scroll_to_show_cursor(&mut self) {
+        let column = self.display_column(self.row, self.column);
+        if column < self.h_scroll {
+            self.h_scroll = column;
+        } else if column >= self.h_scroll + self.text_width() {
+            self.h_scroll = column + 1 - self.text_width();
+        }
+    }
+
+    /// Reads the rest of a file that's still loading. A paged file is never read all at once.
+    pub fn load_all(&mut self) {
+        if self.pager.is_none() {
+            self.load_lines(usize::MAX - 1);
+        }
+    }
+
+    /// Replaces the whole buffer, such as after reading the file again.
+    pub fn reload(&mut self, text: &str) {
+        self.buffer = Rope::from_str(text);
+        self.bracket_depths.clear();
+        self.mark = None;
+        self.set_language(self.highlighter.language());
+        #[cfg(feature = "tree-sitter")]
+        if let Some(tree) = &mut self.tree {
+            tree.reset(&self.buffer);
+        }
+        self.selection = None;
+        self.folds.clear();
+        self.history = History::default();
+        self.change_end = None;
+        self.hunks_stale = true;
+        self.modified = false;
+        self.changes += 1;
+        self.diagnostics.clear();
+        self.snippet = None;
+        self.cursors.clear();
+
+        self.row = self.row.min(self.buffer.len_lines() - 1);
+        self.clamp_column();
+        self.scroll_to_show_cursor();
+    }
+
+    /// Makes sure `row` has been completely read in when lazily loading.
+    pub fn load_lines(&mut self, row: usize) {
+        if self.pager.is_some() {
+            // pages that aren't needed any more are paged out again by `settle_pages`
+            while self.buffer.len_lines() <= row + 1 && self.page_in_after() {}
+            return;
+        }
+
+        // the last line in the buffer might only be partially read,
+        // so we need to load until there's at least one line after `row`
+        while self.buffer.len_lines() <= row + 1 {
+            let loader = match &mut self.loader {
+                Some(loader) => loader,
+                None => return,
+            };
+
+            let mut line = String::new();
+            match loader.reader.read_line(&mut line) {
+                Ok(0) => self.loader = None,
+                Ok(n) => {
+                    loader.offset += n as u64;
+                    let last_row = self.buffer.len_lines() - 1;
+                    self.buffer.insert(self.buffer.len_chars(), &line);
+                    self.highlighter
+                        .edited(last_row..last_row + 1, line.matches('\n').count() + 1);
+                    self.bracket_depths.truncate(last_row + 1);
+                }
+                // we can't show the rest of the file,
+                // so make sure what we have loaded isn't saved over it
+                Err(_) => {
+                    self.loader = None;
+                    self.read_only = true;
+                }
+            }
+        }
+    }
+
+    pub fn is_paged(&self) -> bool {
+        self.pager.is_some()
+    }
+
+    /// How many rows of a paged file are paged out before the buffer, which is what to add to
+    /// a row of the buffer to get the row of the file.
+    pub fn rows_before(&self) -> usize {
+        self.pager.as_ref().map_or(0, Pager::rows_before)
+    }
+
+    fn chars_before(&self) -> usize {
+        self.pager.as_ref().map_or(0, Pager::chars_before)
+    }
+
+    fn has_next_page(&self) -> bool {
+        self.pager.as_ref().is_some_and(Pager::has_next)
+    }
+
+    /// How far through a paged file the view starts, as a percentage.
+    pub fn page_position(&self) -> Option<u64> {
+        let (bytes, len) = self.view_bytes()?;
+        Some(bytes.start * 100 / len.max(1))
+    }
+
+    /// Which bytes of a paged file are in view, and how many bytes long the whole file is.
+    fn view_bytes(&self) -> Option<(Range<u64>, u64)> {
+        let pager = self.pager.as_ref()?;
+        let before = pager.bytes_before();
+        let last_row = self
+            .rows_on_screen()
+            .last()
+            .map_or(self.scroll, |(_, row)| row);
+        let start = before + self.buffer.line_to_byte(self.scroll) as u64;
+        let end = self
+            .buffer
+            .line_to_byte((last_row + 1).min(self.buffer.len_lines()));
+        let len = before + self.buffer.len_bytes() as u64 + pager.bytes_after();
+        Some((start..before + end as u64, len))
+    }
+
+    /// Pages in a paged file's next page on either side of what's in view and everything that
+    /// points into the buffer, like the cursors and the mark, and pages out the rest.
+    ///
+    /// This is done between commands rather than by them, so that rows don't shift under a
+    /// command as it goes. Anything that goes further in one go pages its own way there.
+    pub fn settle_pages(&mut self) {
+        while let Some(pager) = &self.pager {
+            let pages = pager.pages();
+            let pinned = self.pinned_chars();
+            if pinned.start < pages[0] && self.page_in_before() {
+                continue;
+            }
+            let last = self.buffer.len_chars() - pages[pages.len() - 1];
+            if pinned.end >= last && self.page_in_after() {
+                continue;
+            }
+            break;
+        }
+        self.trim_pages(self.pinned_chars());
+    }
+
+    /// The chars from the first to the last of the ones in view and the ones pointed at.
+    fn pinned_chars(&self) -> Range<usize> {
+        let last_row = self
+            .rows_on_screen()
+            .last()
+            .map_or(self.scroll, |(_, row)| row);
+        let mut pinned = vec![
+            self.buffer.line_to_char(self.scroll),
+            self.buffer
+                .line_to_char((last_row + 1).min(self.buffer.len_lines())),
+            self.cursor_char(),
+        ];
+        if let Some(selection) = self.selection {
+            pinned.push(self.position_to_char(self.clamp_position(selection.anchor)));
+        }
+        pinned.extend(&self.cursors);
+        pinned.extend(self.mark);
+        if let Some(snippet) = &self.snippet {
+            pinned.extend(snippet.stops.iter().flat_map(|stop| [stop.start, stop.end]));
+        }
+
+        let start = pinned.iter().min().unwrap();
+        let end = pinned.iter().max().unwrap();
+        *start..*end
+    }
+
+    /// Pages out the pages that are more than a page away from the chars in `keep`.
+    fn trim_pages(&mut self, mut keep: Range<usize>) {
+        while let Some(pager) = &self.pager {
+            let pages = pager.pages();
+            let n = pages.len();
+            if n > 2 && pages[0] + pages[1] < keep.start && self.page_out_first() {
+                keep = keep.start - pages[0]..keep.end - pages[0];
+                continue;
+            }
+            // where the second to last page starts
+            if n > 2
+                && self.buffer.len_chars() - pages[n - 1] - pages[n - 2] > keep.end
+                && self.page_out_last()
+            {
+                continue;
+            }
+            break;
+        }
+    }
+
+    /// Reads the page before the buffer into the start of it, returning whether there was one.
+    fn page_in_before(&mut self) -> bool {
+        let Some(pager) = &mut self.pager else {
+            return false;
+        };
+        let text = match pager.previous_page() {
+            Ok(Some(text)) => text,
+            Ok(None) => return false,
+            // like with a file that can't be read to the end, what's there can't be saved
+            Err(_) => {
+                self.read_only = true;
+                return false;
+            }
+        };
+
+        let chars = text.chars().count();
+        self.buffer.insert(0, &text);
+        let rows = self.buffer.char_to_line(chars);
+        self.highlighter.edited(0..1, rows + 1);
+        self.bracket_depths.clear();
+        self.shift_for_paging(|idx| Some(idx + chars), |row| Some(row + rows));
+        true
+    }
+
+    /// Reads the page after the buffer onto the end of it, returning whether there was one.
+    fn page_in_after(&mut self) -> bool {
+        let Some(pager) = &mut self.pager else {
+            return false;
+        };
+        let text = match pager.next_page() {
+            Ok(Some(text)) => text,
+            Ok(None) => return false,
+            Err(_) => {
+                self.read_only = true;
+                return false;
+            }
+        };
+
+        let last_row = self.buffer.len_lines() - 1;
+        self.buffer.insert(self.buffer.len_chars(), &text);
+        self.highlighter
+            .edited(last_row..last_row + 1, self.buffer.len_lines() - last_row);
+        self.bracket_depths.truncate(last_row + 1);
+        self.changes += 1;
+        true
+    }
+
+    /// Pages out the buffer's first page, returning whether it could. It can't if it's the
+    /// only one, or if it doesn't end with a newline, since then the page after it would start
+    /// partway through a line.
+    fn page_out_first(&mut self) -> bool {
+        let Some(pager) = &mut self.pager else {
+            return false;
+        };
+        let pages = pager.pages();
+        let chars = pages[0];
+        if pages.len() < 2 || chars == 0 || self.buffer.char(chars - 1) != '\n' {
+            return false;
+        }
+
+        let rows = self.buffer.char_to_line(chars);
+        pager.page_out_first(self.buffer.slice(..chars));
+        self.buffer.remove(..chars);
+        self.highlighter.edited(0..rows + 1, 1);
+        self.bracket_depths.clear();
+        self.shift_for_paging(|idx| idx.checked_sub(chars), |row| row.checked_sub(rows));
+        true
+    }
+
+    /// Pages out the buffer's last page, returning whether it could, which is the same as for
+    /// the first page.
+    fn page_out_last(&mut self) -> bool {
+        let Some(pager) = &mut self.pager else {
+            return false;
+        };
+        let pages = pager.pages();
+        let start = self.buffer.len_chars() - pages[pages.len() - 1];
+        if pages.len() < 2 || start == 0 || self.buffer.char(start - 1) != '\n' {
+            return false;
+        }
+
+        let row = self.buffer.char_to_line(start);
+        let rows = self.buffer.len_lines();
+        pager.page_out_last(self.buffer.slice(start..));
+        self.buffer.remove(start..);
+        self.highlighter.edited(row..rows, 1);
+        self.bracket_depths.truncate(row + 1);
+        self.shift_for_paging(
+            |idx| (idx <= start).then_some(idx),
+            |r| (r <= row).then_some(r),
+        );
+        true
+    }
+
+    /// Keeps everything that points into the buffer pointing at the same text after paging has
+    /// added or taken away text at one end of it, with `char` and `row` saying where chars and
+    /// rows have ended up, if they're still in the buffer. What's been paged out is forgotten.
+    fn shift_for_paging(
+        &mut self,
+        char: impl Fn(usize) -> Option<usize>,
+        row: impl Fn(usize) -> Option<usize>,
+    ) {
+        self.changes += 1;
+        let last_row = self.buffer.len_lines() - 1;
+        self.row = row(self.row).unwrap_or(0).min(last_row);
+        self.scroll = row(self.scroll).unwrap_or(0).min(last_row);
+        self.clamp_column();
+        self.selection = self.selection.and_then(|selection| {
+            Some(Selection {
+                anchor: (row(selection.anchor.0)?, selection.anchor.1),
+                ..selection
+            })
+        });
+
+        self.cursors = self
+            .cursors
+            .iter()
+            .filter_map(|cursor| char(*cursor))
+            .collect();
+        self.mark = self.mark.and_then(&char);
+        self.snippet = self.snippet.take().and_then(|mut snippet| {
+            for stop in &mut snippet.stops {
+                *stop = char(stop.start)?..char(stop.end)?;
+            }
+            Some(snippet)
+        });
+        self.diagnostics.retain_mut(|diagnostic| {
+            match (char(diagnostic.range.start), char(diagnostic.range.end)) {
+                (Some(start), Some(end)) => {
+                    diagnostic.range = start..end;
+                    true
+                }
+                _ => false,
+            }
+        });
+        let expansions = self
+            .expansions
+            .iter()
+            .map(|expansion| Some(char(expansion.start)?..char(expansion.end)?))
+            .collect::<Option<_>>();
+        self.expansions = expansions.unwrap_or_default();
+        self.change_end = self
+            .change_end
+            .and_then(|(kind, end)| Some((kind, char(end)?)));
+        self.folds = self
+            .folds
+            .iter()
+            .filter_map(|fold| Some(row(fold.start)?..row(fold.end - 1)? + 1))
+            .collect();
+    }
+
+    /// Pages through a paged file towards what `place` is looking for, keeping only the pages
+    /// around where it's got to as it goes. `place` says which way that is from the buffer, or
+    /// that it's in it, along with which of the buffer's chars to keep.
+    fn page_to(&mut self, place: impl Fn(&Self) -> (Ordering, Range<usize>)) {
+        loop {
+            let paged = match place(self).0 {
+                Ordering::Less => self.page_in_before(),
+                Ordering::Greater => self.page_in_after(),
+                Ordering::Equal => return,
+            };
+            if !paged {
+                return;
+            }
+            self.trim_pages(place(self).1);
+        }
+    }
+
+    /// Pages through a paged file until its chars in `range` are in the buffer, returning where
+    /// they are in it.
+    fn show_file_chars(&mut self, range: Range<usize>) -> Range<usize> {
+        let place = |editor: &Self| {
+            let start = editor.chars_before();
+            let end = start + editor.buffer.len_chars();
+            let order = if range.start < start {
+                Ordering::Less
+            } else if range.end > end {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            };
+            let clamp = |idx: usize| idx.clamp(start, end) - start;
+            (order, clamp(range.start)..clamp(range.end))
+        };
+        self.page_to(place);
+        place(self).1
+    }
+
+    /// Pages through a paged file until its `row` is in the buffer, returning which row of the
+    /// buffer it is, or the last one if the file doesn't have that many rows. A file that's
+    /// still loading is read as far as it.
+    pub fn show_file_row(&mut self, row: usize) -> usize {
+        if self.pager.is_none() {
+            self.load_lines(row);
+        }
+        self.page_to(|editor| {
+            let before = editor.rows_before();
+            let len = editor.buffer.len_chars();
+            if row < before {
+                (Ordering::Less, 0..0)
+            // the last row is where the next page starts
+            } else if row - before >= editor.buffer.len_lines() - 1 && editor.has_next_page() {
+                (Ordering::Greater, len..len)
+            } else {
+                let row = (row - before).min(editor.buffer.len_lines() - 1);
+                let start = editor.buffer.line_to_char(row);
+                (Ordering::Equal, start..start)
+            }
+        });
+
+        let row = row.saturating_sub(self.rows_before());
+        row.min(self.buffer.len_lines() - 1)
+    }
+
+    /// Pages through a paged file until its byte `byte` is in the buffer, returning the row of
+    /// the buffer it's in.
+    fn show_file_byte(&mut self, byte: u64) -> usize {
+        let place = |editor: &Self| {
+            let before = editor.pager.as_ref().map_or(0, Pager::bytes_before);
+            let len = editor.buffer.len_bytes() as u64;
+            let chars = editor.buffer.len_chars();
+            if byte < before {
+                (Ordering::Less, 0..0)
+            } else if byte >= before + len && editor.has_next_page() {
+                (Ordering::Greater, chars..chars)
+            } else {
+                let idx = editor
+                    .buffer
+                    .byte_to_char(((byte - before).min(len)) as usize);
+                (Ordering::Equal, idx..idx)
+            }
+        };
+        self.page_to(place);
+        self.buffer.char_to_line(place(self).1.start)
+    }
+
+    /// Writes out the whole file, including the parts of a paged file that aren't in the
+    /// buffer.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        if let Some(pager) = &self.pager {
+            return pager.write(&self.buffer, out);
+        }
+
+        for chunk in self.buffer.chunks() {
+            out.write_all(chunk.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn clamp_column(&mut self) {
+        if self.settings.virtual_space {
+            return;
+        }
+
+        let len = self.line_len(self.row);
+        if self.column > len {
+            self.column = len;
+        }
+    }
+
+    /// The contents of the given line, excluding its trailing newline.
+    pub fn line(&self, row: usize) -> RopeSlice<'_> {
+        let line = self.buffer.line(row);
+        let len = line.len_chars();
+        if len != 0 && line.char(len - 1) == '\n' {
+            line.slice(..len - 1)
+        } else {
+            line
+        }
+    }
+
+    fn line_len(&self, row: usize) -> usize {
+        self.line(row).len_chars()
+    }
+
+    /// How many lines there are, as they'd be counted in the saved file.
+    ///
+    /// A final newline ends the last line rather than starting another, so the empty row after
+    /// it is only there for the cursor to add more from, and doesn't count. An empty buffer is
+    /// one empty line.
+    pub fn line_count(&self) -> usize {
+        let rows = self.buffer.len_lines();
+        if rows > 1 && self.line_len(rows - 1) == 0 {
+            rows - 1
+        } else {
+            rows
+        }
+    }
+
+    /// `text` as it would be shown in the buffer, with tabs out to the next tab stop and control
+    /// characters in caret notation, so that it can be written to the terminal as it is.
+    pub fn displayed(&self, text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let highlights = vec![Highlight::default(); chars.len()];
+        let (shown, _) = expand_tabs(
+            &chars,
+            &highlights,
+            0,
+            0..usize::MAX,
+            self.settings.tab_width,
+        );
+        shown.into_iter().collect()
+    }
+
+    /// How many columns the view is scrolled to the right, so that's how much of every line is
+    /// off the left edge.
+    pub fn h_scroll(&self) -> usize {
+        self.h_scroll
+    }
+
+    /// How many lines are longer than the line length limit, or 0 if there isn't one.
+    /// A file that's still loading or paged isn't counted, since only part of it could be.
+    pub fn lines_over_limit(&mut self) -> usize {
+        let Some(limit) = self.settings.line_length_limit else {
+            return 0;
+        };
+        if self.loader.is_some() || self.pager.is_some() {
+            return 0;
+        }
+
+        let tab_width = self.settings.tab_width;
+        // so as not to go over the whole file every time the status bar is drawn
+        if let Some(long_lines) = &self.long_lines {
+            if long_lines.changes == self.changes
+                && long_lines.limit == limit
+                && long_lines.tab_width == tab_width
+            {
+                return long_lines.count;
+            }
+        }
+
+        let count = (0..self.line_count())
+            .filter(|row| self.display_column(*row, self.line_len(*row)) > limit)
+            .count();
+        self.long_lines = Some(LongLines {
+            changes: self.changes,
+            limit,
+            tab_width,
+            count,
+        });
+        count
+    }
+
+    /// Whether the buffer's last line is missing the newline that would end it,
+    /// which saving won't add. An empty buffer, a file that's still loading and a paged file
+    /// whose end isn't in the buffer don't count.
+    pub fn missing_final_newline(&self) -> bool {
+        self.loader.is_none()
+            && !self.has_next_page()
+            && self.line_len(self.buffer.len_lines() - 1) > 0
+    }
+
+    /// How far across the screen `column` of `row` is, with tabs reaching to the next tab stop.
+    fn display_column(&self, row: usize, column: usize) -> usize {
+        let mut width = 0;
+        let mut chars = 0;
+        for c in self.line(row).chars().take(column) {
+            width = advance(width, c, self.settings.tab_width);
+            chars += 1;
+        }
+
+        // columns past the end of the line in virtual space are a space wide
+        width + column - chars
+    }
+
+    /// The column of the char shown at `display_column` of `row`.
+    fn column_at_display(&self, row: usize, display_column: usize) -> usize {
+        let mut width = 0;
+        for (column, c) in self.line(row).chars().enumerate() {
+            let next = advance(width, c, self.settings.tab_width);
+            if next > display_column {
+                return column;
+            }
+            width = next;
+        }
+
+        self.line_len(row) + display_column - width
+    }
+
+    fn cursor_char(&self) -> usize {
+        self.position_to_char((self.row, self.column))
+    }
+
+    fn set_cursor_char(&mut self, idx: usize) {
+        self.row = self.buffer.char_to_line(idx);
+        self.column = idx - self.buffer.line_to_char(self.row);
+    }
+
+    /// Where a position is in the buffer, with positions past the end of their line
+    /// (in virtual space) at the end of it.
+    pub fn position_to_char(&self, (row, column): (usize, usize)) -> usize {
+        self.buffer.line_to_char(row) + column.min(self.line_len(row))
+    }
+
+    /// Moves a position that might have been left past the end of the buffer
+    /// (such as by an edit from another view) back inside it.
+    pub fn clamp_position(&self, (row, column): (usize, usize)) -> (usize, usize) {
+        let row = row.min(self.buffer.len_lines() - 1);
+        (row, column.min(self.line_len(row)))
+    }
+}
+
+/// Splits `line` into its indentation, along with the line comment `leader` and the space after
+/// it if it starts with one, and the text after that.
+fn quoted_text(line: &str, leader: Option<&str>) -> (String, String) {
+    let line = line.trim_end_matches('\n');
+    let indent = line.len() - line.trim_start().len();
+    let mut rest = &line[indent..];
+    if let Some((leader, after)) =
+        leader.and_then(|leader| Some((leader, rest.strip_prefix(leader)?)))
+    {
+        // doc comments like `///` and `//!` are the same comment with more after it
+        let last = leader.chars().last().unwrap();
+        let after = after.trim_start_matches(last);
+        let after = after.strip_prefix('!').unwrap_or(after);
+        rest = after.trim_start();
+    }
+    let prefix = &line[..line.len() - rest.len()];
+
+    (prefix.to_string(), rest.to_string())
+}
+
+/// How long the list marker that `text` starts with is, like `- ` or `1. `, or 0 if it doesn't
+/// start with one.
+fn list_marker(text: &str) -> usize {
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    let marker = match text[digits..].chars().next() {
+        Some('-' | '*' | '+') if digits == 0 => 1,
+        Some('.' | ')') if digits > 0 => digits + 1,
+        _ => return 0,
+    };
+    match text[marker..].chars().take_while(|c| *c == ' ').count() {
+        0 => 0,
+        spaces => marker + spaces,
+    }
+}
+
+/// How many line breaks there are before the char at `idx`.
+fn line_breaks_before(text: &str, idx: usize) -> usize {
+    text.chars().take(idx).filter(|c| *c == '\n').count()
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The columns where `word` appears in `line` as a whole word.
+fn word_occurrences<'a>(line: &'a [char], word: &'a [char]) -> impl Iterator<Item = usize> + 'a {
+    (0..line.len().saturating_sub(word.len() - 1)).filter(move |start| {
+        let end = start + word.len();
+        line[*start..end] == *word
+            && (*start == 0 || !is_word_char(line[start - 1]))
+            && (end == line.len() || !is_word_char(line[end]))
+    })
+}
+
+/// The bracket nesting depth after `c`, given the depth before it.
+fn nest(depth: usize, c: char) -> usize {
+    match c {
+        '(' | '[' | '{' => depth + 1,
+        ')' | ']' | '}' => depth.saturating_sub(1),
+        _ => depth,
+    }
+}
+
+/// Whether `column` is in a string or comment, going by the line's `tokens`.
+fn in_string_or_comment(tokens: &[(Range<usize>, syntax::Token)], column: usize) -> bool {
+    tokens.iter().any(|(columns, token)| {
+        columns.contains(&column) && matches!(token, syntax::Token::String | syntax::Token::Comment)
+    })
+}
+
+/// The digits of the number `column` is in, or of the first one after it.
+fn number_at(line: &[char], column: usize) -> Option<Range<usize>> {
+    let mut start = column.min(line.len());
+    while start > 0 && line[start - 1].is_ascii_digit() {
+        start -= 1;
+    }
+    start += line[start..].iter().position(char::is_ascii_digit)?;
+
+    let len = line[start..]
+        .iter()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    Some(start..start + len)
+}
+
+/// The display column after `c`, if it starts at `column`.
+fn advance(column: usize, c: char, tab_width: usize) -> usize {
+    if c == '\t' {
+        column + tab_width - column % tab_width
+    } else if caret_notation(c).is_some() {
+        column + 2
+    } else {
+        column + 1
+    }
+}
+
+/// How a control character other than a tab is shown after a `^`, like `^[` for escape.
+fn caret_notation(c: char) -> Option<char> {
+    match c {
+        '\t' => None,
+        '\u{7f}' => Some('?'),
+        _ if c.is_ascii_control() => Some((c as u8 + b'@') as char),
+        _ => None,
+    }
+}
+
+/// Replaces tabs with spaces up to the next tab stop and control characters with caret
+/// notation, given the display column `text` starts at, and cuts off whatever's outside of
+/// `view`.
+fn expand_tabs(
+    text: &[char],
+    highlights: &[Highlight],
+    mut column: usize,
+    view: Range<usize>,
+    tab_width: usize,
+) -> (Vec<char>, Vec<Highlight>) {
+    let mut expanded = Vec::new();
+    let mut expanded_highlights = Vec::new();
+
+    for (c, highlight) in text.iter().zip(highlights) {
+        let next = advance(column, *c, tab_width);
+        let visible = column.max(view.start)..next.min(view.end);
+        if let Some(caret) = caret_notation(*c) {
+            let highlight = Highlight {
+                kind: HighlightKind::ControlChar,
+                ..*highlight
+            };
+            // either half can be cut off by the edge of the view
+            for (cell, shown) in (column..next).zip(['^', caret]) {
+                if visible.contains(&cell) {
+                    expanded.push(shown);
+                    expanded_highlights.push(highlight);
+                }
+            }
+        } else {
+            // C1 control characters have no caret notation, but can't be written out either
+            let shown = match c {
+                '\t' => ' ',
+                _ if c.is_control() => '\u{fffd}',
+                _ => *c,
+            };
+            let cells = visible.end.saturating_sub(visible.start);
+            expanded.extend(iter::repeat_n(shown, cells));
+            expanded_highlights.extend(iter::repeat_n(*highlight, cells));
+        }
+        column = next;
+    }
+
+    (expanded, expanded_highlights)
+}
+
+/// Groups runs of characters with the same highlight into spans.
+fn spans(text: &[char], highlights: &[Highlight]) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+
+    for (c, highlight) in text.iter().zip(highlights) {
+        match spans.last_mut() {
+            Some(span) if span.highlight == *highlight => span.text.push(*c),
+            _ => spans.push(Span {
+                text: c.to_string(),
+                highlight: *highlight,
+            }),
+        }
+    }
+
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    /// Edits and movements the way the frontend makes them, at every cursor.
+    fn random_step(source_editor: &mut SourceEditor, rng: &mut Rng, clipboard: &mut Clipboard) {
+        const CHARS: &[char] = &['a', ' ', '(', '}', '"', 'é', '語', '\t'];
+        let extend = rng.below(4) == 0;
+
+        match rng.below(25) {
+            0..=3 => {
+                let c = rng.pick(CHARS);
+                source_editor.at_each_cursor(|source_editor| source_editor.keypress(c));
+            }
+            4 => source_editor.at_each_cursor(SourceEditor::backspace),
+            5 => source_editor.at_each_cursor(SourceEditor::enter),
+            6 => source_editor.at_each_cursor(SourceEditor::tab),
+            7 => source_editor.dedent(),
+            8 => source_editor.move_cursor(extend, SourceEditor::left),
+            9 => source_editor.move_cursor(extend, SourceEditor::right),
+            10 => source_editor.move_cursor(extend, SourceEditor::up),
+            11 => source_editor.move_cursor(extend, SourceEditor::down),
+            12 => source_editor.move_cursor(extend, SourceEditor::home),
+            13 => source_editor.move_cursor(extend, SourceEditor::end),
+            14 => source_editor.move_cursor(extend, SourceEditor::next_blank_line),
+            15 => source_editor.move_cursor(extend, SourceEditor::previous_blank_line),
+            16 => source_editor.undo(),
+            17 => source_editor.redo(),
+            18 => {
+                if let Some(cut) = source_editor.cut() {
+                    *clipboard = cut;
+                }
+            }
+            19 => source_editor.paste(clipboard),
+            20 => source_editor.add_cursor_vertically(rng.below(2) == 0),
+            21 => source_editor.toggle_fold(),
+            22 => match rng.below(4) {
+                0 => source_editor.scroll_up(),
+                1 => source_editor.scroll_down(),
+                2 => source_editor.unfold_all(),
+                _ => source_editor.fold_all(),
+            },
+            23 => match rng.below(2) {
+                0 => source_editor.earlier(),
+                _ => source_editor.later(),
+            },
+            _ => source_editor.resize(1 + rng.below(80), 1 + rng.below(20)),
+        }
+    }
+
+    fn check_invariants(source_editor: &SourceEditor) {
+        let lines = source_editor.buffer.len_lines();
+        assert!(lines > 0, "the buffer has no lines");
+        assert!(
+            source_editor.row < lines,
+            "row {} is past the end",
+            source_editor.row
+        );
+        assert!(
+            source_editor.column <= source_editor.line_len(source_editor.row),
+            "column {} is past the end of row {}",
+            source_editor.column,
+            source_editor.row,
+        );
+        assert!(
+            source_editor.fold_hiding(source_editor.row).is_none(),
+            "row {} is hidden in a fold",
+            source_editor.row,
+        );
+        assert!(
+            source_editor.scroll <= source_editor.row
+                && source_editor.screen_row(source_editor.row) < source_editor.height,
+            "row {} isn't shown when scrolled to {}",
+            source_editor.row,
+            source_editor.scroll,
+        );
+
+        let mut depth = 0;
+        for (row, known) in source_editor.bracket_depths.iter().enumerate() {
+            assert_eq!(
+                *known, depth,
+                "the bracket depth of row {row} is out of date"
+            );
+            depth = source_editor.nest_row(row, depth);
+        }
+    }
+
+    #[test]
+    fn random_edits_keep_the_cursor_in_bounds() {
+        const TEXT: &str = "fn main() {\n\tlet s = \"héllo\";\n    if x { y(); }   \n}\n\n日本語\n";
+
+        for seed in 1..=100 {
+            eprintln!("seed {seed}");
+            let mut rng = Rng(seed);
+            let mut source_editor = SourceEditor::new(TEXT.to_string());
+            source_editor.resize(40, 6);
+            let mut clipboard = Clipboard::default();
+
+            for _ in 0..300 {
+                random_step(&mut source_editor, &mut rng, &mut clipboard);
+                source_editor.update_highlighting();
+                check_invariants(&source_editor);
+            }
+        }
+    }
+
+    #[test]
+    fn brackets_in_strings_and_comments_dont_nest() {
+        let mut source_editor = SourceEditor::new("let s = \"(\"; // [\nf(x);\n".to_string());
+        source_editor.set_language(syntax::Language::named("rust"));
+        source_editor.resize(40, 5);
+        source_editor.update_highlighting();
+        assert_eq!(source_editor.bracket_depths[..2], [0, 0]);
+
+        let (lines, _, _) = source_editor.render();
+        let bracket = lines[1].iter().find(|span| span.text == "(").unwrap();
+        assert_eq!(bracket.highlight.kind, HighlightKind::Bracket(0));
+    }
+
+    #[test]
+    fn markers_in_source_files_are_only_highlighted_in_comments() {
+        let mut source_editor = SourceEditor::new("let TODO = \"TODO\"; // TODO\n".to_string());
+        source_editor.settings.markers = vec!["TODO".to_string()];
+        source_editor.set_language(syntax::Language::named("rust"));
+        source_editor.resize(40, 5);
+        source_editor.update_highlighting();
+
+        let (lines, _, _) = source_editor.render();
+        let markers: Vec<&str> = lines[0]
+            .iter()
+            .filter(|span| span.highlight.kind == HighlightKind::Marker)
+            .map(|span| span.text.as_str())
+            .collect();
+        assert_eq!(markers, ["TODO"]);
+        let last = lines[0]
+            .iter()
+            .rfind(|span| !span.text.trim().is_empty())
+            .unwrap();
+        assert_eq!(last.highlight.kind, HighlightKind::Marker);
+    }
+
+    #[test]
+    fn a_final_newline_doesnt_start_another_line() {
+        for (text, lines) in [("", 1), ("a", 1), ("a\nb\n", 2), ("a\n\n\n", 3), ("\n", 1)] {
+            let source_editor = SourceEditor::new(text.to_string());
+            assert_eq!(source_editor.line_count(), lines, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn control_characters_are_shown_in_caret_notation() {
+        let mut source_editor = SourceEditor::new("a\u{1b}[0mb\u{7}".to_string());
+        source_editor.resize(20, 5);
+        source_editor.column = 5;
+
+        let (lines, column, _) = source_editor.render();
+        let text: String = lines[0].iter().map(|span| span.text.as_str()).collect();
+        assert_eq!(text.trim(), "a^[[0mb^G");
+        assert_eq!(column, source_editor.gutter_width() + 6);
+
+        let shown = source_editor.displayed("-a\u{1b}[0m\tb\u{9b}");
+        assert_eq!(shown, "-a^[[0m b\u{fffd}");
+    }
+
+    #[test]
+    fn reflowing_keeps_indentation_comments_and_list_markers() {
+        let comments = Comments {
+            line: Some("//".to_string()),
+            block: None,
+        };
+        for (text, reflowed) in [
+            (
+                "one two three\nfour five six seven\n\nafter",
+                "one two three four\nfive six seven\n\nafter",
+            ),
+            (
+                "    // one two three four five six\n    //\n    // after",
+                "    // one two three\n    // four five six\n    //\n    // after",
+            ),
+            (
+                "- one two three four five six\n- after",
+                "- one two three four\n  five six\n- after",
+            ),
+        ] {
+            let mut source_editor = SourceEditor::new(text.to_string());
+            source_editor.settings.text_width = 20;
+            source_editor.reflow_paragraph(&comments).unwrap();
+            assert_eq!(source_editor.buffer.to_string(), reflowed);
+        }
+    }
+
+    #[test]
+    fn reflowing_keeps_the_cursor_on_its_word() {
+        let mut source_editor = SourceEditor::new("one two three\nfour five".to_string());
+        source_editor.settings.text_width = 40;
+        (source_editor.row, source_editor.column) = (1, 6);
+
+        source_editor
+            .reflow_paragraph(&Comments::default())
+            .unwrap();
+
+        assert_eq!(source_editor.buffer.to_string(), "one two three four five");
+        assert_eq!((source_editor.row, source_editor.column), (0, 20));
+    }
+
+    fn overwriting(text: &str, column: usize) -> SourceEditor {
+        let mut source_editor = SourceEditor::new(text.to_string());
+        source_editor.settings.overwrite = true;
+        source_editor.column = column;
+        source_editor
+    }
+
+    #[test]
+    fn typing_in_overwrite_mode_replaces_what_it_types_over() {
+        let mut source_editor = overwriting("abcd", 1);
+        source_editor.keypress('x');
+        source_editor.keypress('y');
+        assert_eq!(source_editor.buffer.to_string(), "axyd");
+
+        // there's nothing left to replace at the end of the line
+        source_editor.keypress('z');
+        source_editor.keypress('!');
+        assert_eq!(source_editor.buffer.to_string(), "axyz!");
+    }
+
+    #[test]
+    fn backspace_in_overwrite_mode_blanks_out_what_it_deletes() {
+        // the rest of the line stays where it is
+        let mut source_editor = overwriting("abcd", 2);
+        source_editor.backspace();
+        assert_eq!(source_editor.buffer.to_string(), "a cd");
+        assert_eq!(source_editor.column, 1);
+
+        // at the end of the line there's nothing after it to keep in place
+        let mut source_editor = overwriting("abcd", 4);
+        source_editor.backspace();
+        assert_eq!(source_editor.buffer.to_string(), "abc");
+
+        // at the start of a line there's nothing to blank out, so it joins lines as usual
+        let mut source_editor = overwriting("ab\ncd", 0);
+        source_editor.row = 1;
+        source_editor.backspace();
+        assert_eq!(source_editor.buffer.to_string(), "abcd");
+
+        // undoing brings back what was blanked out
+        let mut source_editor = overwriting("abcd", 3);
+        source_editor.backspace();
+        source_editor.backspace();
+        assert_eq!(source_editor.buffer.to_string(), "a  d");
+        source_editor.undo();
+        assert_eq!(source_editor.buffer.to_string(), "abcd");
+    }
+
+    #[test]
+    fn backspace_in_virtual_space_only_moves_back() {
+        let mut source_editor = SourceEditor::new("ab\n".to_string());
+        source_editor.settings.virtual_space = true;
+        source_editor.column = 5;
+
+        source_editor.backspace();
+
+        assert_eq!(source_editor.buffer.to_string(), "ab\n");
+        assert_eq!(source_editor.column, 4);
+    }
+
+    #[test]
+    fn joining_onto_a_long_line_shows_the_cursor() {
+        let mut source_editor = SourceEditor::new(format!("{}\nshort", "x".repeat(100)));
+        source_editor.resize(20, 5);
+        source_editor.row = 1;
+
+        source_editor.backspace();
+
+        assert_eq!((source_editor.row, source_editor.column), (0, 100));
+        let (x, _) = source_editor.screen_position(0, 100);
+        assert!(x < source_editor.width);
+    }
+
+    #[test]
+    fn sibling_lines_skip_deeper_ones_and_stay_in_their_block() {
+        let text =
+            "fn a() {\n    one;\n    if x {\n        two;\n\n    }\n\tthree;\n}\nfn b() {}\n";
+        let mut source_editor = SourceEditor::new(text.to_string());
+        source_editor.resize(40, 20);
+        source_editor.row = 1;
+
+        source_editor.next_sibling_line();
+        assert_eq!((source_editor.row, source_editor.column), (2, 4));
+        source_editor.next_sibling_line();
+        assert_eq!((source_editor.row, source_editor.column), (5, 4));
+        // a tab is as wide as the four spaces
+        source_editor.next_sibling_line();
+        assert_eq!((source_editor.row, source_editor.column), (6, 1));
+        // the closing brace ends the block
+        source_editor.next_sibling_line();
+        assert_eq!(source_editor.row, 6);
+
+        source_editor.previous_sibling_line();
+        assert_eq!(source_editor.row, 5);
+        source_editor.row = 1;
+        source_editor.previous_sibling_line();
+        assert_eq!(source_editor.row, 1);
+
+        source_editor.row = 0;
+        source_editor.next_sibling_line();
+        assert_eq!((source_editor.row, source_editor.column), (7, 0));
+    }
+
+    #[test]
+    fn undoing_and_then_editing_keeps_what_was_undone() {
+        let mut source_editor = SourceEditor::new(String::new());
+        let type_word = |source_editor: &mut SourceEditor, word: &str| {
+            for c in word.chars() {
+                source_editor.keypress(c);
+            }
+            source_editor.change_end = None;
+        };
+        type_word(&mut source_editor, "one");
+        type_word(&mut source_editor, " two");
+        source_editor.undo();
+        type_word(&mut source_editor, " three");
+        assert_eq!(source_editor.buffer.to_string(), "one three");
+        assert!(!source_editor.can_redo());
+
+        // going back in time goes through " two" even though it's on another branch
+        source_editor.earlier();
+        assert_eq!(source_editor.buffer.to_string(), "one two");
+        source_editor.earlier();
+        assert_eq!(source_editor.buffer.to_string(), "one");
+        source_editor.earlier();
+        assert_eq!(source_editor.buffer.to_string(), "");
+        assert!(!source_editor.can_undo());
+
+        source_editor.later();
+        source_editor.later();
+        assert_eq!(source_editor.buffer.to_string(), "one two");
+        assert_eq!(source_editor.history_position(), (2, 3));
+        source_editor.later();
+        assert_eq!(source_editor.buffer.to_string(), "one three");
+        assert!(!source_editor.can_go_later());
+
+        // redoing follows the branch that was last undone
+        source_editor.undo();
+        source_editor.undo();
+        source_editor.redo();
+        source_editor.redo();
+        assert_eq!(source_editor.buffer.to_string(), "one three");
+        source_editor.go_to_state(2);
+        source_editor.undo();
+        source_editor.redo();
+        assert_eq!(source_editor.buffer.to_string(), "one two");
+    }
+
+    #[test]
+    fn the_scrollbar_thumb_shows_what_part_of_the_buffer_is_in_view() {
+        let text = "line\n".repeat(100);
+        let mut source_editor = SourceEditor::new(text);
+        source_editor.settings.scrollbar = true;
+        source_editor.resize(20, 10);
+        assert_eq!(source_editor.scrollbar_thumb(), Some(0..1));
+
+        source_editor.scroll_to_scrollbar(5);
+        assert_eq!(source_editor.scroll, 50);
+        assert_eq!(source_editor.row, 50);
+        assert_eq!(source_editor.scrollbar_thumb(), Some(5..6));
+
+        // it takes a column away from the text
+        assert_eq!(source_editor.text_width(), 20 - FOLD_GUTTER_WIDTH - 1);
+
+        // a buffer that fits in the view fills the whole bar
+        let mut source_editor = SourceEditor::new("a\nb\n".to_string());
+        source_editor.settings.scrollbar = true;
+        source_editor.resize(20, 10);
+        assert_eq!(source_editor.scrollbar_thumb(), Some(0..10));
+    }
+
+    #[test]
+    fn other_cursors_edits_get_their_own_undo_step_when_the_main_one_does_nothing() {
+        let mut source_editor = SourceEditor::new("ab\ncd\n".to_string());
+        source_editor.jump_to_row(1);
+        source_editor.end();
+        source_editor.keypress('x');
+
+        // Backspace does nothing at the start of the buffer, but joins the other cursor's line
+        source_editor.jump_to_row(0);
+        source_editor.cursors = vec![3];
+        source_editor.at_each_cursor(SourceEditor::backspace);
+        assert_eq!(source_editor.buffer.to_string(), "abcdx\n");
+
+        source_editor.undo();
+        assert_eq!(source_editor.buffer.to_string(), "ab\ncdx\n");
+        assert_eq!((source_editor.row, source_editor.column), (0, 0));
+        source_editor.undo();
+        assert_eq!(source_editor.buffer.to_string(), "ab\ncd\n");
+        source_editor.redo();
+        assert_eq!(source_editor.buffer.to_string(), "ab\ncdx\n");
+        source_editor.redo();
+        assert_eq!(source_editor.buffer.to_string(), "abcdx\n");
+    }
+
+    /// Lines numbered from 0, to page through.
+    fn numbered_lines() -> String {
+        (0..1000).map(|idx| format!("line {idx}\n")).collect()
+    }
+
+    /// A source editor paging in a file of `text` a hundred or so bytes at a time.
+    fn paged(name: &str, text: &str) -> SourceEditor {
+        let path = std::env::temp_dir().join(format!("editor-{name}-{}", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let file = File::open(&path).unwrap();
+        // what's still open can still be read
+        let _ = std::fs::remove_file(&path);
+
+        let mut source_editor = SourceEditor::paged_by(file, text.len() as u64, 100).unwrap();
+        source_editor.resize(20, 5);
+        source_editor.settle_pages();
+        source_editor
+    }
+
+    fn file_text(source_editor: &SourceEditor) -> String {
+        let mut text = Vec::new();
+        source_editor.write_to(&mut text).unwrap();
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn paged_files_only_have_the_pages_around_the_view_in_the_buffer() {
+        let text = numbered_lines();
+        let mut source_editor = paged("paging", &text);
+        assert!(source_editor.buffer.len_bytes() < 500);
+
+        let row = source_editor.show_file_row(500);
+        source_editor.jump_to_row(row);
+        source_editor.settle_pages();
+        assert_eq!(source_editor.rows_before() + source_editor.row, 500);
+        assert_eq!(source_editor.line(source_editor.row), "line 500");
+        assert!(source_editor.buffer.len_bytes() < 500);
+        source_editor.keypress('x');
+
+        // the edit is kept while it's paged out, and undoing it pages back to it
+        let row = source_editor.show_file_row(0);
+        source_editor.jump_to_row(row);
+        source_editor.settle_pages();
+        assert_eq!(source_editor.rows_before(), 0);
+        assert!(!source_editor.buffer.to_string().contains("xline"));
+        assert_eq!(
+            file_text(&source_editor),
+            text.replace("line 500", "xline 500")
+        );
+
+        source_editor.undo();
+        source_editor.settle_pages();
+        assert_eq!(source_editor.rows_before() + source_editor.row, 500);
+        assert_eq!(file_text(&source_editor), text);
+        source_editor.redo();
+        assert_eq!(
+            file_text(&source_editor),
+            text.replace("line 500", "xline 500")
+        );
+    }
+
+    #[test]
+    fn searching_a_paged_file_pages_through_it() {
+        let mut source_editor = paged("paged-search", &numbered_lines());
+        let search = |query: &str| Search {
+            query: query.to_string(),
+            forward: true,
+        };
+
+        assert_eq!(source_editor.search(search("line 900")), Found::Match);
+        assert_eq!(source_editor.rows_before() + source_editor.row, 900);
+        assert!(source_editor.buffer.len_bytes() < 500);
+
+        // there's no "line 3" after line 900, so it goes around to the start for it
+        assert_eq!(source_editor.search(search("line 3")), Found::Wrapped);
+        assert_eq!(source_editor.rows_before() + source_editor.row, 3);
+
+        source_editor.settle_pages();
+        let row = source_editor.show_file_row(700);
+        source_editor.jump_to_row(row);
+        source_editor.settle_pages();
+        assert_eq!(source_editor.search(search("nowhere")), Found::Nothing);
+        assert_eq!(source_editor.rows_before() + source_editor.row, 700);
+        source_editor.settle_pages();
+        assert!(source_editor.buffer.len_bytes() < 500);
+    }
+}
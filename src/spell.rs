@@ -0,0 +1,167 @@
+//! Checks spelling against a list of words, such as the system's `/usr/share/dict/words`,
+//! along with a personal dictionary of words the user has added.
+
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const MAX_SUGGESTIONS: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct Dictionary {
+    /// Shared between editors, since it's big and never changes.
+    words: Rc<HashSet<String>>,
+    personal: HashSet<String>,
+    /// Where the personal dictionary is kept, if there's anywhere to keep it.
+    personal_path: Option<PathBuf>,
+}
+
+impl Dictionary {
+    pub fn load(path: &Path, personal_path: Option<PathBuf>) -> io::Result<Self> {
+        let words = fs::read_to_string(path)?
+            .lines()
+            .map(|word| word.trim().to_string())
+            .collect();
+
+        let personal = match personal_path.as_ref().map(fs::read_to_string) {
+            Some(Ok(text)) => text.lines().map(|word| word.trim().to_string()).collect(),
+            _ => HashSet::new(),
+        };
+
+        Ok(Self {
+            words: Rc::new(words),
+            personal,
+            personal_path,
+        })
+    }
+
+    /// Whether `word` is spelled correctly, allowing for it being capitalized
+    /// at the start of a sentence.
+    pub fn contains(&self, word: &str) -> bool {
+        let known = |word: &str| self.words.contains(word) || self.personal.contains(word);
+        let word = word.strip_suffix("'s").unwrap_or(word);
+
+        known(word) || known(&word.to_lowercase())
+    }
+
+    /// Adds `word` to the personal dictionary, saving it for next time.
+    pub fn add(&mut self, word: &str) -> io::Result<()> {
+        let path = self
+            .personal_path
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{word}")?;
+        self.personal.insert(word.to_string());
+
+        Ok(())
+    }
+
+    /// Words that are one change away from `word`, or two if there aren't any.
+    pub fn suggestions(&self, word: &str) -> Vec<String> {
+        let capitalized = word.starts_with(char::is_uppercase);
+        let lowercase = word.to_lowercase();
+
+        let mut suggestions: Vec<String> = edits(&lowercase)
+            .into_iter()
+            .filter(|edit| self.contains(edit))
+            .collect();
+        if suggestions.is_empty() {
+            suggestions = edits(&lowercase)
+                .iter()
+                .flat_map(|edit| edits(edit))
+                .filter(|edit| self.contains(edit))
+                .collect();
+        }
+
+        let mut seen = HashSet::new();
+        suggestions.retain(|suggestion| seen.insert(suggestion.clone()));
+        suggestions.truncate(MAX_SUGGESTIONS);
+
+        if capitalized {
+            for suggestion in &mut suggestions {
+                let mut chars = suggestion.chars();
+                if let Some(first) = chars.next() {
+                    *suggestion = first.to_uppercase().chain(chars).collect();
+                }
+            }
+        }
+
+        suggestions
+    }
+}
+
+/// The columns of the words in `line` that are worth checking.
+///
+/// Anything that looks like code, such as `snake_case`, `camelCase` or `v2`, is left out,
+/// as are acronyms.
+pub fn words(line: &[char]) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut idx = 0;
+
+    while idx < line.len() {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+        if !is_word(line[idx]) {
+            idx += 1;
+            continue;
+        }
+
+        let start = idx;
+        while idx < line.len() && is_word(line[idx]) {
+            idx += 1;
+        }
+        // quotes around a word aren't part of it
+        let mut word = start..idx;
+        while word.start < word.end && line[word.start] == '\'' {
+            word.start += 1;
+        }
+        while word.end > word.start && line[word.end - 1] == '\'' {
+            word.end -= 1;
+        }
+
+        let chars = &line[word.clone()];
+        let looks_like_code = chars.iter().any(|c| c.is_numeric() || *c == '_')
+            || chars.iter().skip(1).any(|c| c.is_uppercase());
+        if chars.len() > 1 && !looks_like_code {
+            words.push(word);
+        }
+    }
+
+    words
+}
+
+/// Everything one deletion, swap, replacement or insertion away from `word`.
+fn edits(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let letters = ('a'..='z').chain(['\'']);
+    let mut edits = Vec::new();
+
+    for idx in 0..=chars.len() {
+        let (before, after) = chars.split_at(idx);
+        let with = |middle: &[char], rest: &[char]| {
+            before.iter().chain(middle).chain(rest).collect::<String>()
+        };
+
+        if let Some((_, rest)) = after.split_first() {
+            edits.push(with(&[], rest));
+        }
+        if let [a, b, rest @ ..] = after {
+            edits.push(with(&[*b, *a], rest));
+        }
+        for letter in letters.clone() {
+            if let Some((_, rest)) = after.split_first() {
+                edits.push(with(&[letter], rest));
+            }
+            edits.push(with(&[letter], after));
+        }
+    }
+
+    edits
+}
@@ -0,0 +1,616 @@
+//! Syntax highlighting for a handful of languages, by recognising keywords, strings, comments
+//! and numbers line by line.
+//!
+//! Block comments and strings can span lines, so each line is highlighted starting from the
+//! state the previous line left off in. Those states are cached, and after an edit only the
+//! lines from the edit onward are looked at again, stopping once a line ends up in the same
+//! state it did before, since nothing after it can have changed either.
+
+use std::ops::Range;
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct Language {
+    /// What the language is called in the status bar, and when choosing it by hand.
+    pub name: &'static str,
+    extensions: &'static [&'static str],
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    /// Whether words starting with a capital letter are types, as is the convention in Rust.
+    capitalized_types: bool,
+    pub line_comment: Option<&'static str>,
+    pub block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    /// Whether `'a'` is a char literal, even though `'` isn't a string quote.
+    char_literals: bool,
+    /// Whether strings can go on past the end of a line.
+    multiline_strings: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Keyword,
+    Type,
+    String,
+    Comment,
+    Number,
+}
+
+/// What the text at the start of a line is inside of.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum State {
+    #[default]
+    Normal,
+    BlockComment,
+    String(char),
+}
+
+const LANGUAGES: &[Language] = &[
+    Language {
+        name: "Rust",
+        extensions: &["rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+            "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+            "trait", "true", "type", "unsafe", "use", "where", "while",
+        ],
+        types: &[
+            "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32",
+            "i64", "i128", "isize", "f32", "f64",
+        ],
+        capitalized_types: true,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+        char_literals: true,
+        multiline_strings: true,
+    },
+    Language {
+        name: "C",
+        extensions: &["c", "h"],
+        keywords: &[
+            "break", "case", "const", "continue", "default", "do", "else", "enum", "extern", "for",
+            "goto", "if", "inline", "return", "sizeof", "static", "struct", "switch", "typedef",
+            "union", "volatile", "while", "NULL", "#include", "#define", "#ifdef", "#ifndef",
+            "#endif", "#if", "#else",
+        ],
+        types: &[
+            "char", "double", "float", "int", "long", "short", "signed", "unsigned", "void",
+            "size_t", "bool",
+        ],
+        capitalized_types: false,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+        char_literals: true,
+        multiline_strings: false,
+    },
+    Language {
+        name: "C++",
+        extensions: &["cpp", "cc", "cxx", "hpp", "hh"],
+        keywords: &[
+            "auto",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "constexpr",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "enum",
+            "explicit",
+            "extern",
+            "false",
+            "for",
+            "friend",
+            "if",
+            "inline",
+            "namespace",
+            "new",
+            "nullptr",
+            "operator",
+            "private",
+            "protected",
+            "public",
+            "return",
+            "sizeof",
+            "static",
+            "struct",
+            "switch",
+            "template",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "typedef",
+            "typename",
+            "union",
+            "using",
+            "virtual",
+            "while",
+            "#include",
+            "#define",
+            "#ifdef",
+            "#ifndef",
+            "#endif",
+            "#if",
+            "#else",
+        ],
+        types: &[
+            "bool", "char", "double", "float", "int", "long", "short", "signed", "unsigned",
+            "void", "size_t",
+        ],
+        capitalized_types: false,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+        char_literals: true,
+        multiline_strings: false,
+    },
+    Language {
+        name: "Go",
+        extensions: &["go"],
+        keywords: &[
+            "break",
+            "case",
+            "chan",
+            "const",
+            "continue",
+            "default",
+            "defer",
+            "else",
+            "fallthrough",
+            "false",
+            "for",
+            "func",
+            "go",
+            "goto",
+            "if",
+            "import",
+            "interface",
+            "map",
+            "nil",
+            "package",
+            "range",
+            "return",
+            "select",
+            "struct",
+            "switch",
+            "true",
+            "type",
+            "var",
+        ],
+        types: &[
+            "bool", "byte", "error", "float32", "float64", "int", "int8", "int16", "int32",
+            "int64", "rune", "string", "uint", "uint8", "uint16", "uint32", "uint64",
+        ],
+        capitalized_types: false,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '`'],
+        char_literals: true,
+        multiline_strings: false,
+    },
+    Language {
+        name: "JavaScript",
+        extensions: &["js", "mjs", "cjs", "jsx"],
+        keywords: &[
+            "async",
+            "await",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "export",
+            "extends",
+            "false",
+            "finally",
+            "for",
+            "function",
+            "if",
+            "import",
+            "in",
+            "instanceof",
+            "let",
+            "new",
+            "null",
+            "of",
+            "return",
+            "static",
+            "super",
+            "switch",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "typeof",
+            "undefined",
+            "var",
+            "void",
+            "while",
+            "yield",
+        ],
+        types: &[],
+        capitalized_types: true,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        char_literals: false,
+        multiline_strings: false,
+    },
+    Language {
+        name: "TypeScript",
+        extensions: &["ts", "tsx"],
+        keywords: &[
+            "as",
+            "async",
+            "await",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "enum",
+            "export",
+            "extends",
+            "false",
+            "finally",
+            "for",
+            "function",
+            "if",
+            "implements",
+            "import",
+            "in",
+            "instanceof",
+            "interface",
+            "let",
+            "new",
+            "null",
+            "of",
+            "private",
+            "protected",
+            "public",
+            "readonly",
+            "return",
+            "static",
+            "super",
+            "switch",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "type",
+            "typeof",
+            "undefined",
+            "var",
+            "void",
+            "while",
+            "yield",
+        ],
+        types: &[
+            "any", "boolean", "never", "number", "object", "string", "symbol", "unknown",
+        ],
+        capitalized_types: true,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        char_literals: false,
+        multiline_strings: false,
+    },
+    Language {
+        name: "Python",
+        extensions: &["py"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "False", "finally", "for", "from", "global", "if", "import",
+            "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return",
+            "True", "try", "while", "with", "yield",
+        ],
+        types: &[
+            "bool", "bytes", "dict", "float", "int", "list", "object", "set", "str", "tuple",
+        ],
+        capitalized_types: true,
+        line_comment: Some("#"),
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+        multiline_strings: false,
+    },
+    Language {
+        name: "Shell",
+        extensions: &["sh", "bash", "zsh"],
+        keywords: &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+            "in", "local", "return", "then", "until", "while",
+        ],
+        types: &[],
+        capitalized_types: false,
+        line_comment: Some("#"),
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+        multiline_strings: true,
+    },
+    Language {
+        name: "TOML",
+        extensions: &["toml"],
+        keywords: &["true", "false"],
+        types: &[],
+        capitalized_types: false,
+        line_comment: Some("#"),
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+        multiline_strings: false,
+    },
+    Language {
+        name: "JSON",
+        extensions: &["json"],
+        keywords: &["true", "false", "null"],
+        types: &[],
+        capitalized_types: false,
+        line_comment: None,
+        block_comment: None,
+        quotes: &['"'],
+        char_literals: false,
+        multiline_strings: false,
+    },
+];
+
+impl Language {
+    /// Works out the language of a file from its extension.
+    pub fn detect(path: &Path) -> Option<&'static Language> {
+        let extension = path.extension()?.to_str()?;
+        LANGUAGES
+            .iter()
+            .find(|language| language.extensions.contains(&extension))
+    }
+
+    /// The language called `name`, or that files ending in `.name` are in.
+    pub fn named(name: &str) -> Option<&'static Language> {
+        LANGUAGES.iter().find(|language| {
+            language.name.eq_ignore_ascii_case(name)
+                || language
+                    .extensions
+                    .iter()
+                    .any(|e| e.eq_ignore_ascii_case(name))
+        })
+    }
+
+    /// The tokens in `line`, given the state the line starts in, and the state it ends in.
+    pub fn highlight_line(
+        &self,
+        mut state: State,
+        line: &[char],
+    ) -> (Vec<(Range<usize>, Token)>, State) {
+        let mut tokens = Vec::new();
+        let mut idx = 0;
+
+        while idx < line.len() {
+            let start = idx;
+            match state {
+                State::BlockComment => {
+                    let (_, end) = self.block_comment.unwrap();
+                    match find(line, idx, end) {
+                        Some(end_idx) => {
+                            idx = end_idx + end.chars().count();
+                            state = State::Normal;
+                        }
+                        None => idx = line.len(),
+                    }
+                    tokens.push((start..idx, Token::Comment));
+                }
+                State::String(quote) => {
+                    let mut escaped = false;
+                    while idx < line.len() {
+                        let c = line[idx];
+                        idx += 1;
+                        if c == quote && !escaped {
+                            state = State::Normal;
+                            break;
+                        }
+                        escaped = c == '\\' && !escaped;
+                    }
+                    if idx == line.len() && state != State::Normal && !self.multiline_strings {
+                        state = State::Normal;
+                    }
+                    tokens.push((start..idx, Token::String));
+                }
+                State::Normal => {
+                    let c = line[idx];
+                    let previous = idx.checked_sub(1).map(|idx| line[idx]);
+                    let after_word = previous.is_some_and(is_word_char);
+
+                    if self
+                        .line_comment
+                        .is_some_and(|comment| starts_with(line, idx, comment))
+                    {
+                        tokens.push((idx..line.len(), Token::Comment));
+                        idx = line.len();
+                    } else if let Some((open, _)) = self
+                        .block_comment
+                        .filter(|(open, _)| starts_with(line, idx, open))
+                    {
+                        idx += open.chars().count();
+                        state = State::BlockComment;
+                        // the rest of the comment is highlighted on the next time round
+                        tokens.push((start..idx, Token::Comment));
+                    } else if self.quotes.contains(&c) {
+                        idx += 1;
+                        state = State::String(c);
+                        tokens.push((start..idx, Token::String));
+                    } else if c == '\'' && self.char_literals {
+                        match char_literal_len(&line[idx..]) {
+                            Some(len) => {
+                                idx += len;
+                                tokens.push((start..idx, Token::String));
+                            }
+                            // a Rust lifetime or label
+                            None => idx += 1,
+                        }
+                    } else if c.is_ascii_digit() && !after_word {
+                        while idx < line.len() && (is_word_char(line[idx]) || line[idx] == '.') {
+                            idx += 1;
+                        }
+                        tokens.push((start..idx, Token::Number));
+                    } else if is_word_char(c) || c == '#' {
+                        idx += 1;
+                        while idx < line.len() && is_word_char(line[idx]) {
+                            idx += 1;
+                        }
+                        let word: String = line[start..idx].iter().collect();
+                        if self.keywords.contains(&word.as_str()) {
+                            tokens.push((start..idx, Token::Keyword));
+                        } else if self.types.contains(&word.as_str())
+                            || self.capitalized_types && c.is_uppercase()
+                        {
+                            tokens.push((start..idx, Token::Type));
+                        }
+                    } else {
+                        idx += 1;
+                    }
+                }
+            }
+        }
+
+        // merge the pieces of strings and comments that were split up by state changes
+        tokens.dedup_by(|next, previous| {
+            let merge = previous.1 == next.1 && previous.0.end == next.0.start;
+            if merge {
+                previous.0.end = next.0.end;
+            }
+            merge
+        });
+
+        (tokens, state)
+    }
+}
+
+/// Keeps track of the state each line starts in, so that only what's changed is redone.
+#[derive(Debug)]
+pub struct Highlighter {
+    language: Option<&'static Language>,
+    /// The state at the start of each row, some of which might be out of date.
+    states: Vec<State>,
+    /// The first row whose state might be out of date; every row before it is correct.
+    stale_from: usize,
+    /// The rows before this were edited, so their states can't be trusted to be the same.
+    edited_until: usize,
+}
+
+impl Highlighter {
+    pub fn new(language: Option<&'static Language>, rows: usize) -> Self {
+        Self {
+            language,
+            states: vec![State::Normal; rows],
+            stale_from: 1,
+            // nothing's been worked out yet, so none of it can be trusted
+            edited_until: rows,
+        }
+    }
+
+    pub fn language(&self) -> Option<&'static Language> {
+        self.language
+    }
+
+    /// Notes that the rows in `old` have been replaced with `new_len` rows.
+    pub fn edited(&mut self, old: Range<usize>, new_len: usize) {
+        if self.language.is_none() {
+            return;
+        }
+
+        let end = old.end.min(self.states.len());
+        let start = old.start.min(end);
+        // the first edited row still starts in the same state, but the rest mightn't
+        self.states.splice(
+            (start + 1).min(end)..end,
+            std::iter::repeat_n(State::Normal, new_len.saturating_sub(1)),
+        );
+
+        let shift = |row: usize| {
+            if row >= old.end {
+                row + new_len - old.len()
+            } else {
+                row
+            }
+        };
+        self.edited_until = shift(self.edited_until).max(start + new_len);
+        self.stale_from = self.stale_from.min(start + 1);
+    }
+
+    /// Brings the states of every row up to `last_row` up to date.
+    pub fn update(&mut self, last_row: usize, mut line: impl FnMut(usize) -> Vec<char>) {
+        let language = match self.language {
+            Some(language) => language,
+            None => return,
+        };
+
+        while self.stale_from <= last_row && self.stale_from < self.states.len() {
+            let row = self.stale_from;
+            let (_, state) = language.highlight_line(self.states[row - 1], &line(row - 1));
+
+            if row >= self.edited_until && state == self.states[row] {
+                // nothing after this could have changed
+                self.stale_from = self.states.len();
+                break;
+            }
+
+            self.states[row] = state;
+            self.stale_from += 1;
+        }
+
+        if self.stale_from >= self.states.len() {
+            self.edited_until = 0;
+        }
+    }
+
+    /// The tokens in `row`, which must be up to date.
+    pub fn line(&self, row: usize, line: &[char]) -> Vec<(Range<usize>, Token)> {
+        match self.language {
+            Some(language) => {
+                let state = self.states.get(row).copied().unwrap_or_default();
+                language.highlight_line(state, line).0
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn starts_with(line: &[char], idx: usize, pattern: &str) -> bool {
+    let mut chars = line[idx..].iter();
+    pattern.chars().all(|c| chars.next() == Some(&c))
+}
+
+fn find(line: &[char], from: usize, pattern: &str) -> Option<usize> {
+    (from..line.len()).find(|idx| starts_with(line, *idx, pattern))
+}
+
+/// How long the char literal at the start of `chars` is, like `'a'` or `'\n'`.
+fn char_literal_len(chars: &[char]) -> Option<usize> {
+    match chars {
+        ['\'', '\\', _, '\'', ..] => Some(4),
+        ['\'', '\\', rest @ ..] => rest.iter().position(|c| *c == '\'').map(|idx| idx + 3),
+        ['\'', _, '\'', ..] => Some(3),
+        _ => None,
+    }
+}
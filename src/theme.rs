@@ -0,0 +1,160 @@
+use crate::lsp::Severity;
+use crate::syntax::Token;
+use crossterm::style::Color;
+use serde::Deserialize;
+
+/// The colors everything is drawn with.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    pub status_bar: Color,
+    pub status_bar_background: Color,
+    pub message: Color,
+    pub error: Color,
+    /// The command palette and key hints.
+    pub popup: Color,
+    pub popup_dim: Color,
+    pub popup_background: Color,
+    pub divider: Color,
+    /// Fold markers, fold summaries and the like next to and around the text.
+    pub gutter: Color,
+    /// The line between line numbers and the text, if there is one.
+    pub gutter_separator: Color,
+    pub selection: Color,
+    /// The background of other occurrences of the word under the cursor.
+    pub occurrence: Color,
+    pub diff_added: Color,
+    pub diff_modified: Color,
+    pub diff_removed: Color,
+    pub marker: Color,
+    pub marker_background: Color,
+    pub trailing_whitespace: Color,
+    /// The background of the parts of lines past the line length limit.
+    pub over_limit: Color,
+    /// Control characters, which are shown in caret notation like `^[`.
+    pub control_char: Color,
+    /// The scrollbar, and the part of it showing what's in view.
+    pub scrollbar: Color,
+    pub scrollbar_thumb: Color,
+    /// The underlines and status bar messages for problems a language server finds.
+    pub diagnostic_error: Color,
+    pub diagnostic_warning: Color,
+    pub diagnostic_info: Color,
+    /// The underline under misspelled words.
+    pub misspelled: Color,
+    pub keyword: Color,
+    pub type_name: Color,
+    pub string: Color,
+    pub comment: Color,
+    pub number: Color,
+    /// The colors brackets cycle through as they're nested more deeply.
+    pub brackets: Vec<Color>,
+}
+
+impl Theme {
+    pub fn diagnostic(&self, severity: Severity) -> Color {
+        match severity {
+            Severity::Error => self.diagnostic_error,
+            Severity::Warning => self.diagnostic_warning,
+            Severity::Information | Severity::Hint => self.diagnostic_info,
+        }
+    }
+
+    pub fn token(&self, token: Token) -> Color {
+        match token {
+            Token::Keyword => self.keyword,
+            Token::Type => self.type_name,
+            Token::String => self.string,
+            Token::Comment => self.comment,
+            Token::Number => self.number,
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            _ => None,
+        }
+    }
+
+    pub fn dark() -> Self {
+        Self {
+            status_bar: Color::DarkGrey,
+            status_bar_background: Color::Black,
+            message: Color::White,
+            error: Color::Red,
+            popup: Color::White,
+            popup_dim: Color::Grey,
+            popup_background: Color::Black,
+            divider: Color::DarkGrey,
+            gutter: Color::DarkGrey,
+            gutter_separator: Color::DarkGrey,
+            selection: Color::DarkBlue,
+            occurrence: Color::DarkGrey,
+            diff_added: Color::Green,
+            diff_modified: Color::Blue,
+            diff_removed: Color::Red,
+            marker: Color::Black,
+            marker_background: Color::Yellow,
+            trailing_whitespace: Color::Red,
+            over_limit: Color::DarkRed,
+            control_char: Color::Magenta,
+            scrollbar: Color::DarkGrey,
+            scrollbar_thumb: Color::Grey,
+            diagnostic_error: Color::Red,
+            diagnostic_warning: Color::Yellow,
+            diagnostic_info: Color::Blue,
+            misspelled: Color::Cyan,
+            keyword: Color::Magenta,
+            type_name: Color::Cyan,
+            string: Color::Green,
+            comment: Color::DarkGrey,
+            number: Color::Yellow,
+            brackets: vec![Color::Yellow, Color::Magenta, Color::Cyan],
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            status_bar: Color::Black,
+            status_bar_background: Color::Grey,
+            message: Color::Black,
+            error: Color::DarkRed,
+            popup: Color::Black,
+            popup_dim: Color::DarkGrey,
+            popup_background: Color::Grey,
+            divider: Color::Grey,
+            gutter: Color::Grey,
+            gutter_separator: Color::Grey,
+            selection: Color::Cyan,
+            occurrence: Color::Grey,
+            diff_added: Color::DarkGreen,
+            diff_modified: Color::DarkBlue,
+            diff_removed: Color::DarkRed,
+            marker: Color::Black,
+            marker_background: Color::Yellow,
+            trailing_whitespace: Color::Red,
+            over_limit: Color::Yellow,
+            control_char: Color::DarkMagenta,
+            scrollbar: Color::Grey,
+            scrollbar_thumb: Color::DarkGrey,
+            diagnostic_error: Color::DarkRed,
+            diagnostic_warning: Color::DarkYellow,
+            diagnostic_info: Color::DarkBlue,
+            misspelled: Color::DarkCyan,
+            keyword: Color::DarkMagenta,
+            type_name: Color::DarkCyan,
+            string: Color::DarkGreen,
+            comment: Color::DarkGrey,
+            number: Color::DarkYellow,
+            brackets: vec![Color::DarkYellow, Color::DarkMagenta, Color::DarkCyan],
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
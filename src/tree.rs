@@ -0,0 +1,239 @@
+//! A syntax tree of the buffer built with tree-sitter, for more accurate highlighting than going
+//! line by line and for commands that work on the structure of the code.
+//!
+//! Edits are passed on to the tree as they're made, so that reparsing only has to look again at
+//! the parts of it that changed. Only Rust is supported so far.
+
+use crate::syntax::Token;
+use ropey::Rope;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use tree_sitter::{InputEdit, Node, Parser, Point, Query, QueryCursor, StreamingIterator, Tree};
+
+pub struct SyntaxTree {
+    parser: Parser,
+    tree: Tree,
+    query: Query,
+    /// Whether the tree has been edited since it was last parsed.
+    stale: bool,
+}
+
+impl fmt::Debug for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyntaxTree")
+            .field("tree", &self.tree)
+            .field("stale", &self.stale)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SyntaxTree {
+    /// Parses `text`, if there's a grammar for the language of `path`.
+    pub fn new(path: &Path, text: &Rope) -> Option<Self> {
+        if path.extension()? != "rs" {
+            return None;
+        }
+
+        let language = tree_sitter_rust::LANGUAGE.into();
+        let mut parser = Parser::new();
+        parser.set_language(&language).ok()?;
+        let query = Query::new(&language, tree_sitter_rust::HIGHLIGHTS_QUERY).ok()?;
+        let tree = parse(&mut parser, text, None)?;
+
+        Some(Self {
+            parser,
+            tree,
+            query,
+            stale: false,
+        })
+    }
+
+    /// Parses `text` from scratch, such as when the whole buffer has been replaced.
+    pub fn reset(&mut self, text: &Rope) {
+        if let Some(tree) = parse(&mut self.parser, text, None) {
+            self.tree = tree;
+        }
+        self.stale = false;
+    }
+
+    /// Tells the tree that `range` of `text` is about to be replaced with `new`.
+    pub fn edit(&mut self, text: &Rope, range: Range<usize>, new: &str) {
+        let start_byte = text.char_to_byte(range.start);
+        let old_end_byte = text.char_to_byte(range.end);
+        let start_position = point(text, start_byte);
+
+        let new_end_position = match new.rfind('\n') {
+            Some(idx) => Point {
+                row: start_position.row + new.matches('\n').count(),
+                column: new.len() - idx - 1,
+            },
+            None => Point {
+                row: start_position.row,
+                column: start_position.column + new.len(),
+            },
+        };
+
+        self.tree.edit(&InputEdit {
+            start_byte,
+            old_end_byte,
+            new_end_byte: start_byte + new.len(),
+            start_position,
+            old_end_position: point(text, old_end_byte),
+            new_end_position,
+        });
+        self.stale = true;
+    }
+
+    /// Reparses the parts of the tree that have been edited.
+    pub fn update(&mut self, text: &Rope) {
+        if !self.stale {
+            return;
+        }
+
+        if let Some(tree) = parse(&mut self.parser, text, Some(&self.tree)) {
+            self.tree = tree;
+        }
+        self.stale = false;
+    }
+
+    /// The tokens in `row`, as ranges of chars in the line.
+    pub fn line(&self, text: &Rope, row: usize) -> Vec<(Range<usize>, Token)> {
+        let line_start = text.line_to_byte(row);
+        let line_end = text.line_to_byte(row + 1);
+        let line_start_char = text.line_to_char(row);
+
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(line_start..line_end);
+        let source = |node: Node| {
+            text.byte_slice(node.byte_range())
+                .chunks()
+                .map(str::as_bytes)
+        };
+        let mut captures = cursor.captures(&self.query, self.tree.root_node(), source);
+
+        let mut tokens: Vec<(Range<usize>, Token)> = Vec::new();
+        let mut last_node = None;
+        while let Some((capture_match, idx)) = captures.next() {
+            let capture = capture_match.captures()[*idx];
+            // when several patterns match the same node, the first one wins
+            if last_node == Some(capture.node.id()) {
+                continue;
+            }
+            last_node = Some(capture.node.id());
+
+            let name = self.query.capture_names()[capture.index as usize];
+            let Some(token) = token(name) else { continue };
+            let bytes = capture.node.byte_range();
+            let start = text.byte_to_char(bytes.start.max(line_start)) - line_start_char;
+            let end = text.byte_to_char(bytes.end.min(line_end)) - line_start_char;
+            tokens.push((start..end, token));
+        }
+
+        tokens
+    }
+
+    /// The smallest node that's bigger than `range`.
+    pub fn enclosing_node(&self, text: &Rope, range: Range<usize>) -> Option<Range<usize>> {
+        let bytes = to_bytes(text, range);
+        let mut node = self.node_at(bytes.clone())?;
+        while node.byte_range() == bytes {
+            node = node.parent()?;
+        }
+
+        Some(to_chars(text, node.byte_range()))
+    }
+
+    /// The node after the one at the start of `range`, among those with the same parent.
+    pub fn next_sibling(&self, text: &Rope, range: Range<usize>) -> Option<Range<usize>> {
+        self.sibling(text, range, true)
+    }
+
+    pub fn previous_sibling(&self, text: &Rope, range: Range<usize>) -> Option<Range<usize>> {
+        self.sibling(text, range, false)
+    }
+
+    /// The function `range` is inside of.
+    pub fn function(&self, text: &Rope, range: Range<usize>) -> Option<Range<usize>> {
+        let mut node = self.node_at(to_bytes(text, range))?;
+        while node.kind() != "function_item" {
+            node = node.parent()?;
+        }
+
+        Some(to_chars(text, node.byte_range()))
+    }
+
+    fn sibling(&self, text: &Rope, range: Range<usize>, next: bool) -> Option<Range<usize>> {
+        let mut node = self.node_at(to_bytes(text, range))?;
+
+        // at the start of a statement, the smallest node is the first part of it,
+        // but it's the statement that has the statements around it as siblings
+        while let Some(parent) = node.parent() {
+            if parent.start_byte() != node.start_byte() || parent.parent().is_none() {
+                break;
+            }
+            node = parent;
+        }
+
+        loop {
+            let sibling = if next {
+                node.next_named_sibling()
+            } else {
+                node.prev_named_sibling()
+            };
+            if let Some(sibling) = sibling {
+                return Some(to_chars(text, sibling.byte_range()));
+            }
+            node = node.parent()?;
+        }
+    }
+
+    fn node_at(&self, bytes: Range<usize>) -> Option<Node<'_>> {
+        self.tree
+            .root_node()
+            .named_descendant_for_byte_range(bytes.start, bytes.end)
+    }
+}
+
+fn parse(parser: &mut Parser, text: &Rope, old_tree: Option<&Tree>) -> Option<Tree> {
+    parser.parse_with_options(
+        &mut |byte, _| {
+            if byte >= text.len_bytes() {
+                return &[][..];
+            }
+            let (chunk, chunk_start, _, _) = text.chunk_at_byte(byte);
+            &chunk.as_bytes()[byte - chunk_start..]
+        },
+        old_tree,
+        None,
+    )
+}
+
+fn point(text: &Rope, byte: usize) -> Point {
+    let row = text.byte_to_line(byte);
+    Point {
+        row,
+        column: byte - text.line_to_byte(row),
+    }
+}
+
+fn to_bytes(text: &Rope, range: Range<usize>) -> Range<usize> {
+    text.char_to_byte(range.start)..text.char_to_byte(range.end)
+}
+
+fn to_chars(text: &Rope, range: Range<usize>) -> Range<usize> {
+    text.byte_to_char(range.start)..text.byte_to_char(range.end)
+}
+
+/// The token a capture from the highlights query is shown as, if any.
+fn token(capture: &str) -> Option<Token> {
+    let kind = capture.split('.').next().unwrap_or(capture);
+    match kind {
+        "keyword" => Some(Token::Keyword),
+        "type" | "constructor" => Some(Token::Type),
+        "string" | "escape" => Some(Token::String),
+        "comment" => Some(Token::Comment),
+        _ if capture == "constant.builtin" => Some(Token::Number),
+        _ => None,
+    }
+}